@@ -0,0 +1,338 @@
+//! Data structures handed from laranja-loader to laranja-kernel.
+//!
+//! Everything here is `#[repr(C)]` and shared by both crates, so the loader
+//! and the kernel always agree on the layout. The kernel must check the
+//! header with [`BootInfo::is_compatible`] before touching anything else.
+//!
+//! Addresses are physical unless noted otherwise. The loader maps the whole
+//! physical memory at [`BootInfo::physical_memory_offset`], so the kernel
+//! reaches `addr` at `physical_memory_offset + addr`.
+#![no_std]
+
+use core::marker::PhantomData;
+
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"LARANJA\0");
+pub const BOOT_INFO_VERSION: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32,
+}
+
+impl Header {
+    pub const fn new() -> Self {
+        Header {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: core::mem::size_of::<BootInfo>() as u32,
+        }
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct BootInfo {
+    pub header: Header,
+    pub physical_memory_offset: u64,
+    /// Empty if the firmware has no graphics output; see
+    /// [`BootInfo::frame_buffer`].
+    pub frame_buffer: FrameBufferInfo,
+    pub memory_map: Slice<MemoryDescriptor>,
+    /// Physical address of the ACPI RSDP, 0 if not found.
+    pub rsdp: u64,
+    pub command_line: Str,
+    pub files: Slice<LoadedFile>,
+    /// Virtual address range of the stack `kernel_main` is called on. The page
+    /// below `bottom` is left unmapped as a guard.
+    pub kernel_stack: StackInfo,
+}
+
+impl BootInfo {
+    /// Returns true if this structure was built by a loader using the same
+    /// layout as this crate.
+    pub fn is_compatible(&self) -> bool {
+        self.header.magic == BOOT_INFO_MAGIC
+            && self.header.version == BOOT_INFO_VERSION
+            && self.header.size as usize == core::mem::size_of::<BootInfo>()
+    }
+
+    /// Returns `None` when booted headless.
+    pub fn frame_buffer(&self) -> Option<&FrameBufferInfo> {
+        if self.frame_buffer.is_present() {
+            Some(&self.frame_buffer)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct StackInfo {
+    pub bottom: u64,
+    pub top: u64,
+}
+
+impl StackInfo {
+    pub fn size(&self) -> u64 {
+        self.top - self.bottom
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.bottom <= addr && addr < self.top
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PixelFormat {
+    Rgb = 0,
+    Bgr,
+    Bitmask,
+    BltOnly,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub struct PixelBitmask {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub reserved: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct ModeInfo {
+    pub hor_res: u32,
+    pub ver_res: u32,
+    pub stride: u32,
+    pub format: PixelFormat,
+    pub mask: PixelBitmask,
+}
+
+impl ModeInfo {
+    pub fn resolution(&self) -> (usize, usize) {
+        (self.hor_res as usize, self.ver_res as usize)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct FrameBufferInfo {
+    pub base: u64,
+    pub size: u64,
+    pub mode: ModeInfo,
+}
+
+impl FrameBufferInfo {
+    /// Stands for no framebuffer.
+    pub const fn none() -> Self {
+        FrameBufferInfo {
+            base: 0,
+            size: 0,
+            mode: ModeInfo {
+                hor_res: 0,
+                ver_res: 0,
+                stride: 0,
+                format: PixelFormat::BltOnly,
+                mask: PixelBitmask {
+                    red: 0,
+                    green: 0,
+                    blue: 0,
+                    reserved: 0,
+                },
+            },
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.size != 0
+    }
+}
+
+/// Same values as UEFI `EFI_MEMORY_TYPE`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct MemoryType(pub u32);
+
+impl MemoryType {
+    pub const RESERVED: MemoryType = MemoryType(0);
+    pub const LOADER_CODE: MemoryType = MemoryType(1);
+    pub const LOADER_DATA: MemoryType = MemoryType(2);
+    pub const BOOT_SERVICES_CODE: MemoryType = MemoryType(3);
+    pub const BOOT_SERVICES_DATA: MemoryType = MemoryType(4);
+    pub const RUNTIME_SERVICES_CODE: MemoryType = MemoryType(5);
+    pub const RUNTIME_SERVICES_DATA: MemoryType = MemoryType(6);
+    pub const CONVENTIONAL: MemoryType = MemoryType(7);
+    pub const UNUSABLE: MemoryType = MemoryType(8);
+    pub const ACPI_RECLAIM: MemoryType = MemoryType(9);
+    pub const ACPI_NON_VOLATILE: MemoryType = MemoryType(10);
+    pub const MMIO: MemoryType = MemoryType(11);
+    pub const MMIO_PORT_SPACE: MemoryType = MemoryType(12);
+    pub const PAL_CODE: MemoryType = MemoryType(13);
+    pub const PERSISTENT_MEMORY: MemoryType = MemoryType(14);
+
+    pub fn as_str(&self) -> &'static str {
+        const NAMES: [&str; 15] = [
+            "Reserved",
+            "LoaderCode",
+            "LoaderData",
+            "BootServicesCode",
+            "BootServicesData",
+            "RuntimeServicesCode",
+            "RuntimeServicesData",
+            "Conventional",
+            "Unusable",
+            "AcpiReclaim",
+            "AcpiNonVolatile",
+            "Mmio",
+            "MmioPortSpace",
+            "PalCode",
+            "PersistentMemory",
+        ];
+        NAMES.get(self.0 as usize).unwrap_or(&"Unknown")
+    }
+}
+
+impl core::fmt::Debug for MemoryType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}({})", self.as_str(), self.0)
+    }
+}
+
+pub const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct MemoryDescriptor {
+    pub ty: MemoryType,
+    pub phys_start: u64,
+    pub page_count: u64,
+    /// Same bits as UEFI `EFI_MEMORY_ATTRIBUTE`.
+    pub attribute: u64,
+}
+
+impl MemoryDescriptor {
+    pub fn phys_end(&self) -> u64 {
+        self.phys_start + self.page_count * PAGE_SIZE
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FileKind {
+    Kernel = 0,
+    Initrd,
+    Other,
+}
+
+pub const FILE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct LoadedFile {
+    pub kind: FileKind,
+    /// NUL padded file name on the boot volume.
+    pub name: [u8; FILE_NAME_LEN],
+    pub base: u64,
+    pub size: u64,
+}
+
+impl LoadedFile {
+    /// Copy `name` into a fixed size buffer, truncating it if it is too long.
+    pub fn new(kind: FileKind, name: &str, base: u64, size: u64) -> Self {
+        let mut buf = [0; FILE_NAME_LEN];
+        let len = core::cmp::min(name.len(), FILE_NAME_LEN);
+        buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+        LoadedFile {
+            kind,
+            name: buf,
+            base,
+            size,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(FILE_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// An array living in memory owned by the loader.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Slice<T> {
+    pub addr: u64,
+    pub len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Slice<T> {
+    pub const fn new(addr: u64, len: u64) -> Self {
+        Slice {
+            addr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn empty() -> Self {
+        Self::new(0, 0)
+    }
+
+    /// # Safety
+    /// `addr` must point to `len` valid elements which live for `'a`, and
+    /// physical memory must be mapped at `offset`.
+    pub unsafe fn as_slice<'a>(&self, offset: u64) -> &'a [T] {
+        if self.len == 0 {
+            &[]
+        } else {
+            core::slice::from_raw_parts((offset + self.addr) as *const T, self.len as usize)
+        }
+    }
+}
+
+/// An UTF-8 string living in memory owned by the loader.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Str {
+    pub addr: u64,
+    pub len: u64,
+}
+
+impl Str {
+    pub const fn new(addr: u64, len: u64) -> Self {
+        Str { addr, len }
+    }
+
+    pub const fn empty() -> Self {
+        Self::new(0, 0)
+    }
+
+    /// # Safety
+    /// `addr` must point to `len` bytes of UTF-8 which live for `'a`, and
+    /// physical memory must be mapped at `offset`.
+    pub unsafe fn as_str<'a>(&self, offset: u64) -> &'a str {
+        if self.len == 0 {
+            ""
+        } else {
+            let bytes =
+                core::slice::from_raw_parts((offset + self.addr) as *const u8, self.len as usize);
+            core::str::from_utf8(bytes).unwrap_or("")
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Name of the optional configuration file in the root of the boot volume.
+pub const CONFIG_FILE: &str = "laranja.cfg";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    MissingSeparator,
+    UnknownKey(String),
+    InvalidValue(String, String),
+}
+
+/// A line of the configuration file which was ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} line {}: ", CONFIG_FILE, self.line)?;
+        match &self.kind {
+            ErrorKind::MissingSeparator => write!(f, "expected key=value"),
+            ErrorKind::UnknownKey(key) => write!(f, "unknown key {}", key),
+            ErrorKind::InvalidValue(key, value) => {
+                write!(f, "invalid value {} for {}", value, key)
+            }
+        }
+    }
+}
+
+/// Loader settings read from `laranja.cfg`.
+///
+/// The file consists of `key=value` lines. Blank lines and lines starting with
+/// `#` are ignored. The keys are
+///
+/// * `kernel`: path of the kernel on the boot volume
+/// * `initrd`: path of the initial ramdisk, which is loaded if it exists
+///   (empty to disable)
+/// * `resolution`: preferred GOP mode such as `1920x1080`; without it the
+///   modes of GPD Pocket and Lemur Pro are preferred, except on EDK II
+/// * `memmap`: whether to write the memory map to `memmap` (`true`/`false`)
+/// * `timeout`: seconds the boot menu waits before booting `kernel`; 0 skips
+///   the menu
+/// * `log`: loader log level (`off`, `error`, `warn`, `info`, `debug`, `trace`)
+/// * `cmdline`: command line passed to the kernel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub kernel: String,
+    pub initrd: String,
+    pub resolution: Option<(usize, usize)>,
+    pub memmap: bool,
+    pub timeout: usize,
+    pub log_level: log::LevelFilter,
+    pub cmdline: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            kernel: "laranja-kernel".to_string(),
+            initrd: "initrd.tar".to_string(),
+            resolution: None,
+            memmap: true,
+            timeout: 3,
+            log_level: log::LevelFilter::Info,
+            cmdline: String::new(),
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_resolution(value: &str) -> Option<(usize, usize)> {
+    let (hor, ver) = value.split_once('x')?;
+    Some((hor.trim().parse().ok()?, ver.trim().parse().ok()?))
+}
+
+impl Config {
+    /// Parses `text`, starting from the default settings. Lines which can't be
+    /// understood are skipped and returned as errors, so that a typo doesn't
+    /// prevent booting.
+    pub fn parse(text: &str) -> (Self, Vec<Error>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let kind = match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim()),
+                None => Err(ErrorKind::MissingSeparator),
+            };
+            if let Err(kind) = kind {
+                errors.push(Error { line: i + 1, kind });
+            }
+        }
+        (config, errors)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ErrorKind> {
+        let invalid = || ErrorKind::InvalidValue(key.to_string(), value.to_string());
+        match key {
+            "kernel" if !value.is_empty() => self.kernel = value.to_string(),
+            "kernel" => return Err(invalid()),
+            "initrd" => self.initrd = value.to_string(),
+            "resolution" => self.resolution = Some(parse_resolution(value).ok_or_else(invalid)?),
+            "memmap" => self.memmap = parse_bool(value).ok_or_else(invalid)?,
+            "timeout" => self.timeout = value.parse().map_err(|_| invalid())?,
+            "log" => self.log_level = value.parse().map_err(|_| invalid())?,
+            "cmdline" => self.cmdline = value.to_string(),
+            _ => return Err(ErrorKind::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use core::fmt;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    TooSmall,
+    BadMagic,
+    NotElf64,
+    NotLittleEndian,
+    UnsupportedMachine(u16),
+    NotExecutable(u16),
+    BadProgramHeader,
+    SegmentOutOfFile(u64),
+    BadSegmentSize(u64),
+    OverlappingSegments(u64, u64),
+    NoLoadableSegment,
+    EntryNotExecutable(u64),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooSmall => write!(f, "file is too small"),
+            Error::BadMagic => write!(f, "not an ELF file"),
+            Error::NotElf64 => write!(f, "not a 64 bit ELF file"),
+            Error::NotLittleEndian => write!(f, "not a little endian ELF file"),
+            Error::UnsupportedMachine(m) => write!(f, "unsupported machine type {}", m),
+            Error::NotExecutable(t) => write!(f, "not an executable (type {})", t),
+            Error::BadProgramHeader => write!(f, "broken program header table"),
+            Error::SegmentOutOfFile(v) => write!(f, "segment at {:x} exceeds the file", v),
+            Error::BadSegmentSize(v) => write!(f, "segment at {:x} has memsz < filesz", v),
+            Error::OverlappingSegments(a, b) => {
+                write!(f, "segments at {:x} and {:x} overlap", a, b)
+            }
+            Error::NoLoadableSegment => write!(f, "no loadable segment"),
+            Error::EntryNotExecutable(e) => {
+                write!(f, "entry point {:x} is not in an executable segment", e)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct Header {
+    ident: [u8; 16],
+    ty: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct ProgramHeader {
+    ty: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SegmentFlags(u32);
+
+impl SegmentFlags {
+    pub fn readable(&self) -> bool {
+        self.0 & PF_R != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.0 & PF_W != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.0 & PF_X != 0
+    }
+}
+
+impl fmt::Display for SegmentFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.readable() { 'R' } else { '-' },
+            if self.writable() { 'W' } else { '-' },
+            if self.executable() { 'X' } else { '-' }
+        )
+    }
+}
+
+/// A PT_LOAD segment.
+#[derive(Copy, Clone, Debug)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub flags: SegmentFlags,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.vaddr + self.memsz
+    }
+}
+
+/// A validated x86_64 ELF executable.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < core::mem::size_of::<Header>() {
+            return Err(Error::TooSmall);
+        }
+        let header = unsafe { (data.as_ptr() as *const Header).read_unaligned() };
+        if header.ident[..4] != ELF_MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if header.ident[4] != ELF_CLASS_64 {
+            return Err(Error::NotElf64);
+        }
+        if header.ident[5] != ELF_DATA_LITTLE_ENDIAN {
+            return Err(Error::NotLittleEndian);
+        }
+        if header.machine != EM_X86_64 {
+            return Err(Error::UnsupportedMachine(header.machine));
+        }
+        if header.ty != ET_EXEC {
+            return Err(Error::NotExecutable(header.ty));
+        }
+        let table_size = header.phentsize as u64 * header.phnum as u64;
+        if (header.phentsize as usize) < core::mem::size_of::<ProgramHeader>()
+            || header
+                .phoff
+                .checked_add(table_size)
+                .map_or(true, |end| end > data.len() as u64)
+        {
+            return Err(Error::BadProgramHeader);
+        }
+
+        let elf = Elf { data, header };
+        elf.validate_segments()?;
+        Ok(elf)
+    }
+
+    fn validate_segments(&self) -> Result<()> {
+        let mut found = false;
+        for (i, s) in self.segments().enumerate() {
+            found = true;
+            if s.memsz < s.filesz {
+                return Err(Error::BadSegmentSize(s.vaddr));
+            }
+            if s.offset
+                .checked_add(s.filesz)
+                .map_or(true, |end| end > self.data.len() as u64)
+                || s.vaddr.checked_add(s.memsz).is_none()
+            {
+                return Err(Error::SegmentOutOfFile(s.vaddr));
+            }
+            if let Some(other) = self
+                .segments()
+                .take(i)
+                .find(|o| o.vaddr < s.end() && s.vaddr < o.end())
+            {
+                return Err(Error::OverlappingSegments(other.vaddr, s.vaddr));
+            }
+        }
+        if !found {
+            return Err(Error::NoLoadableSegment);
+        }
+        let entry = self.entry();
+        if !self
+            .segments()
+            .any(|s| s.flags.executable() && s.vaddr <= entry && entry < s.end())
+        {
+            return Err(Error::EntryNotExecutable(entry));
+        }
+        Ok(())
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.header.entry
+    }
+
+    fn program_header(&self, index: usize) -> ProgramHeader {
+        let offset = self.header.phoff as usize + index * self.header.phentsize as usize;
+        unsafe { (self.data[offset..].as_ptr() as *const ProgramHeader).read_unaligned() }
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.header.phnum as usize)
+            .map(move |i| self.program_header(i))
+            .filter(|ph| ph.ty == PT_LOAD)
+            .map(|ph| Segment {
+                vaddr: ph.vaddr,
+                offset: ph.offset,
+                filesz: ph.filesz,
+                memsz: ph.memsz,
+                flags: SegmentFlags(ph.flags),
+            })
+    }
+
+    /// Page aligned virtual address range covering all segments.
+    pub fn span(&self) -> (u64, u64) {
+        let first = self.segments().map(|s| s.vaddr).min().unwrap_or(0);
+        let last = self.segments().map(|s| s.end()).max().unwrap_or(0);
+        (
+            first & !(PAGE_SIZE - 1),
+            (last + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+        )
+    }
+
+    /// Copies every segment into `dest`, which will be mapped at `span().0`.
+    /// `dest` is cleared first, so the bss part of each segment
+    /// (`memsz - filesz`) is zero filled.
+    pub fn load(&self, dest: &mut [u8]) {
+        let (first, _) = self.span();
+        dest.fill(0);
+        for s in self.segments() {
+            let start = (s.vaddr - first) as usize;
+            let src = &self.data[s.offset as usize..(s.offset + s.filesz) as usize];
+            dest[start..start + src.len()].copy_from_slice(src);
+        }
+    }
+}
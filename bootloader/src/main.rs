@@ -5,35 +5,62 @@
 
 #[macro_use]
 extern crate alloc;
+use alloc::boxed::Box;
 use alloc::string::ToString;
+use boot_info::{
+    BootInfo, FileKind, FrameBufferInfo, Header, LoadedFile, ModeInfo, PixelBitmask, PixelFormat,
+    Slice, StackInfo, Str,
+};
+use config::{Config, CONFIG_FILE};
 use console::gop;
 use core::arch::asm;
 use core::fmt::Write;
-use elf_rs::*;
+use elf::Elf;
+use paging::{
+    PageTableBuilder, KERNEL_STACK_SIZE, KERNEL_STACK_TOP, NO_EXECUTE, PAGE_SIZE,
+    PHYSICAL_MEMORY_OFFSET, WRITABLE,
+};
 use proto::console;
 use uefi::{
     prelude::*,
     proto::{self, console::gop::GraphicsOutput, media::fs::SimpleFileSystem},
-    table::boot::{EventType, MemoryDescriptor, Tpl},
+    table::boot::{BootServices, EventType, MemoryDescriptor, Tpl},
 };
 use uefi::{
-    proto::media::file::{File, FileAttribute, FileInfo, FileMode, FileType::Regular},
+    proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, FileType::Regular},
     table::boot::{AllocateType, MemoryType},
+    table::cfg::{ACPI2_GUID, ACPI_GUID},
 };
 
+mod config;
+mod elf;
+mod menu;
+mod paging;
+
 static mut LOGGER: Option<uefi::logger::Logger> = None;
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-struct FrameBufferInfo {
-    pub fb: *mut u8,
-    pub size: usize,
+/// Switches to the mode with the given resolution. Returns false if there is
+/// no such mode.
+fn set_gop_mode(gop: &mut GraphicsOutput, resolution: (usize, usize)) -> bool {
+    let mode = gop
+        .modes()
+        .map(|m| m.unwrap())
+        .find(|m| m.info().resolution() == resolution);
+    match mode {
+        Some(mode) => {
+            gop.set_mode(&mode).unwrap().unwrap();
+            true
+        }
+        None => false,
+    }
 }
 
-#[allow(dead_code)]
-fn set_gop_mode(gop: &mut GraphicsOutput) {
+/// Picks a mode when laranja.cfg has no resolution and the firmware is not
+/// EDK II (QEMU): the panels of GPD Pocket and Lemur Pro, or 1024x768. Keeps
+/// the firmware's mode otherwise.
+fn set_default_gop_mode(gop: &mut GraphicsOutput) {
     let mut mode: Option<gop::Mode> = None;
-    for m in gop.modes().into_iter() {
+    for m in gop.modes() {
         let m = m.unwrap();
         let res = m.info().resolution();
 
@@ -48,6 +75,160 @@ fn set_gop_mode(gop: &mut GraphicsOutput) {
     }
 }
 
+fn to_mode_info(mi: &gop::ModeInfo) -> ModeInfo {
+    let (hor_res, ver_res) = mi.resolution();
+    let format = match mi.pixel_format() {
+        gop::PixelFormat::Rgb => PixelFormat::Rgb,
+        gop::PixelFormat::Bgr => PixelFormat::Bgr,
+        gop::PixelFormat::Bitmask => PixelFormat::Bitmask,
+        gop::PixelFormat::BltOnly => PixelFormat::BltOnly,
+    };
+    let mask = match mi.pixel_bitmask() {
+        Some(m) => PixelBitmask {
+            red: m.red,
+            green: m.green,
+            blue: m.blue,
+            reserved: m.reserved,
+        },
+        None => PixelBitmask {
+            red: 0,
+            green: 0,
+            blue: 0,
+            reserved: 0,
+        },
+    };
+    ModeInfo {
+        hor_res: hor_res as u32,
+        ver_res: ver_res as u32,
+        stride: mi.stride() as u32,
+        format,
+        mask,
+    }
+}
+
+/// End address of the physical memory, but at least 4 GiB to cover the MMIO
+/// regions under it.
+fn max_physical_address(bt: &BootServices) -> u64 {
+    let mmap_size = bt.memory_map_size() + 8 * core::mem::size_of::<MemoryDescriptor>();
+    let mut buf = vec![0; mmap_size];
+    let (_, memmap_iter) = bt.memory_map(&mut buf).unwrap().unwrap();
+    memmap_iter
+        .map(|m| m.phys_start + m.page_count * PAGE_SIZE)
+        .fold(0x1_0000_0000, core::cmp::max)
+}
+
+/// Loads the kernel into newly allocated pages and maps each of them with the
+/// permissions of the segments it contains. Returns the entry point.
+fn load_kernel(
+    bt: &BootServices,
+    data: &[u8],
+    page_table: &mut PageTableBuilder,
+) -> elf::Result<u64> {
+    let elf = Elf::parse(data)?;
+    let (first, last) = elf.span();
+    let n_of_pages = ((last - first) / PAGE_SIZE) as usize;
+    log::info!(
+        "kernel_first {:x}, last {:x}, pages {:?}",
+        first,
+        last,
+        n_of_pages
+    );
+    // the kernel is linked in the higher half; any physical pages will do
+    let kernel_phys = bt
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, n_of_pages)
+        .expect_success("failed to allocate pages for the kernel");
+    let dest =
+        unsafe { core::slice::from_raw_parts_mut(kernel_phys as *mut u8, (last - first) as usize) };
+    elf.load(dest);
+
+    // a page shared by segments gets the union of their permissions
+    let mut flags = vec![NO_EXECUTE; n_of_pages];
+    for s in elf.segments() {
+        log::info!("segment {:x} - {:x} {}", s.vaddr, s.end(), s.flags);
+        let start = ((s.vaddr - first) / PAGE_SIZE) as usize;
+        let end = ((s.end() - first + PAGE_SIZE - 1) / PAGE_SIZE) as usize;
+        for f in &mut flags[start..end] {
+            if s.flags.writable() {
+                *f |= WRITABLE;
+            }
+            if s.flags.executable() {
+                *f &= !NO_EXECUTE;
+            }
+        }
+    }
+    for (i, f) in flags.iter().enumerate() {
+        let offset = i as u64 * PAGE_SIZE;
+        page_table.map_page(first + offset, kernel_phys + offset, *f);
+    }
+    Ok(elf.entry())
+}
+
+/// Reads a whole regular file into LOADER_DATA pages, which the kernel keeps.
+/// Returns `None` if the file can't be opened or read.
+fn read_file(bt: &BootServices, root: &mut Directory, path: &str) -> Option<&'static mut [u8]> {
+    let file = root
+        .open(path, FileMode::Read, FileAttribute::READ_ONLY)
+        .ok()?
+        .unwrap();
+    let mut file = match file.into_type().ok()?.unwrap() {
+        Regular(f) => f,
+        _ => return None,
+    };
+    const BUF_SIZE: usize = 4000;
+    let buf = &mut [0u8; BUF_SIZE];
+    let info: &mut FileInfo = file.get_info(buf).ok()?.unwrap();
+    let size = info.file_size() as usize;
+    if size == 0 {
+        return Some(&mut []);
+    }
+    let pages = (size + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
+    let addr = bt
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages)
+        .ok()?
+        .unwrap();
+    let data = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+    let read = file.read(data);
+    file.close();
+    match read {
+        Ok(completion) if completion.unwrap() == size => Some(data),
+        _ => {
+            let _ = bt.free_pages(addr, pages);
+            None
+        }
+    }
+}
+
+fn read_config(bt: &BootServices, root: &mut Directory) -> Config {
+    let text = match read_file(bt, root, CONFIG_FILE) {
+        Some(data) => data,
+        None => return Config::default(),
+    };
+    let text = match core::str::from_utf8(text) {
+        Ok(text) => text,
+        Err(_) => {
+            log::warn!("{} is not UTF-8; ignored", CONFIG_FILE);
+            return Config::default();
+        }
+    };
+    let (config, errors) = Config::parse(text);
+    for e in errors {
+        log::warn!("{}", e);
+    }
+    config
+}
+
+/// Physical address of the ACPI RSDP, preferring the ACPI 2.0 one. Returns 0
+/// if the firmware provides neither.
+fn find_rsdp(st: &SystemTable<Boot>) -> u64 {
+    let find = |guid| {
+        st.config_table()
+            .iter()
+            .find(|entry| entry.guid == guid)
+            .map(|entry| entry.address as u64)
+    };
+    find(ACPI2_GUID).or_else(|| find(ACPI_GUID)).unwrap_or(0)
+}
+
 fn exit_boot_services(_: uefi::Event) {
     uefi::alloc::exit_boot_services();
 }
@@ -56,12 +237,11 @@ fn exit_boot_services(_: uefi::Event) {
 fn efi_main(handle: Handle, st: SystemTable<Boot>) -> Status {
     let bt = st.boot_services();
 
-    let gop = if let Ok(gop) = bt.locate_protocol::<GraphicsOutput>() {
+    // without GOP the kernel runs headless and logs to the serial port
+    let mut gop = bt.locate_protocol::<GraphicsOutput>().ok().map(|gop| {
         let gop = gop.expect("Warnings encountered while opening GOP");
         unsafe { &mut *gop.get() }
-    } else {
-        panic!("no ogp");
-    };
+    });
 
     //    uefi_services::init(&st).expect_success("Failed to initialize utilities");
     unsafe {
@@ -83,10 +263,6 @@ fn efi_main(handle: Handle, st: SystemTable<Boot>) -> Status {
     log::set_logger(logger).unwrap();
     log::set_max_level(log::LevelFilter::Info);
 
-    if st.firmware_vendor().to_string() != "EDK II" {
-        // set gop mode if it is not in QEMU
-        set_gop_mode(gop);
-    }
     log::error!("intentional panic");
     writeln!(stdout, "Hello from rust").unwrap();
     writeln!(stdout, "Firmware Vendor {}", st.firmware_vendor()).unwrap();
@@ -100,128 +276,196 @@ fn efi_main(handle: Handle, st: SystemTable<Boot>) -> Status {
         panic!("no sfs");
     };
     let mut root = sfs.open_volume().unwrap().unwrap();
+    let mut config = read_config(bt, &mut root);
+    log::set_max_level(config.log_level);
+    match (gop.as_mut(), config.resolution) {
+        (Some(gop), Some((hor, ver))) => {
+            if !set_gop_mode(gop, (hor, ver)) {
+                log::warn!("no graphics mode for {}x{}", hor, ver);
+            }
+        }
+        // set gop mode if it is not in QEMU
+        (Some(gop), None) if st.firmware_vendor().to_string() != "EDK II" => {
+            set_default_gop_mode(gop)
+        }
+        (None, _) => log::warn!("no graphics output; booting headless"),
+        _ => {}
+    }
+    if config.timeout > 0 {
+        let kernels = menu::find_kernels(&mut root, &config.kernel);
+        let choice = menu::run(&st, &kernels, &config.cmdline, config.timeout);
+        config.kernel = choice.kernel;
+        config.cmdline = choice.cmdline;
+    }
 
-    let memmap_file = root
-        .open("memmap", FileMode::CreateReadWrite, FileAttribute::empty())
-        .unwrap()
-        .unwrap();
-    let memmap_file = memmap_file.into_type().unwrap().unwrap();
-    if let Regular(mut memmap_file) = memmap_file {
-        let mmap_buf = &mut [0; 4096 * 4];
-        let (_, memmap_iter) = bt.memory_map(mmap_buf).unwrap().unwrap();
-        memmap_file
-            .write("Index, Type, PhysicalStart, NumberOfPages, Attribute\n".as_bytes())
+    if config.memmap {
+        let memmap_file = root
+            .open("memmap", FileMode::CreateReadWrite, FileAttribute::empty())
             .unwrap()
             .unwrap();
-        for (i, m) in memmap_iter.enumerate() {
+        let memmap_file = memmap_file.into_type().unwrap().unwrap();
+        if let Regular(mut memmap_file) = memmap_file {
+            let mmap_buf = &mut [0; 4096 * 4];
+            let (_, memmap_iter) = bt.memory_map(mmap_buf).unwrap().unwrap();
             memmap_file
-                .write(
-                    format!(
-                        "{}, {:?}, {}, {}, {:?}\n",
-                        i, m.ty, m.phys_start, m.page_count, m.att
-                    )
-                    .as_bytes(),
-                )
+                .write("Index, Type, PhysicalStart, NumberOfPages, Attribute\n".as_bytes())
                 .unwrap()
                 .unwrap();
+            for (i, m) in memmap_iter.enumerate() {
+                memmap_file
+                    .write(
+                        format!(
+                            "{}, {:?}, {}, {}, {:?}\n",
+                            i, m.ty, m.phys_start, m.page_count, m.att
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap()
+                    .unwrap();
+            }
+            memmap_file.close();
+        };
+    }
+    let kernel_file_buf = match read_file(bt, &mut root, &config.kernel) {
+        Some(data) => data,
+        None => {
+            writeln!(stdout, "failed to open the kernel {}", config.kernel).unwrap();
+            return Status::LOAD_ERROR;
         }
-        memmap_file.close();
-    };
-    let kernel_file = root
-        .open("laranja-kernel", FileMode::Read, FileAttribute::READ_ONLY)
-        .unwrap()
-        .unwrap();
-    let kernel_file = kernel_file.into_type().unwrap().unwrap();
-    let mut kernel_file = match kernel_file {
-        Regular(f) => f,
-        _ => panic!("kernel file is not regular file"),
     };
-    const BUF_SIZE: usize = 4000;
-    let buf = &mut [0u8; BUF_SIZE];
-    let info: &mut FileInfo = kernel_file.get_info(buf).unwrap().unwrap();
-    let kernel_file_size = info.file_size();
-    let kernel_file_buf = bt
-        .allocate_pool(MemoryType::LOADER_DATA, kernel_file_size as usize)
-        .unwrap()
-        .unwrap();
-    let entry_pointer_address: *const u64 = (kernel_file_buf as u64 + 24) as *const u64;
-    let kernel_file_buf =
-        unsafe { core::slice::from_raw_parts_mut(kernel_file_buf, kernel_file_size as usize) };
-    kernel_file.read(kernel_file_buf).unwrap().unwrap();
-    kernel_file.close();
-
-    let elf = match Elf::from_bytes(&kernel_file_buf).unwrap() {
-        Elf::Elf64(e) => e,
-        Elf::Elf32(_) => {
-            panic!("Elf32 is not supported");
+
+    let mut page_table = PageTableBuilder::new(bt);
+    let entry_pointer = match load_kernel(bt, kernel_file_buf, &mut page_table) {
+        Ok(entry) => entry,
+        Err(e) => {
+            writeln!(stdout, "failed to load the kernel: {}", e).unwrap();
+            return Status::LOAD_ERROR;
         }
     };
-    let mut kernel_first = u64::max_value();
-    let mut kernel_last = u64::min_value();
-    for h in elf.program_header_iter() {
-        let header = h.ph;
-        if matches!(header.ph_type(), ProgramType::LOAD) {
-            let v = header.vaddr();
-            let len = header.memsz();
-            kernel_first = core::cmp::min(kernel_first, v);
-            kernel_last = core::cmp::max(kernel_last, v + len);
+    let frame_buffer = match gop {
+        Some(gop) => {
+            let mi = gop.current_mode_info();
+            let mut fb = gop.frame_buffer();
+            FrameBufferInfo {
+                base: fb.as_mut_ptr() as u64,
+                size: fb.size() as u64,
+                mode: to_mode_info(&mi),
+            }
         }
+        None => FrameBufferInfo::none(),
+    };
+    page_table.map_physical_memory(core::cmp::max(
+        max_physical_address(bt),
+        frame_buffer.base + frame_buffer.size,
+    ));
+    let kernel_stack = StackInfo {
+        bottom: page_table.map_stack(KERNEL_STACK_TOP, KERNEL_STACK_SIZE),
+        top: KERNEL_STACK_TOP,
+    };
+    let pml4 = page_table.pml4_addr();
+    let mut files = vec![LoadedFile::new(
+        FileKind::Kernel,
+        &config.kernel,
+        kernel_file_buf.as_ptr() as u64,
+        kernel_file_buf.len() as u64,
+    )];
+    let initrd = if config.initrd.is_empty() {
+        None
+    } else {
+        read_file(bt, &mut root, &config.initrd)
+    };
+    if let Some(initrd) = initrd {
+        log::info!("initrd {}: {} bytes", config.initrd, initrd.len());
+        files.push(LoadedFile::new(
+            FileKind::Initrd,
+            &config.initrd,
+            initrd.as_ptr() as u64,
+            initrd.len() as u64,
+        ));
     }
-    let kernel_first = kernel_first as usize / 0x1000 * 0x1000;
-    let load_size = kernel_last as usize - kernel_first;
-    let n_of_pages = (load_size + 0xfff) / 0x1000;
-    writeln!(
-        stdout,
-        "kernel_first {:x}, last {:x}, pages {:?}",
-        kernel_first, kernel_last, n_of_pages
-    )
-    .unwrap();
-    bt.allocate_pages(
-        AllocateType::Address(kernel_first),
-        MemoryType::LOADER_DATA,
-        n_of_pages,
-    )
-    .unwrap()
-    .unwrap();
-
-    // load kernel
-    for h in elf.program_header_iter() {
-        let header = h.ph;
-        if matches!(header.ph_type(), ProgramType::LOAD) {
-            let segment = h.segment();
-            let dest = header.vaddr();
-            let len = header.filesz();
-            let dest = unsafe { core::slice::from_raw_parts_mut(dest as *mut u8, len as usize) };
-            (0..len as usize).for_each(|i| {
-                dest[i] = segment[i];
-            });
-        }
+    let files = files.leak();
+    let cmdline = Box::leak(config.cmdline.into_boxed_str());
+    let rsdp = find_rsdp(&st);
+    if rsdp == 0 {
+        log::warn!("no ACPI RSDP in the configuration table");
     }
-
-    let entry_pointer = unsafe { *entry_pointer_address } as *const ();
-    let kernel_entry = unsafe {
-        core::mem::transmute::<
-            *const (),
-            extern "sysv64" fn(fb: *mut FrameBufferInfo, mi: *mut gop::ModeInfo) -> (),
-        >(entry_pointer)
-    };
-    let mut mi = gop.current_mode_info();
-    let mut fb = gop.frame_buffer();
-    let fb_pt = fb.as_mut_ptr();
-    let fb_size = fb.size();
+    let boot_info = Box::leak(Box::new(BootInfo {
+        header: Header::new(),
+        physical_memory_offset: PHYSICAL_MEMORY_OFFSET,
+        frame_buffer,
+        memory_map: Slice::empty(),
+        rsdp,
+        command_line: Str::new(cmdline.as_ptr() as u64, cmdline.len() as u64),
+        files: Slice::new(files.as_ptr() as u64, files.len() as u64),
+        kernel_stack,
+    }));
     // exit boot service
     let max_mmap_size = bt.memory_map_size() + 8 * core::mem::size_of::<MemoryDescriptor>();
     let mut mmap_storage = vec![0; max_mmap_size].into_boxed_slice();
-    let (_st, _iter) = st
+    // no allocation is possible after exiting boot services, so reserve the
+    // kernel's copy of the memory map here.
+    let max_descriptors = max_mmap_size / core::mem::size_of::<MemoryDescriptor>();
+    let memory_map = Box::leak(
+        vec![
+            boot_info::MemoryDescriptor {
+                ty: boot_info::MemoryType::RESERVED,
+                phys_start: 0,
+                page_count: 0,
+                attribute: 0,
+            };
+            max_descriptors
+        ]
+        .into_boxed_slice(),
+    );
+    let (_st, memmap_iter) = st
         .exit_boot_services(handle, &mut mmap_storage[..])
         .expect_success("Failed to exit boot services");
-    let mut fb = FrameBufferInfo {
-        fb: fb_pt,
-        size: fb_size,
-    };
-    kernel_entry(&mut fb, &mut mi);
+    let mut count: u64 = 0;
+    for (dest, m) in memory_map.iter_mut().zip(memmap_iter) {
+        *dest = boot_info::MemoryDescriptor {
+            ty: boot_info::MemoryType(m.ty.0),
+            phys_start: m.phys_start,
+            page_count: m.page_count,
+            attribute: m.att.bits(),
+        };
+        count += 1;
+    }
+    boot_info.memory_map = Slice::new(memory_map.as_ptr() as u64, count);
+    let boot_info = boot_info as *const BootInfo as u64 + PHYSICAL_MEMORY_OFFSET;
+    unsafe { jump_to_kernel(pml4, entry_pointer, boot_info, KERNEL_STACK_TOP) }
+}
 
-    uefi::Status::SUCCESS
+/// Switches to the kernel page tables and stack and calls the kernel entry
+/// point, passing `boot_info` as the first argument. NX and write protection
+/// are enabled so that the segment permissions take effect.
+///
+/// # Safety
+/// The loader code must stay mapped at the same address in `pml4`, and
+/// `stack_top` must be a 16 byte aligned stack mapped in `pml4`.
+unsafe fn jump_to_kernel(pml4: u64, entry: u64, boot_info: u64, stack_top: u64) -> ! {
+    asm!(
+        "cli",
+        // IA32_EFER.NXE
+        "mov ecx, 0xc0000080",
+        "rdmsr",
+        "or eax, 0x800",
+        "wrmsr",
+        // CR0.WP
+        "mov rax, cr0",
+        "or rax, 0x10000",
+        "mov cr0, rax",
+        "mov cr3, r8",
+        "mov rsp, r10",
+        "call r9",
+        "2:",
+        "hlt",
+        "jmp 2b",
+        in("r8") pml4,
+        in("r9") entry,
+        in("r10") stack_top,
+        in("rdi") boot_info,
+        options(noreturn)
+    );
 }
 
 #[alloc_error_handler]
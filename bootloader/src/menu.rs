@@ -0,0 +1,202 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use uefi::{
+    prelude::*,
+    proto::console::text::{Key, ScanCode},
+    proto::media::file::{Directory, FileAttribute},
+    table::boot::{EventType, TimerTrigger, Tpl},
+    Event,
+};
+
+/// Files in the root of the boot volume whose name starts with this are
+/// offered as kernels.
+const KERNEL_PREFIX: &str = "laranja-kernel";
+
+const ENTER: char = '\r';
+const BACKSPACE: char = '\u{8}';
+
+pub struct Choice {
+    pub kernel: String,
+    pub cmdline: String,
+}
+
+struct Menu<'a> {
+    kernels: &'a [String],
+    selected: usize,
+    cmdline: String,
+    remaining: Option<usize>,
+}
+
+/// Lists the kernels in the root of the boot volume. `default` always comes
+/// first, even if it lives somewhere else.
+pub fn find_kernels(root: &mut Directory, default: &str) -> Vec<String> {
+    let mut kernels = Vec::new();
+    let mut buf = [0u8; 512];
+    root.reset_entry_readout().unwrap().unwrap();
+    while let Ok(entry) = root.read_entry(&mut buf) {
+        let info = match entry.unwrap() {
+            Some(info) => info,
+            None => break,
+        };
+        if info.attribute().contains(FileAttribute::DIRECTORY) {
+            continue;
+        }
+        let name: String = char::decode_utf16(info.file_name().to_u16_slice().iter().copied())
+            .map(|c| c.unwrap_or('?'))
+            .collect();
+        if name.starts_with(KERNEL_PREFIX) && name != default {
+            kernels.push(name);
+        }
+    }
+    kernels.sort();
+    kernels.insert(0, String::from(default));
+    kernels
+}
+
+impl<'a> Menu<'a> {
+    fn draw(&self, st: &SystemTable<Boot>) {
+        let stdout = st.stdout();
+        stdout.clear().unwrap().unwrap();
+        writeln!(stdout, "Laranja OS").unwrap();
+        writeln!(stdout).unwrap();
+        for (i, kernel) in self.kernels.iter().enumerate() {
+            let mark = if i == self.selected { '>' } else { ' ' };
+            writeln!(stdout, " {} {}) {}", mark, i + 1, kernel).unwrap();
+        }
+        writeln!(stdout).unwrap();
+        writeln!(stdout, "command line: {}", self.cmdline).unwrap();
+        writeln!(stdout).unwrap();
+        writeln!(
+            stdout,
+            "Up/Down or number to select, Enter to boot, e to edit the command line"
+        )
+        .unwrap();
+        if let Some(remaining) = self.remaining {
+            writeln!(
+                stdout,
+                "Booting {} in {}s",
+                self.kernels[self.selected], remaining
+            )
+            .unwrap();
+        }
+    }
+
+    /// Lets the user edit the command line. Escape keeps the previous one.
+    fn edit_cmdline(&mut self, st: &SystemTable<Boot>) {
+        let stdout = st.stdout();
+        let mut cmdline = self.cmdline.clone();
+        write!(stdout, "\r\n> {}", cmdline).unwrap();
+        loop {
+            match read_key(st) {
+                Key::Printable(c) => match char::from(c) {
+                    ENTER => {
+                        self.cmdline = cmdline;
+                        return;
+                    }
+                    BACKSPACE => {
+                        if cmdline.pop().is_some() {
+                            // move back, blank the character and move back again
+                            write!(stdout, "{} {}", BACKSPACE, BACKSPACE).unwrap();
+                        }
+                    }
+                    c => {
+                        cmdline.push(c);
+                        write!(stdout, "{}", c).unwrap();
+                    }
+                },
+                Key::Special(ScanCode::ESCAPE) => return,
+                Key::Special(_) => {}
+            }
+        }
+    }
+}
+
+fn read_key(st: &SystemTable<Boot>) -> Key {
+    let stdin = st.stdin();
+    let mut events = [stdin.wait_for_key_event()];
+    loop {
+        st.boot_services()
+            .wait_for_event(&mut events)
+            .expect_success("failed to wait for a key");
+        if let Some(key) = stdin.read_key().expect_success("failed to read a key") {
+            return key;
+        }
+    }
+}
+
+/// Closes `event`. uefi 0.10 keeps `CloseEvent()` private, so this calls it
+/// through the boot services table, where it follows the 24-byte header and
+/// eleven other functions.
+fn close_event(bt: &BootServices, event: Event) -> uefi::Result {
+    let table = bt as *const BootServices as *const u8;
+    let close_event: extern "efiapi" fn(Event) -> Status =
+        unsafe { core::mem::transmute(*(table.add(24 + 11 * 8) as *const usize)) };
+    close_event(event).into()
+}
+
+/// Shows the boot menu until the user picks a kernel or `timeout` seconds pass
+/// without any key being pressed. `kernels[0]` is the default.
+pub fn run(st: &SystemTable<Boot>, kernels: &[String], cmdline: &str, timeout: usize) -> Choice {
+    let bt = st.boot_services();
+    let stdin = st.stdin();
+    let timer = unsafe { bt.create_event(EventType::TIMER, Tpl::APPLICATION, None) }
+        .expect_success("failed to create a timer");
+    // in 100ns units
+    bt.set_timer(timer, TimerTrigger::Periodic(10_000_000))
+        .expect_success("failed to set a timer");
+
+    let mut menu = Menu {
+        kernels,
+        selected: 0,
+        cmdline: String::from(cmdline),
+        remaining: Some(timeout),
+    };
+    loop {
+        menu.draw(st);
+        if menu.remaining == Some(0) {
+            break;
+        }
+        let mut events = [stdin.wait_for_key_event(), timer];
+        let index = bt
+            .wait_for_event(&mut events)
+            .expect_success("failed to wait for an event");
+        if index == 1 {
+            menu.remaining = menu.remaining.map(|r| r - 1);
+            continue;
+        }
+        let key = match stdin.read_key().expect_success("failed to read a key") {
+            Some(key) => key,
+            None => continue,
+        };
+        // any key stops the countdown
+        menu.remaining = None;
+        match key {
+            Key::Special(ScanCode::UP) => menu.selected = menu.selected.saturating_sub(1),
+            Key::Special(ScanCode::DOWN) => {
+                menu.selected = core::cmp::min(menu.selected + 1, kernels.len() - 1)
+            }
+            Key::Printable(c) => match char::from(c) {
+                ENTER => break,
+                'e' => menu.edit_cmdline(st),
+                c => {
+                    if let Some(i) = c.to_digit(10) {
+                        if 1 <= i as usize && i as usize <= kernels.len() {
+                            menu.selected = i as usize - 1;
+                        }
+                    }
+                }
+            },
+            Key::Special(_) => {}
+        }
+    }
+    bt.set_timer(timer, TimerTrigger::Cancel)
+        .expect_success("failed to cancel a timer");
+    close_event(bt, timer).expect_success("failed to close a timer");
+    st.stdout().clear().unwrap().unwrap();
+
+    Choice {
+        kernel: kernels[menu.selected].clone(),
+        cmdline: menu.cmdline,
+    }
+}
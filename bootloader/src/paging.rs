@@ -0,0 +1,127 @@
+use uefi::{
+    prelude::*,
+    table::boot::{AllocateType, BootServices, MemoryType},
+};
+
+/// Where the kernel sees the whole physical memory.
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xffff_8000_0000_0000;
+
+/// The kernel stack ends right below the kernel image at `0xffff_ffff_8000_0000`,
+/// leaving a few unmapped pages in between.
+pub const KERNEL_STACK_TOP: u64 = 0xffff_ffff_7fff_0000;
+pub const KERNEL_STACK_SIZE: u64 = 1024 * 1024;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+const HUGE_PAGE_SIZE: u64 = 0x20_0000;
+const PML4_ENTRY_SIZE: u64 = 0x80_0000_0000;
+
+pub const PRESENT: u64 = 1;
+pub const WRITABLE: u64 = 1 << 1;
+const HUGE_PAGE: u64 = 1 << 7;
+pub const NO_EXECUTE: u64 = 1 << 63;
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+#[repr(C, align(4096))]
+struct PageTable([u64; 512]);
+
+/// Builds the page tables the kernel starts with, while boot services are
+/// still available to allocate them.
+pub struct PageTableBuilder<'a> {
+    bt: &'a BootServices,
+    pml4: &'static mut PageTable,
+    pml4_addr: u64,
+}
+
+fn index(virt: u64, level: u32) -> usize {
+    ((virt >> (12 + 9 * (level - 1))) & 0x1ff) as usize
+}
+
+impl<'a> PageTableBuilder<'a> {
+    pub fn new(bt: &'a BootServices) -> Self {
+        let (pml4, pml4_addr) = Self::allocate_table(bt);
+        PageTableBuilder {
+            bt,
+            pml4,
+            pml4_addr,
+        }
+    }
+
+    pub fn pml4_addr(&self) -> u64 {
+        self.pml4_addr
+    }
+
+    fn allocate_table(bt: &BootServices) -> (&'static mut PageTable, u64) {
+        let addr = bt
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1)
+            .expect_success("failed to allocate a page table");
+        let table = unsafe { &mut *(addr as *mut PageTable) };
+        table.0.iter_mut().for_each(|e| *e = 0);
+        (table, addr)
+    }
+
+    fn next_table(
+        bt: &BootServices,
+        table: &mut PageTable,
+        index: usize,
+    ) -> &'static mut PageTable {
+        let entry = &mut table.0[index];
+        if *entry & PRESENT == 0 {
+            let (_, addr) = Self::allocate_table(bt);
+            *entry = addr | PRESENT | WRITABLE;
+        }
+        unsafe { &mut *((*entry & ADDRESS_MASK) as *mut PageTable) }
+    }
+
+    /// Maps a 4 KiB page.
+    pub fn map_page(&mut self, virt: u64, phys: u64, flags: u64) {
+        let pdpt = Self::next_table(self.bt, self.pml4, index(virt, 4));
+        let pd = Self::next_table(self.bt, pdpt, index(virt, 3));
+        let pt = Self::next_table(self.bt, pd, index(virt, 2));
+        pt.0[index(virt, 1)] = phys | flags | PRESENT;
+    }
+
+    /// Allocates `size` bytes and maps them right below `top` as a stack.
+    /// Nothing is mapped at the page below the stack, so an overflow faults
+    /// instead of overwriting other memory. Returns the bottom of the stack.
+    pub fn map_stack(&mut self, top: u64, size: u64) -> u64 {
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let phys = self
+            .bt
+            .allocate_pages(
+                AllocateType::AnyPages,
+                MemoryType::LOADER_DATA,
+                pages as usize,
+            )
+            .expect_success("failed to allocate the kernel stack");
+        let bottom = top - pages * PAGE_SIZE;
+        for i in 0..pages {
+            self.map_page(
+                bottom + i * PAGE_SIZE,
+                phys + i * PAGE_SIZE,
+                WRITABLE | NO_EXECUTE,
+            );
+        }
+        bottom
+    }
+
+    fn map_huge_page(&mut self, virt: u64, phys: u64, flags: u64) {
+        let pdpt = Self::next_table(self.bt, self.pml4, index(virt, 4));
+        let pd = Self::next_table(self.bt, pdpt, index(virt, 3));
+        pd.0[index(virt, 2)] = phys | flags | PRESENT | HUGE_PAGE;
+    }
+
+    /// Maps `0..end` of the physical memory both at the same address, which the
+    /// loader keeps running on after switching, and at `PHYSICAL_MEMORY_OFFSET`.
+    pub fn map_physical_memory(&mut self, end: u64) {
+        let end = (end + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1);
+        for addr in (0..end).step_by(HUGE_PAGE_SIZE as usize) {
+            self.map_huge_page(addr, addr, WRITABLE);
+        }
+        // the direct map shares the lower level tables with the identity map
+        let entries = ((end + PML4_ENTRY_SIZE - 1) / PML4_ENTRY_SIZE) as usize;
+        let first = index(PHYSICAL_MEMORY_OFFSET, 4);
+        for i in 0..entries {
+            self.pml4.0[first + i] = self.pml4.0[i];
+        }
+    }
+}
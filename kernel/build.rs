@@ -0,0 +1,126 @@
+//! Generates the tables of `src/pci/ids.rs` from `pci.ids`.
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+struct Entry {
+    id: u16,
+    name: String,
+    children: Vec<Entry>,
+}
+
+impl Entry {
+    fn parse(line: &str, id_len: usize) -> Entry {
+        let (id, name) = line.split_at(id_len);
+        Entry {
+            id: u16::from_str_radix(id, 16)
+                .unwrap_or_else(|_| panic!("pci.ids: bad id in {:?}", line)),
+            name: name.trim().to_string(),
+            children: Vec::new(),
+        }
+    }
+}
+
+/// The vendors and the classes, with their children sorted by ID.
+fn parse(ids: &str) -> (Vec<Entry>, Vec<Entry>) {
+    let mut vendors: Vec<Entry> = Vec::new();
+    let mut classes: Vec<Entry> = Vec::new();
+    let mut in_classes = false;
+    for line in ids.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(class) = line.strip_prefix("C ") {
+            in_classes = true;
+            classes.push(Entry::parse(class, 2));
+        } else if let Some(line) = line.strip_prefix("\t\t") {
+            // subsystems of a device are ignored
+            if in_classes {
+                let class = classes.last_mut().expect("pci.ids: prog-if without class");
+                let sub = class
+                    .children
+                    .last_mut()
+                    .expect("pci.ids: prog-if without subclass");
+                sub.children.push(Entry::parse(line, 2));
+            }
+        } else if let Some(line) = line.strip_prefix('\t') {
+            let (parent, id_len) = if in_classes {
+                (classes.last_mut(), 2)
+            } else {
+                (vendors.last_mut(), 4)
+            };
+            let parent = parent.expect("pci.ids: entry without parent");
+            parent.children.push(Entry::parse(line, id_len));
+        } else {
+            in_classes = false;
+            vendors.push(Entry::parse(line, 4));
+        }
+    }
+    fn sort(entries: &mut [Entry]) {
+        entries.sort_by_key(|e| e.id);
+        entries.iter_mut().for_each(|e| sort(&mut e.children));
+    }
+    sort(&mut vendors);
+    sort(&mut classes);
+    (vendors, classes)
+}
+
+fn generate(vendors: &[Entry], classes: &[Entry]) -> String {
+    let mut out = String::new();
+    writeln!(out, "// generated by build.rs from pci.ids").unwrap();
+    writeln!(out, "static VENDORS: &[Vendor] = &[").unwrap();
+    for vendor in vendors {
+        write!(
+            out,
+            "Vendor {{ id: 0x{:04x}, name: {:?}, devices: &[",
+            vendor.id, vendor.name
+        )
+        .unwrap();
+        for device in &vendor.children {
+            write!(out, "(0x{:04x}, {:?}), ", device.id, device.name).unwrap();
+        }
+        writeln!(out, "] }},").unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    writeln!(out, "static CLASSES: &[Class] = &[").unwrap();
+    for class in classes {
+        writeln!(
+            out,
+            "Class {{ id: 0x{:02x}, name: {:?}, subclasses: &[",
+            class.id, class.name
+        )
+        .unwrap();
+        for sub in &class.children {
+            write!(
+                out,
+                "Subclass {{ id: 0x{:02x}, name: {:?}, interfaces: &[",
+                sub.id, sub.name
+            )
+            .unwrap();
+            for interface in &sub.children {
+                write!(out, "(0x{:02x}, {:?}), ", interface.id, interface.name).unwrap();
+            }
+            writeln!(out, "] }},").unwrap();
+        }
+        writeln!(out, "] }},").unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    out
+}
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let ids_path = Path::new(&manifest_dir).join("pci.ids");
+    println!("cargo:rerun-if-changed={}", ids_path.display());
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let ids = fs::read_to_string(&ids_path).expect("failed to read pci.ids");
+    let (vendors, classes) = parse(&ids);
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(
+        Path::new(&out_dir).join("pci_ids.rs"),
+        generate(&vendors, &classes),
+    )
+    .expect("failed to write pci_ids.rs");
+}
@@ -0,0 +1,460 @@
+//! ACPI table discovery.
+//!
+//! The loader passes the physical address of the RSDP. From there the RSDT, or
+//! the XSDT on ACPI 2.0 and later, lists every other table. All tables are
+//! read through the direct map of the physical memory.
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::mem::size_of;
+
+use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use x86_64::PhysAddr;
+
+use crate::paging::{self, CacheMode};
+
+pub mod aml;
+pub mod fadt;
+pub mod madt;
+pub mod mcfg;
+
+static TABLES: spin::Mutex<Option<Tables>> = spin::Mutex::new(None);
+
+/// Physical pages mapped for memory registers and their virtual addresses.
+static REGISTER_PAGES: spin::Mutex<BTreeMap<u64, u64>> = spin::Mutex::new(BTreeMap::new());
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    NoRsdp,
+    BadSignature,
+    BadChecksum,
+    BadLength,
+    NotFound,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0 and later
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the ACPI 1.0 part of the RSDP, covered by `checksum`.
+const RSDP_V1_SIZE: usize = 20;
+
+/// The header every system description table starts with.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// # Safety
+/// `addr` must point to `len` readable bytes.
+unsafe fn bytes<'a>(addr: u64, len: usize) -> &'a [u8] {
+    core::slice::from_raw_parts(addr as *const u8, len)
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    /// The whole table including this header.
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { bytes(self as *const Self as u64, self.length as usize) }
+    }
+
+    /// The part of the table following this header.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes()[size_of::<Self>()..]
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (self.length as usize) >= size_of::<Self>() && sum(self.bytes()) == 0
+    }
+
+    fn validate(&self, signature: &[u8; 4]) -> Result<()> {
+        if &self.signature != signature {
+            Err(Error::BadSignature)
+        } else if (self.length as usize) < size_of::<Self>() {
+            Err(Error::BadLength)
+        } else if sum(self.bytes()) != 0 {
+            Err(Error::BadChecksum)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for SdtHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let length = self.length;
+        let revision = self.revision;
+        write!(
+            f,
+            "{} rev {} len {} oem {} {}",
+            self.signature(),
+            revision,
+            length,
+            core::str::from_utf8(&self.oem_id).unwrap_or("?"),
+            core::str::from_utf8(&self.oem_table_id).unwrap_or("?"),
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+/// Generic Address Structure, the location of a register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// 1: byte, 2: word, 3: dword, 4: qword, 0: derive from `bit_width`.
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SIZE: usize = 12;
+
+    fn parse(data: &[u8]) -> Self {
+        let space = match data[0] {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfig,
+            n => AddressSpace::Other(n),
+        };
+        GenericAddress {
+            space,
+            bit_width: data[1],
+            bit_offset: data[2],
+            access_size: data[3],
+            address: u64_at(data, 4),
+        }
+    }
+
+    /// A register block in the I/O space given by the ACPI 1.0 fields.
+    pub fn io(port: u32, len: u8) -> Self {
+        GenericAddress {
+            space: AddressSpace::SystemIo,
+            bit_width: len * 8,
+            bit_offset: 0,
+            access_size: 0,
+            address: port as u64,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    fn access_bytes(&self) -> u8 {
+        match self.access_size {
+            1..=4 => 1 << (self.access_size - 1),
+            _ => core::cmp::max(self.bit_width / 8, 1),
+        }
+    }
+
+    /// Returns `None` if the address space is not supported.
+    ///
+    /// # Safety
+    /// Reading the register must have no unexpected side effects.
+    pub unsafe fn read(&self) -> Option<u64> {
+        match self.space {
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                Some(match self.access_bytes() {
+                    1 => PortReadOnly::<u8>::new(port).read() as u64,
+                    2 => PortReadOnly::<u16>::new(port).read() as u64,
+                    _ => PortReadOnly::<u32>::new(port).read() as u64,
+                })
+            }
+            AddressSpace::SystemMemory => {
+                let addr = self.map()?;
+                Some(match self.access_bytes() {
+                    1 => (addr as *const u8).read_volatile() as u64,
+                    2 => (addr as *const u16).read_volatile() as u64,
+                    4 => (addr as *const u32).read_volatile() as u64,
+                    _ => (addr as *const u64).read_volatile(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns false if the address space is not supported.
+    ///
+    /// # Safety
+    /// Writing `value` to the register must be safe.
+    pub unsafe fn write(&self, value: u64) -> bool {
+        match self.space {
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                match self.access_bytes() {
+                    1 => PortWriteOnly::<u8>::new(port).write(value as u8),
+                    2 => PortWriteOnly::<u16>::new(port).write(value as u16),
+                    _ => PortWriteOnly::<u32>::new(port).write(value as u32),
+                }
+                true
+            }
+            AddressSpace::SystemMemory => match self.map() {
+                Some(addr) => {
+                    match self.access_bytes() {
+                        1 => (addr as *mut u8).write_volatile(value as u8),
+                        2 => (addr as *mut u16).write_volatile(value as u16),
+                        4 => (addr as *mut u32).write_volatile(value as u32),
+                        _ => (addr as *mut u64).write_volatile(value),
+                    }
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    // registers are polled, so each page is mapped once and kept mapped
+    fn map(&self) -> Option<u64> {
+        let page = self.address & !0xfff;
+        let mut pages = REGISTER_PAGES.lock();
+        let virt = match pages.get(&page) {
+            Some(&virt) => virt,
+            None => {
+                let virt = paging::map_mmio(PhysAddr::new(page), 0x1000, CacheMode::Uncached)
+                    .ok()?
+                    .as_u64();
+                pages.insert(page, virt);
+                virt
+            }
+        };
+        Some(virt + (self.address & 0xfff))
+    }
+}
+
+/// The root table (RSDT or XSDT) and how to reach the tables it lists.
+#[derive(Copy, Clone, Debug)]
+pub struct Tables {
+    /// Added to physical addresses to access them.
+    offset: u64,
+    root: &'static SdtHeader,
+    /// 4 for the RSDT, 8 for the XSDT.
+    entry_size: usize,
+}
+
+impl Tables {
+    /// Validates the RSDP at `rsdp` and the root table it points to.
+    ///
+    /// # Safety
+    /// Physical memory must be mapped at `offset`, and `rsdp` must be the
+    /// address given by the firmware.
+    pub unsafe fn from_rsdp(rsdp: u64, offset: u64) -> Result<Self> {
+        if rsdp == 0 {
+            return Err(Error::NoRsdp);
+        }
+        let rsdp_bytes = bytes(rsdp + offset, RSDP_V1_SIZE);
+        let r = (rsdp_bytes.as_ptr() as *const Rsdp).read_unaligned();
+        if &r.signature != b"RSD PTR " {
+            return Err(Error::BadSignature);
+        }
+        if sum(rsdp_bytes) != 0 {
+            return Err(Error::BadChecksum);
+        }
+
+        let (root, entry_size, signature) = if r.revision >= 2 {
+            let length = r.length as usize;
+            if length < size_of::<Rsdp>() {
+                return Err(Error::BadLength);
+            }
+            if sum(bytes(rsdp + offset, length)) != 0 {
+                return Err(Error::BadChecksum);
+            }
+            (r.xsdt_address, 8, b"XSDT")
+        } else {
+            (r.rsdt_address as u64, 4, b"RSDT")
+        };
+        let root = &*((root + offset) as *const SdtHeader);
+        root.validate(signature)?;
+        Ok(Tables {
+            offset,
+            root,
+            entry_size,
+        })
+    }
+
+    pub fn root(&self) -> &'static SdtHeader {
+        self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.data().len() / self.entry_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All tables listed in the root table. Their checksums are not checked;
+    /// see [`SdtHeader::is_valid`].
+    pub fn iter(&self) -> impl Iterator<Item = &'static SdtHeader> + '_ {
+        let data = self.root.data();
+        data.chunks_exact(self.entry_size).map(move |entry| {
+            let mut addr = [0u8; 8];
+            addr[..entry.len()].copy_from_slice(entry);
+            let addr = u64::from_le_bytes(addr);
+            unsafe { &*((addr + self.offset) as *const SdtHeader) }
+        })
+    }
+
+    /// The first valid table with `signature`.
+    pub fn find(&self, signature: &[u8; 4]) -> Result<&'static SdtHeader> {
+        self.iter()
+            .filter(|t| &t.signature == signature)
+            .find(|t| t.is_valid())
+            .ok_or(Error::NotFound)
+    }
+
+    /// A table which is not listed in the root table, such as the DSDT.
+    pub fn table_at(&self, addr: u64, signature: &[u8; 4]) -> Result<&'static SdtHeader> {
+        if addr == 0 {
+            return Err(Error::NotFound);
+        }
+        let table = unsafe { &*((addr + self.offset) as *const SdtHeader) };
+        table.validate(signature)?;
+        Ok(table)
+    }
+}
+
+/// Finds the ACPI tables from the RSDP passed by the loader.
+pub fn init(rsdp: u64) -> Result<Tables> {
+    let tables = unsafe { Tables::from_rsdp(rsdp, paging::physical_memory_offset())? };
+    *TABLES.lock() = Some(tables);
+    Ok(tables)
+}
+
+pub fn tables() -> Option<Tables> {
+    *TABLES.lock()
+}
+
+pub fn find(signature: &[u8; 4]) -> Result<&'static SdtHeader> {
+    tables().ok_or(Error::NoRsdp)?.find(signature)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn fix_checksum(bytes: &mut [u8], checksum_offset: usize) {
+        bytes[checksum_offset] = 0;
+        bytes[checksum_offset] = 0u8.wrapping_sub(sum(bytes));
+    }
+
+    /// Builds a table with `signature` and `data`, with a valid checksum.
+    pub fn make_table(signature: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let length = size_of::<SdtHeader>() + data.len();
+        let mut table = Vec::with_capacity(length);
+        table.extend_from_slice(signature);
+        table.extend_from_slice(&(length as u32).to_le_bytes());
+        table.push(1);
+        table.push(0);
+        table.extend_from_slice(b"LARNJA");
+        table.extend_from_slice(b"TESTTABL");
+        table.extend_from_slice(&[0; 12]);
+        table.extend_from_slice(data);
+        fix_checksum(&mut table, 9);
+        table
+    }
+
+    fn make_rsdp(xsdt: u64) -> Vec<u8> {
+        let mut rsdp = Vec::new();
+        rsdp.extend_from_slice(b"RSD PTR ");
+        rsdp.push(0);
+        rsdp.extend_from_slice(b"LARNJA");
+        rsdp.push(2);
+        rsdp.extend_from_slice(&0u32.to_le_bytes());
+        rsdp.extend_from_slice(&(size_of::<Rsdp>() as u32).to_le_bytes());
+        rsdp.extend_from_slice(&xsdt.to_le_bytes());
+        rsdp.extend_from_slice(&[0; 4]);
+        fix_checksum(&mut rsdp[..RSDP_V1_SIZE], 8);
+        fix_checksum(&mut rsdp, 32);
+        rsdp
+    }
+
+    #[test_case]
+    fn test_enumerate_tables() {
+        let apic = make_table(b"APIC", &[1, 2, 3, 4]);
+        let mut broken = make_table(b"FACP", &[0; 8]);
+        broken[40] = 1;
+        let mut entries = Vec::new();
+        entries.extend_from_slice(&(apic.as_ptr() as u64).to_le_bytes());
+        entries.extend_from_slice(&(broken.as_ptr() as u64).to_le_bytes());
+        let xsdt = make_table(b"XSDT", &entries);
+        let rsdp = make_rsdp(xsdt.as_ptr() as u64);
+
+        // the test buffers are addressed directly, so the offset is 0
+        let tables = unsafe { Tables::from_rsdp(rsdp.as_ptr() as u64, 0) }.unwrap();
+        assert_eq!(tables.len(), 2);
+        let signatures: Vec<_> = tables.iter().map(|t| t.signature()).collect();
+        assert_eq!(signatures, ["APIC", "FACP"]);
+        assert_eq!(tables.find(b"APIC").unwrap().data(), &[1, 2, 3, 4]);
+        assert_eq!(tables.find(b"FACP").unwrap_err(), Error::NotFound);
+        assert_eq!(tables.find(b"MCFG").unwrap_err(), Error::NotFound);
+    }
+
+    #[test_case]
+    fn test_bad_rsdp() {
+        let xsdt = make_table(b"XSDT", &[]);
+        let mut rsdp = make_rsdp(xsdt.as_ptr() as u64);
+        rsdp[20] ^= 1;
+        let r = unsafe { Tables::from_rsdp(rsdp.as_ptr() as u64, 0) };
+        assert_eq!(r.unwrap_err(), Error::BadChecksum);
+        let rsdt = make_table(b"RSDT", &[]);
+        let r = unsafe { Tables::from_rsdp(make_rsdp(rsdt.as_ptr() as u64).as_ptr() as u64, 0) };
+        assert_eq!(r.unwrap_err(), Error::BadSignature);
+    }
+}
@@ -0,0 +1,775 @@
+//! A small AML interpreter.
+//!
+//! The DSDT and the SSDTs are definition blocks of AML byte code. Loading them
+//! builds the ACPI namespace, and control methods such as `_STA` or `_PRT` are
+//! executed on demand. Only what firmware commonly uses to describe devices,
+//! interrupt routing and sleep states is supported: there are no buffer
+//! fields, index fields or references, and waiting does nothing.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use x86_64::PhysAddr;
+
+use super::fadt::{self, Fadt};
+use super::{SdtHeader, Tables};
+use crate::paging::{self, CacheMode};
+use crate::{pci, warn};
+
+mod name;
+mod namespace;
+mod parser;
+mod value;
+
+pub use name::{Name, NameSeg, Path};
+pub use namespace::{Field, Method, Namespace, Object, Region};
+pub use value::Value;
+
+static INTERPRETER: spin::Mutex<Option<Interpreter>> = spin::Mutex::new(None);
+
+/// What `_STA` means if a device does not have it: present, enabled, shown
+/// and functioning.
+const DEFAULT_STATUS: u64 = 0x0f;
+const STATUS_PRESENT: u64 = 1;
+
+/// The IDs of PCI and PCI Express host bridges.
+const PCI_HOST_BRIDGE_IDS: [&str; 2] = ["PNP0A03", "PNP0A08"];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEnd,
+    InvalidOpcode(u16),
+    InvalidName,
+    UndefinedName,
+    AlreadyDefined,
+    TypeMismatch,
+    Uninitialized,
+    IndexOutOfRange,
+    DivideByZero,
+    TooDeep,
+    LoopLimit,
+    /// An address space or a field the interpreter can't access.
+    Unsupported,
+    /// The AML code executed `Fatal`.
+    Fatal,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Where an access to an operation region goes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegionAddress {
+    Memory(u64),
+    Io(u16),
+    /// A register in the configuration space of a PCI function.
+    PciConfig(pci::Address, u16),
+}
+
+/// Accesses the hardware for operation regions. `size` is 1, 2, 4 or 8
+/// bytes.
+pub trait Handler {
+    fn read(&mut self, address: RegionAddress, size: u8) -> Result<u64>;
+    fn write(&mut self, address: RegionAddress, size: u8, value: u64) -> Result<()>;
+}
+
+/// Accesses the real hardware.
+#[derive(Default)]
+pub struct Hardware {
+    /// Physical pages mapped for memory regions and their virtual addresses.
+    pages: BTreeMap<u64, u64>,
+}
+
+impl Hardware {
+    fn map(&mut self, addr: u64) -> Result<u64> {
+        let page = addr & !0xfff;
+        let virt = match self.pages.get(&page) {
+            Some(&virt) => virt,
+            None => {
+                let virt = paging::map_mmio(PhysAddr::new(page), 0x1000, CacheMode::Uncached)
+                    .map_err(|_| Error::Unsupported)?
+                    .as_u64();
+                self.pages.insert(page, virt);
+                virt
+            }
+        };
+        Ok(virt + (addr & 0xfff))
+    }
+}
+
+impl Handler for Hardware {
+    fn read(&mut self, address: RegionAddress, size: u8) -> Result<u64> {
+        match address {
+            RegionAddress::Memory(addr) => {
+                let virt = self.map(addr)?;
+                unsafe {
+                    Ok(match size {
+                        1 => (virt as *const u8).read_volatile() as u64,
+                        2 => (virt as *const u16).read_volatile() as u64,
+                        4 => (virt as *const u32).read_volatile() as u64,
+                        _ => (virt as *const u64).read_volatile(),
+                    })
+                }
+            }
+            RegionAddress::Io(port) => unsafe {
+                Ok(match size {
+                    1 => PortReadOnly::<u8>::new(port).read() as u64,
+                    2 => PortReadOnly::<u16>::new(port).read() as u64,
+                    4 => PortReadOnly::<u32>::new(port).read() as u64,
+                    _ => return Err(Error::Unsupported),
+                })
+            },
+            RegionAddress::PciConfig(pci, offset) => Ok(match size {
+                1 => pci::read_config::<u8>(pci, offset) as u64,
+                2 => pci::read_config::<u16>(pci, offset) as u64,
+                4 => pci::read_config::<u32>(pci, offset) as u64,
+                _ => return Err(Error::Unsupported),
+            }),
+        }
+    }
+
+    fn write(&mut self, address: RegionAddress, size: u8, value: u64) -> Result<()> {
+        match address {
+            RegionAddress::Memory(addr) => {
+                let virt = self.map(addr)?;
+                unsafe {
+                    match size {
+                        1 => (virt as *mut u8).write_volatile(value as u8),
+                        2 => (virt as *mut u16).write_volatile(value as u16),
+                        4 => (virt as *mut u32).write_volatile(value as u32),
+                        _ => (virt as *mut u64).write_volatile(value),
+                    }
+                }
+            }
+            RegionAddress::Io(port) => unsafe {
+                match size {
+                    1 => PortWriteOnly::<u8>::new(port).write(value as u8),
+                    2 => PortWriteOnly::<u16>::new(port).write(value as u16),
+                    4 => PortWriteOnly::<u32>::new(port).write(value as u32),
+                    _ => return Err(Error::Unsupported),
+                }
+            },
+            RegionAddress::PciConfig(pci, offset) => match size {
+                1 => pci::write_config(pci, offset, value as u8),
+                2 => pci::write_config(pci, offset, value as u16),
+                4 => pci::write_config(pci, offset, value as u32),
+                _ => return Err(Error::Unsupported),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a PCI routing table (`_PRT`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrtEntry {
+    /// The device number on the bus of the bridge.
+    pub device: u8,
+    /// 0 for INTA# to 3 for INTD#.
+    pub pin: u8,
+    /// The interrupt link device, or `None` if the pin is hardwired.
+    pub source: Option<Path>,
+    /// The global system interrupt if the pin is hardwired, otherwise the
+    /// index of the interrupt in the resources of the link device.
+    pub source_index: u32,
+}
+
+impl PrtEntry {
+    /// Parses an entry of the `_PRT` of `bridge`. A source given as a string
+    /// is looked up from the bridge.
+    fn parse(entry: &Value, bridge: &Path, namespace: &Namespace) -> Result<Self> {
+        let entry = entry.as_package()?;
+        if entry.len() < 4 {
+            return Err(Error::TypeMismatch);
+        }
+        let source = match &entry[2] {
+            Value::Reference(path) => Some(path.clone()),
+            Value::String(s) if !s.is_empty() => Some(
+                namespace
+                    .lookup(&Name::parse(s)?, bridge)
+                    .ok_or(Error::UndefinedName)?,
+            ),
+            _ => None,
+        };
+        Ok(PrtEntry {
+            device: (entry[0].as_integer()? >> 16) as u8,
+            pin: entry[1].as_integer()? as u8,
+            source,
+            source_index: entry[3].as_integer()? as u32,
+        })
+    }
+}
+
+/// Decodes a compressed EISA ID such as `PNP0A03`.
+pub fn eisa_id(id: u32) -> String {
+    let id = id.swap_bytes();
+    let c = |shift: u32| (((id >> shift) & 0x1f) as u8 + 0x40) as char;
+    format!("{}{}{}{:04X}", c(26), c(21), c(16), id & 0xffff)
+}
+
+pub struct Interpreter {
+    namespace: Namespace,
+    handler: Box<dyn Handler + Send>,
+    /// All bits of an integer set. Integers are 32 bits wide if the DSDT
+    /// revision is less than 2.
+    ones: u64,
+    depth: usize,
+}
+
+impl Interpreter {
+    pub fn new(handler: Box<dyn Handler + Send>) -> Self {
+        Interpreter {
+            namespace: Namespace::new(),
+            handler,
+            ones: u64::MAX,
+            depth: 0,
+        }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Loads a DSDT or an SSDT into the namespace. The rest of an object
+    /// which fails to load is skipped with a warning.
+    pub fn load_table(&mut self, table: &SdtHeader) -> Result<()> {
+        if &table.signature == b"DSDT" && table.revision < 2 {
+            self.ones = u32::MAX as u64;
+        }
+        self.load(table.data())
+    }
+
+    /// Runs a method or returns the value of another object.
+    pub fn evaluate(&mut self, path: &Path, args: Vec<Value>) -> Result<Value> {
+        let path = match self.namespace.get(path) {
+            Some(Object::Alias(target)) => target.clone(),
+            Some(_) => path.clone(),
+            None => return Err(Error::UndefinedName),
+        };
+        match self.namespace.get(&path).cloned() {
+            Some(Object::Method(method)) => self.call(&path, &method, args),
+            Some(Object::Value(value)) => Ok(value),
+            Some(Object::Field(field)) => Ok(Value::Integer(self.read_field(&field)?)),
+            Some(_) => Ok(Value::Reference(path)),
+            None => Err(Error::UndefinedName),
+        }
+    }
+
+    /// Evaluates an optional object that gives an integer.
+    pub fn evaluate_or(&mut self, path: &Path, default: u64) -> Result<u64> {
+        match self.evaluate(path, Vec::new()) {
+            Err(Error::UndefinedName) if self.namespace.get(path).is_none() => Ok(default),
+            result => result?.as_integer(),
+        }
+    }
+
+    /// `_STA` of a device.
+    pub fn status(&mut self, device: &Path) -> Result<u64> {
+        self.evaluate_or(&device.join(*b"_STA"), DEFAULT_STATUS)
+    }
+
+    /// `_HID` of a device, with EISA IDs decoded.
+    pub fn hardware_id(&mut self, device: &Path) -> Result<Option<String>> {
+        match self.evaluate(&device.join(*b"_HID"), Vec::new()) {
+            Ok(Value::Integer(id)) => Ok(Some(eisa_id(id as u32))),
+            Ok(Value::String(id)) => Ok(Some(id)),
+            Ok(_) => Err(Error::TypeMismatch),
+            Err(Error::UndefinedName) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `_HID` or `_CID` of a device names a PCI host bridge.
+    fn is_pci_host_bridge(&mut self, device: &Path) -> bool {
+        let is_host_bridge = |id: &str| PCI_HOST_BRIDGE_IDS.contains(&id);
+        if matches!(self.hardware_id(device), Ok(Some(id)) if is_host_bridge(&id)) {
+            return true;
+        }
+        match self.evaluate(&device.join(*b"_CID"), Vec::new()) {
+            Ok(Value::Integer(id)) => is_host_bridge(&eisa_id(id as u32)),
+            Ok(Value::String(id)) => is_host_bridge(&id),
+            _ => false,
+        }
+    }
+
+    /// `_BBN` of the present PCI host bridges, the bus each of them decodes,
+    /// in ascending order.
+    pub fn pci_root_buses(&mut self) -> Vec<u8> {
+        let devices: Vec<Path> = self.namespace.devices().cloned().collect();
+        let mut buses = Vec::new();
+        for device in devices {
+            if !self.is_pci_host_bridge(&device)
+                || !matches!(self.status(&device), Ok(status) if status & STATUS_PRESENT != 0)
+            {
+                continue;
+            }
+            match self.evaluate_or(&device.join(*b"_BBN"), 0) {
+                Ok(bus) => buses.push(bus as u8),
+                Err(e) => warn!("AML: {}._BBN: {:?}", device, e),
+            }
+        }
+        buses.sort_unstable();
+        buses.dedup();
+        buses
+    }
+
+    /// The interrupt routing of the devices behind a PCI bridge.
+    pub fn pci_routing(&mut self, bridge: &Path) -> Result<Vec<PrtEntry>> {
+        let table = self.evaluate(&bridge.join(*b"_PRT"), Vec::new())?;
+        table
+            .as_package()?
+            .iter()
+            .map(|entry| PrtEntry::parse(entry, bridge, &self.namespace))
+            .collect()
+    }
+}
+
+/// Loads the DSDT and the SSDTs.
+pub fn init(tables: &Tables) -> super::Result<()> {
+    let fadt = Fadt::parse(tables.find(fadt::SIGNATURE)?)?;
+    let dsdt = tables.table_at(fadt.dsdt_address(), b"DSDT")?;
+    let mut interpreter = Interpreter::new(Box::new(Hardware::default()));
+    if let Err(e) = interpreter.load_table(dsdt) {
+        warn!("AML: failed to load the DSDT: {:?}", e);
+    }
+    for ssdt in tables
+        .iter()
+        .filter(|t| &t.signature == b"SSDT" && t.is_valid())
+    {
+        if let Err(e) = interpreter.load_table(ssdt) {
+            warn!("AML: failed to load {}: {:?}", ssdt, e);
+        }
+    }
+    *INTERPRETER.lock() = Some(interpreter);
+    Ok(())
+}
+
+/// Runs `f` with the interpreter, if the tables have been loaded.
+pub fn with<R>(f: impl FnOnce(&mut Interpreter) -> R) -> Option<R> {
+    INTERPRETER.lock().as_mut().map(f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acpi::test::make_table;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    const PNP0A03: u32 = 0x030a_d041;
+    const PNP0A08: u32 = 0x080a_d041;
+    const PNP0C0F: u32 = 0x0f0c_d041;
+
+    /// Registers by address; the rest read as zero.
+    #[derive(Clone, Default)]
+    struct Mock(Arc<spin::Mutex<Vec<(RegionAddress, u64)>>>);
+
+    impl Mock {
+        fn get(&self, address: RegionAddress) -> Option<u64> {
+            let regs = self.0.lock();
+            regs.iter().find(|(a, _)| *a == address).map(|&(_, v)| v)
+        }
+
+        fn set(&self, address: RegionAddress, value: u64) {
+            let mut regs = self.0.lock();
+            regs.retain(|(a, _)| *a != address);
+            regs.push((address, value));
+        }
+    }
+
+    impl Handler for Mock {
+        fn read(&mut self, address: RegionAddress, _size: u8) -> Result<u64> {
+            Ok(self.get(address).unwrap_or(0))
+        }
+
+        fn write(&mut self, address: RegionAddress, _size: u8, value: u64) -> Result<()> {
+            self.set(address, value);
+            Ok(())
+        }
+    }
+
+    /// `op` followed by a PkgLength and `parts`.
+    fn pkg(op: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        let body = parts.concat();
+        // the length includes the PkgLength itself
+        let len = body.len() + 1;
+        let mut v = op.to_vec();
+        if len < 0x40 {
+            v.push(len as u8);
+        } else {
+            let len = len + 1;
+            assert!(len < 0x1000);
+            v.extend_from_slice(&[0x40 | (len & 0xf) as u8, (len >> 4) as u8]);
+        }
+        v.extend_from_slice(&body);
+        v
+    }
+
+    fn dword(n: u32) -> Vec<u8> {
+        let mut v = vec![0x0c];
+        v.extend_from_slice(&n.to_le_bytes());
+        v
+    }
+
+    fn name(name: &[u8], value: &[u8]) -> Vec<u8> {
+        [&[0x08], name, value].concat()
+    }
+
+    fn scope(name: &[u8], body: &[&[u8]]) -> Vec<u8> {
+        pkg(&[0x10], &[&[name], body].concat())
+    }
+
+    fn device(name: &[u8], body: &[&[u8]]) -> Vec<u8> {
+        pkg(&[0x5b, 0x82], &[&[name], body].concat())
+    }
+
+    fn method(name: &[u8], args: u8, body: &[&[u8]]) -> Vec<u8> {
+        pkg(&[0x14], &[&[name, &[args]], body].concat())
+    }
+
+    fn if_(predicate: &[u8], body: &[&[u8]]) -> Vec<u8> {
+        pkg(&[0xa0], &[&[predicate], body].concat())
+    }
+
+    fn package(count: u8, elements: &[&[u8]]) -> Vec<u8> {
+        pkg(&[0x12], &[&[&[count][..]], elements].concat())
+    }
+
+    fn load(table: Vec<u8>) -> (Interpreter, Mock) {
+        let mock = Mock::default();
+        let mut interpreter = Interpreter::new(Box::new(mock.clone()));
+        let table = unsafe { &*(table.as_ptr() as *const SdtHeader) };
+        interpreter.load_table(table).unwrap();
+        (interpreter, mock)
+    }
+
+    fn path(s: &str) -> Path {
+        Path::parse(s).unwrap()
+    }
+
+    /// Interrupt link devices whose `_STA` depends on a PIRQ route register.
+    fn link_devices() -> Vec<u8> {
+        // Method (IQST, 1) { If (0x80 & Arg0) { Return (0x09) } Return (0x0B) }
+        let mut aml = method(
+            b"IQST",
+            1,
+            &[
+                &if_(&[0x7b, 0x0a, 0x80, 0x68, 0x00], &[&[0xa4, 0x0a, 0x09]]),
+                &[0xa4, 0x0a, 0x0b],
+            ],
+        );
+        for (link, prq) in [b"LNKA", b"LNKB", b"LNKC", b"LNKD"].iter().zip(b"0123") {
+            let field = [b'P', b'R', b'Q', *prq];
+            // Method (_STA) { Return (IQST (PRQx)) }
+            let sta = method(b"_STA", 0, &[&[0xa4], b"IQST", &field]);
+            aml.extend(device(*link, &[&name(b"_HID", &dword(PNP0C0F)), &sta]));
+        }
+        aml
+    }
+
+    /// The `_PRT` of the i440fx host bridge in QEMU, which builds the table
+    /// in a loop.
+    fn i440fx_prt() -> Vec<u8> {
+        let links: [&[u8; 4]; 4] = [b"LNKD", b"LNKA", b"LNKB", b"LNKC"];
+        let mut choose = Vec::new();
+        for (i, link) in links.iter().enumerate() {
+            // If (Local3 == i) { Local4 = Package () { Zero, Zero, LNKx, Zero } }
+            let entry = package(4, &[&[0x00], &[0x00], *link, &[0x00]]);
+            choose.extend(if_(
+                &[0x93, 0x63, 0x0a, i as u8],
+                &[&[0x70], &entry, &[0x64]],
+            ));
+        }
+        let body = [
+            // Local2 = Local1 >> 2
+            &[0x7a, 0x61, 0x0a, 0x02, 0x62][..],
+            // Local3 = (Local1 + Local2) & 3
+            &[0x7b, 0x72, 0x61, 0x62, 0x00, 0x0a, 0x03, 0x63],
+            &choose,
+            // Local4 [Zero] = (Local2 << 16) | 0xFFFF
+            &[
+                0x70, 0x7d, 0x79, 0x62, 0x0a, 0x10, 0x00, 0x0b, 0xff, 0xff, 0x00, 0x88, 0x64, 0x00,
+                0x00,
+            ],
+            // Local4 [One] = Local1 & 3
+            &[0x70, 0x7b, 0x61, 0x0a, 0x03, 0x00, 0x88, 0x64, 0x01, 0x00],
+            // Local0 [Local1] = Local4
+            &[0x70, 0x64, 0x88, 0x60, 0x61, 0x00],
+            // Local1++
+            &[0x75, 0x61],
+        ]
+        .concat();
+        method(
+            b"_PRT",
+            0,
+            &[
+                // Local0 = Package (0x80) {}
+                &[0x70, 0x12, 0x02, 0x80, 0x60],
+                // Local1 = Zero
+                &[0x70, 0x00, 0x61],
+                // While (Local1 < 0x80)
+                &pkg(&[0xa2], &[&[0x95, 0x61, 0x0a, 0x80], &body]),
+                &[0xa4, 0x60],
+            ],
+        )
+    }
+
+    #[test_case]
+    fn test_i440fx() {
+        let pci0 = device(
+            b"PCI0",
+            &[
+                &name(b"_HID", &dword(PNP0A03)),
+                &name(b"_ADR", &[0x00]),
+                &i440fx_prt(),
+                &device(
+                    b"ISA_",
+                    &[
+                        &name(b"_ADR", &dword(0x0001_0000)),
+                        // OperationRegion (P40C, PCI_Config, 0x60, 0x04)
+                        &[
+                            0x5b, 0x80, b'P', b'4', b'0', b'C', 0x02, 0x0a, 0x60, 0x0a, 0x04,
+                        ],
+                    ],
+                ),
+            ],
+        );
+        // Field (PCI0.ISA.P40C, ByteAcc, NoLock, Preserve) { PRQ0, 8, ... }
+        let field = pkg(
+            &[0x5b, 0x81],
+            &[
+                &[0x2f, 0x03],
+                b"PCI0ISA_P40C",
+                &[0x01],
+                b"PRQ0\x08PRQ1\x08PRQ2\x08PRQ3\x08",
+            ],
+        );
+        let s5 = package(4, &[&[0x00], &[0x00], &[0x00], &[0x00]]);
+        let dsdt = [
+            scope(b"\\_SB_", &[&pci0, &field, &link_devices()]),
+            name(b"\\_S5_", &s5),
+            name(b"ONES", &[0xff]),
+        ]
+        .concat();
+        let (mut aml, mock) = load(make_table(b"DSDT", &dsdt));
+
+        let pci0 = path("\\_SB.PCI0");
+        assert_eq!(aml.hardware_id(&pci0), Ok(Some(String::from("PNP0A03"))));
+        assert_eq!(aml.status(&pci0), Ok(0x0f));
+        assert_eq!(aml.pci_root_buses(), [0]);
+        let routing = aml.pci_routing(&pci0).unwrap();
+        assert_eq!(routing.len(), 128);
+        let entry = |device, pin, link: &str| PrtEntry {
+            device,
+            pin,
+            source: Some(path(link)),
+            source_index: 0,
+        };
+        assert_eq!(routing[0], entry(0, 0, "\\_SB.LNKD"));
+        assert_eq!(routing[5], entry(1, 1, "\\_SB.LNKB"));
+        assert_eq!(routing[127], entry(31, 3, "\\_SB.LNKB"));
+
+        // PIRQA is disabled and PIRQB is routed to IRQ 11
+        let isa = pci::Address {
+            bus: 0,
+            device: 1,
+            function: 0,
+        };
+        mock.set(RegionAddress::PciConfig(isa, 0x60), 0x80);
+        mock.set(RegionAddress::PciConfig(isa, 0x61), 0x0b);
+        assert_eq!(aml.status(&path("\\_SB.LNKA")), Ok(0x09));
+        assert_eq!(aml.status(&path("\\_SB.LNKB")), Ok(0x0b));
+
+        let s5 = aml.evaluate(&path("\\_S5"), Vec::new()).unwrap();
+        assert_eq!(
+            s5.as_package().unwrap()[..2],
+            [Value::Integer(0), Value::Integer(0)]
+        );
+        // the DSDT revision is 1, so integers are 32 bits wide
+        assert_eq!(
+            aml.evaluate(&path("\\ONES"), Vec::new()),
+            Ok(Value::Integer(0xffff_ffff))
+        );
+        assert_eq!(
+            aml.evaluate(&path("\\_SB.NONE"), Vec::new()),
+            Err(Error::UndefinedName)
+        );
+    }
+
+    #[test_case]
+    fn test_q35() {
+        let route = |device: u32, pin: u8, source: &[u8], index: u8| {
+            package(
+                4,
+                &[
+                    &dword(device << 16 | 0xffff),
+                    &[0x0a, pin],
+                    source,
+                    &[0x0a, index],
+                ],
+            )
+        };
+        let prtp = package(2, &[&route(0, 0, b"LNKE", 0), &route(1, 1, b"LNKF", 0)]);
+        let prta = package(
+            2,
+            &[&route(0, 0, &[0x00], 0x10), &route(1, 1, &[0x00], 0x11)],
+        );
+        let pci0 = device(
+            b"PCI0",
+            &[
+                &name(b"_HID", &dword(PNP0A08)),
+                &name(b"_CID", &dword(PNP0A03)),
+                &name(b"PRTP", &prtp),
+                &name(b"PRTA", &prta),
+                // Method (_PRT) { If (PICF) { Return (PRTA) } Return (PRTP) }
+                &method(
+                    b"_PRT",
+                    0,
+                    &[&if_(b"PICF", &[&[0xa4], b"PRTA"]), &[0xa4], b"PRTP"],
+                ),
+            ],
+        );
+        let links = [
+            device(b"LNKE", &[&name(b"_HID", &dword(PNP0C0F))]),
+            device(b"LNKF", &[&name(b"_HID", &dword(PNP0C0F))]),
+        ];
+        let dsdt = [
+            name(b"PICF", &[0x00]),
+            // Method (_PIC, 1) { PICF = Arg0 }
+            method(b"_PIC", 1, &[&[0x70, 0x68], b"PICF"]),
+            scope(b"\\_SB_", &[&pci0, &links[0], &links[1]]),
+            // OperationRegion (DBG, SystemIO, 0x0402, One)
+            vec![
+                0x5b, 0x80, b'D', b'B', b'G', b'_', 0x01, 0x0b, 0x02, 0x04, 0x01,
+            ],
+            // Field (DBG, ByteAcc, NoLock, Preserve) { DBGB, 8 }
+            pkg(&[0x5b, 0x81], &[b"DBG_", &[0x01], b"DBGB\x08"]),
+            // Method (DBUG, 1) { DBGB = Arg0 }
+            method(b"DBUG", 1, &[&[0x70, 0x68], b"DBGB"]),
+            // Method (TEMP) { Name (VAL, 0x05) Return (VAL) }
+            method(
+                b"TEMP",
+                0,
+                &[&name(b"VAL_", &[0x0a, 0x05]), &[0xa4], b"VAL_"],
+            ),
+        ]
+        .concat();
+        let mut table = make_table(b"DSDT", &dsdt);
+        // revision 2 for 64-bit integers; the checksum is not checked
+        table[8] = 2;
+        let (mut aml, mock) = load(table);
+
+        let pci0 = path("\\_SB.PCI0");
+        assert_eq!(aml.hardware_id(&pci0), Ok(Some(String::from("PNP0A08"))));
+        assert_eq!(aml.pci_root_buses(), [0]);
+        let routing = aml.pci_routing(&pci0).unwrap();
+        assert_eq!(routing[1].device, 1);
+        assert_eq!(routing[1].source, Some(path("\\_SB.LNKF")));
+
+        // the routing switches to the I/O APIC
+        aml.evaluate(&path("\\_PIC"), vec![Value::Integer(1)])
+            .unwrap();
+        let routing = aml.pci_routing(&pci0).unwrap();
+        assert_eq!(
+            (routing[1].source.clone(), routing[1].source_index),
+            (None, 0x11)
+        );
+
+        aml.evaluate(&path("\\DBUG"), vec![Value::Integer(0x41)])
+            .unwrap();
+        assert_eq!(mock.get(RegionAddress::Io(0x402)), Some(0x41));
+
+        assert_eq!(
+            aml.evaluate(&path("\\TEMP"), Vec::new()),
+            Ok(Value::Integer(5))
+        );
+        assert!(aml.namespace().get(&path("\\TEMP.VAL")).is_none());
+        let devices: Vec<_> = aml
+            .namespace()
+            .devices()
+            .map(|p| alloc::format!("{}", p))
+            .collect();
+        assert_eq!(devices, ["\\_SB_.LNKE", "\\_SB_.LNKF", "\\_SB_.PCI0"]);
+    }
+
+    #[test_case]
+    fn test_pci_root_buses() {
+        let dsdt = scope(
+            b"\\_SB_",
+            &[
+                &device(b"PCI0", &[&name(b"_HID", &dword(PNP0A08))]),
+                // an extra root bus, as QEMU's pxb-pcie describes it
+                &device(
+                    b"PC10",
+                    &[
+                        &name(b"_HID", b"\x0dQEMU0001\x00"),
+                        &name(b"_CID", &dword(PNP0A03)),
+                        &name(b"_BBN", &[0x0a, 0x10]),
+                    ],
+                ),
+                &device(
+                    b"PC20",
+                    &[
+                        &name(b"_HID", &dword(PNP0A08)),
+                        &name(b"_BBN", &[0x0a, 0x20]),
+                        &method(b"_STA", 0, &[&[0xa4, 0x00]]),
+                    ],
+                ),
+                &device(b"LNKA", &[&name(b"_HID", &dword(PNP0C0F))]),
+            ],
+        );
+        let (mut aml, _) = load(make_table(b"DSDT", &dsdt));
+        assert_eq!(aml.pci_root_buses(), [0, 0x10]);
+    }
+
+    /// The DSDT of a Firecracker microVM, dumped from
+    /// `/sys/firmware/acpi/tables/DSDT` of a guest. It has one PCI Express
+    /// host bridge, whose `_PRT` gives every slot GSI 0, and no `\_S5`.
+    #[test_case]
+    fn test_firecracker_dsdt() {
+        let dsdt = include_bytes!("aml/testdata/firecracker-dsdt.aml");
+        let (mut aml, _) = load(dsdt.to_vec());
+
+        let pc00 = path("\\_SB.PC00");
+        assert_eq!(aml.hardware_id(&pc00), Ok(Some(String::from("PNP0A08"))));
+        assert_eq!(aml.status(&pc00), Ok(0x0f));
+        assert_eq!(aml.pci_root_buses(), [0]);
+        let routing = aml.pci_routing(&pc00).unwrap();
+        assert_eq!(routing.len(), 32);
+        assert_eq!(
+            routing[31],
+            PrtEntry {
+                device: 31,
+                pin: 0,
+                source: None,
+                source_index: 0,
+            }
+        );
+        assert_eq!(
+            aml.hardware_id(&path("\\_SB.COM1")),
+            Ok(Some(String::from("PNP0501")))
+        );
+        // the slots have no _HID
+        assert_eq!(aml.hardware_id(&path("\\_SB.PC00.S001")), Ok(None));
+        assert_eq!(
+            aml.evaluate(&path("\\_S5"), Vec::new()),
+            Err(Error::UndefinedName)
+        );
+    }
+
+    #[test_case]
+    fn test_skip_broken_object() {
+        let dsdt = [
+            // a BankField, which is not supported
+            device(b"BAD0", &[&name(b"_UID", &[0x01]), &[0x5b, 0x87, 0x01]]),
+            device(b"GOOD", &[&name(b"_UID", &[0x0a, 0x02])]),
+        ]
+        .concat();
+        let (mut aml, _) = load(make_table(b"DSDT", &dsdt));
+        assert_eq!(aml.evaluate_or(&path("\\BAD0._UID"), 0), Ok(1));
+        assert_eq!(aml.evaluate_or(&path("\\GOOD._UID"), 0), Ok(2));
+        assert_eq!(eisa_id(PNP0C0F), "PNP0C0F");
+    }
+}
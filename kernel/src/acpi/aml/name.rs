@@ -0,0 +1,172 @@
+//! Names in the ACPI namespace.
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{Error, Result};
+
+pub type NameSeg = [u8; 4];
+
+/// An absolute path in the namespace, such as `\_SB_.PCI0`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Path(Vec<NameSeg>);
+
+pub fn is_lead_name_char(c: u8) -> bool {
+    c == b'_' || c.is_ascii_uppercase()
+}
+
+fn is_name_char(c: u8) -> bool {
+    is_lead_name_char(c) || c.is_ascii_digit()
+}
+
+/// Checks a segment read from AML.
+pub fn name_seg(bytes: &[u8]) -> Result<NameSeg> {
+    if bytes.len() != 4 || !is_lead_name_char(bytes[0]) || !bytes.iter().all(|&c| is_name_char(c)) {
+        return Err(Error::InvalidName);
+    }
+    let mut seg = [0; 4];
+    seg.copy_from_slice(bytes);
+    Ok(seg)
+}
+
+impl Path {
+    pub fn root() -> Self {
+        Path(Vec::new())
+    }
+
+    /// Parses a path such as `\_SB.PCI0._PRT`. Segments shorter than four
+    /// characters are padded with `_`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.strip_prefix('\\').ok_or(Error::InvalidName)?;
+        let mut segments = Vec::new();
+        if !s.is_empty() {
+            for part in s.split('.') {
+                if part.is_empty() || part.len() > 4 {
+                    return Err(Error::InvalidName);
+                }
+                let mut seg = *b"____";
+                seg[..part.len()].copy_from_slice(part.as_bytes());
+                segments.push(name_seg(&seg)?);
+            }
+        }
+        Ok(Path(segments))
+    }
+
+    pub fn segments(&self) -> &[NameSeg] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn parent(&self) -> Option<Path> {
+        let (_, parent) = self.0.split_last()?;
+        Some(Path(parent.to_vec()))
+    }
+
+    pub fn join(&self, seg: NameSeg) -> Path {
+        let mut path = self.clone();
+        path.0.push(seg);
+        path
+    }
+
+    /// The last segment, if this is not the root.
+    pub fn name(&self) -> Option<NameSeg> {
+        self.0.last().copied()
+    }
+
+    pub fn is_child_of(&self, parent: &Path) -> bool {
+        self.0.len() == parent.0.len() + 1 && self.0.starts_with(&parent.0)
+    }
+
+    pub fn starts_with(&self, ancestor: &Path) -> bool {
+        self.0.starts_with(&ancestor.0)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\\")?;
+        for (i, seg) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            // segments only contain ASCII
+            write!(f, "{}", core::str::from_utf8(seg).unwrap_or("????"))?;
+        }
+        Ok(())
+    }
+}
+
+/// A NameString as it appears in AML. Unless it starts with `\`, it is
+/// relative to the current scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name {
+    pub root: bool,
+    /// The number of `^` prefixes.
+    pub parents: usize,
+    pub segments: Vec<NameSeg>,
+}
+
+impl Name {
+    /// Parses a name such as `^PCI0.LNKA`, padding segments as
+    /// `Path::parse` does.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (root, s) = match s.strip_prefix('\\') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let rest = s.trim_start_matches('^');
+        let parents = s.len() - rest.len();
+        if root && parents > 0 {
+            return Err(Error::InvalidName);
+        }
+        let path = Path::parse(&alloc::format!("\\{}", rest))?;
+        Ok(Name {
+            root,
+            parents,
+            segments: path.0,
+        })
+    }
+
+    pub fn is_null(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.is_empty()
+    }
+
+    /// Names of a single segment without prefixes are also looked up in the
+    /// parent scopes.
+    pub fn searches_parents(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.len() == 1
+    }
+
+    pub fn resolve(&self, scope: &Path) -> Result<Path> {
+        let mut path = if self.root {
+            Path::root()
+        } else {
+            scope.clone()
+        };
+        for _ in 0..self.parents {
+            path = path.parent().ok_or(Error::InvalidName)?;
+        }
+        path.0.extend_from_slice(&self.segments);
+        Ok(path)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.root {
+            write!(f, "\\")?;
+        }
+        for _ in 0..self.parents {
+            write!(f, "^")?;
+        }
+        for (i, seg) in self.segments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", core::str::from_utf8(seg).unwrap_or("????"))?;
+        }
+        Ok(())
+    }
+}
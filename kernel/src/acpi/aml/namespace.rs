@@ -0,0 +1,171 @@
+//! The tree of named objects the definition blocks declare.
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{Error, Name, Path, Result, Value};
+use crate::acpi::AddressSpace;
+
+#[derive(Clone, Debug)]
+pub struct Method {
+    pub args: u8,
+    pub serialized: bool,
+    pub(super) code: Arc<[u8]>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub space: AddressSpace,
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub region: Path,
+    pub bit_offset: u64,
+    pub bit_width: u64,
+    /// The size of each access in bytes.
+    pub access_size: u8,
+}
+
+#[derive(Clone, Debug)]
+pub enum Object {
+    /// A predefined scope, or one opened by `Scope` before its object.
+    Scope,
+    Value(Value),
+    Method(Method),
+    Device,
+    Processor,
+    PowerResource,
+    ThermalZone,
+    Region(Region),
+    Field(Field),
+    Mutex,
+    Event,
+    Alias(Path),
+}
+
+impl Object {
+    /// The code `ObjectType` returns.
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Object::Scope => 0,
+            Object::Value(v) => v.type_code(),
+            Object::Field(_) => 5,
+            Object::Device => 6,
+            Object::Event => 7,
+            Object::Method(_) => 8,
+            Object::Mutex => 9,
+            Object::Region(_) => 10,
+            Object::PowerResource => 11,
+            Object::Processor => 12,
+            Object::ThermalZone => 13,
+            Object::Alias(_) => 0,
+        }
+    }
+}
+
+/// Scopes every namespace starts with.
+const PREDEFINED_SCOPES: [&[u8; 4]; 5] = [b"_GPE", b"_PR_", b"_SB_", b"_SI_", b"_TZ_"];
+
+pub struct Namespace {
+    objects: BTreeMap<Path, Object>,
+}
+
+impl Namespace {
+    pub fn new() -> Self {
+        let mut objects = BTreeMap::new();
+        objects.insert(Path::root(), Object::Scope);
+        for seg in PREDEFINED_SCOPES {
+            objects.insert(Path::root().join(*seg), Object::Scope);
+        }
+        Namespace { objects }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Object> {
+        self.objects.get(path)
+    }
+
+    pub(super) fn get_mut(&mut self, path: &Path) -> Option<&mut Object> {
+        self.objects.get_mut(path)
+    }
+
+    /// Adds an object. A scope which was opened before the object was
+    /// defined is replaced.
+    pub(super) fn insert(&mut self, path: Path, object: Object) -> Result<()> {
+        let parent = path.parent().ok_or(Error::AlreadyDefined)?;
+        if !self.objects.contains_key(&parent) {
+            return Err(Error::UndefinedName);
+        }
+        match self.objects.get(&path) {
+            None | Some(Object::Scope) => {
+                self.objects.insert(path, object);
+                Ok(())
+            }
+            Some(_) if matches!(object, Object::Scope) => Ok(()),
+            Some(_) => Err(Error::AlreadyDefined),
+        }
+    }
+
+    /// Removes an object and its children.
+    pub(super) fn remove(&mut self, path: &Path) {
+        let descendants: Vec<Path> = self
+            .objects
+            .range(path.clone()..)
+            .take_while(|(p, _)| p.starts_with(path))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for p in descendants {
+            self.objects.remove(&p);
+        }
+    }
+
+    /// Finds the object `name` refers to from `scope`, following aliases.
+    pub fn lookup(&self, name: &Name, scope: &Path) -> Option<Path> {
+        let path = if name.searches_parents() {
+            let mut scope = scope.clone();
+            loop {
+                let path = scope.join(name.segments[0]);
+                if self.objects.contains_key(&path) {
+                    break path;
+                }
+                scope = scope.parent()?;
+            }
+        } else {
+            let path = name.resolve(scope).ok()?;
+            if !self.objects.contains_key(&path) {
+                return None;
+            }
+            path
+        };
+        match self.objects.get(&path) {
+            Some(Object::Alias(target)) => Some(target.clone()),
+            _ => Some(path),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Object)> {
+        self.objects.iter()
+    }
+
+    pub fn children<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Object)> {
+        self.objects
+            .range(path.clone()..)
+            .take_while(move |(p, _)| p.starts_with(path))
+            .filter(move |(p, _)| p.is_child_of(path))
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &Path> {
+        self.objects
+            .iter()
+            .filter(|(_, o)| matches!(o, Object::Device))
+            .map(|(p, _)| p)
+    }
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1055 @@
+//! Parsing and executing AML byte code.
+//!
+//! Definition blocks and method bodies are executed directly from the byte
+//! code; nothing is parsed ahead of time except the extent of each object.
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem;
+
+use super::name::{is_lead_name_char, name_seg};
+use super::namespace::{Field, Method, Object, Region};
+use super::{Error, Interpreter, Name, Path, RegionAddress, Result, Value};
+use crate::acpi::{u16_at, u32_at, u64_at, AddressSpace};
+use crate::{debug, pci, warn};
+
+const ZERO_OP: u16 = 0x00;
+const ONE_OP: u16 = 0x01;
+const ALIAS_OP: u16 = 0x06;
+const NAME_OP: u16 = 0x08;
+const BYTE_PREFIX: u16 = 0x0a;
+const WORD_PREFIX: u16 = 0x0b;
+const DWORD_PREFIX: u16 = 0x0c;
+const STRING_PREFIX: u16 = 0x0d;
+const QWORD_PREFIX: u16 = 0x0e;
+const SCOPE_OP: u16 = 0x10;
+const BUFFER_OP: u16 = 0x11;
+const PACKAGE_OP: u16 = 0x12;
+const VAR_PACKAGE_OP: u16 = 0x13;
+const METHOD_OP: u16 = 0x14;
+const EXTERNAL_OP: u16 = 0x15;
+const LOCAL0_OP: u16 = 0x60;
+const LOCAL7_OP: u16 = 0x67;
+const ARG0_OP: u16 = 0x68;
+const ARG6_OP: u16 = 0x6e;
+const STORE_OP: u16 = 0x70;
+const ADD_OP: u16 = 0x72;
+const CONCAT_OP: u16 = 0x73;
+const SUBTRACT_OP: u16 = 0x74;
+const INCREMENT_OP: u16 = 0x75;
+const DECREMENT_OP: u16 = 0x76;
+const MULTIPLY_OP: u16 = 0x77;
+const DIVIDE_OP: u16 = 0x78;
+const SHIFT_LEFT_OP: u16 = 0x79;
+const SHIFT_RIGHT_OP: u16 = 0x7a;
+const AND_OP: u16 = 0x7b;
+const NAND_OP: u16 = 0x7c;
+const OR_OP: u16 = 0x7d;
+const NOR_OP: u16 = 0x7e;
+const XOR_OP: u16 = 0x7f;
+const NOT_OP: u16 = 0x80;
+const FIND_SET_LEFT_BIT_OP: u16 = 0x81;
+const FIND_SET_RIGHT_BIT_OP: u16 = 0x82;
+const DEREF_OF_OP: u16 = 0x83;
+const MOD_OP: u16 = 0x85;
+const NOTIFY_OP: u16 = 0x86;
+const SIZE_OF_OP: u16 = 0x87;
+const INDEX_OP: u16 = 0x88;
+const OBJECT_TYPE_OP: u16 = 0x8e;
+const LAND_OP: u16 = 0x90;
+const LOR_OP: u16 = 0x91;
+const LNOT_OP: u16 = 0x92;
+const LEQUAL_OP: u16 = 0x93;
+const LGREATER_OP: u16 = 0x94;
+const LLESS_OP: u16 = 0x95;
+const TO_INTEGER_OP: u16 = 0x99;
+const CONTINUE_OP: u16 = 0x9f;
+const IF_OP: u16 = 0xa0;
+const ELSE_OP: u16 = 0xa1;
+const WHILE_OP: u16 = 0xa2;
+const NOOP_OP: u16 = 0xa3;
+const RETURN_OP: u16 = 0xa4;
+const BREAK_OP: u16 = 0xa5;
+const BREAKPOINT_OP: u16 = 0xcc;
+const ONES_OP: u16 = 0xff;
+
+const EXT_OP_PREFIX: u8 = 0x5b;
+const MUTEX_OP: u16 = 0x5b01;
+const EVENT_OP: u16 = 0x5b02;
+const COND_REF_OF_OP: u16 = 0x5b12;
+const STALL_OP: u16 = 0x5b21;
+const SLEEP_OP: u16 = 0x5b22;
+const ACQUIRE_OP: u16 = 0x5b23;
+const SIGNAL_OP: u16 = 0x5b24;
+const WAIT_OP: u16 = 0x5b25;
+const RESET_OP: u16 = 0x5b26;
+const RELEASE_OP: u16 = 0x5b27;
+const REVISION_OP: u16 = 0x5b30;
+const DEBUG_OP: u16 = 0x5b31;
+const FATAL_OP: u16 = 0x5b32;
+const OP_REGION_OP: u16 = 0x5b80;
+const FIELD_OP: u16 = 0x5b81;
+const DEVICE_OP: u16 = 0x5b82;
+const PROCESSOR_OP: u16 = 0x5b83;
+const POWER_RES_OP: u16 = 0x5b84;
+const THERMAL_ZONE_OP: u16 = 0x5b85;
+
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX: u8 = b'^';
+const DUAL_NAME_PREFIX: u8 = 0x2e;
+const MULTI_NAME_PREFIX: u8 = 0x2f;
+
+// field list elements
+const RESERVED_FIELD: u8 = 0x00;
+const ACCESS_FIELD: u8 = 0x01;
+const EXTENDED_ACCESS_FIELD: u8 = 0x03;
+
+/// What `Revision` returns.
+const INTERPRETER_REVISION: u64 = 1;
+const MAX_CALL_DEPTH: usize = 32;
+const MAX_LOOP_ITERATIONS: usize = 0x10000;
+
+fn is_name_start(c: u8) -> bool {
+    is_lead_name_char(c)
+        || c == ROOT_CHAR
+        || c == PARENT_PREFIX
+        || c == DUAL_NAME_PREFIX
+        || c == MULTI_NAME_PREFIX
+}
+
+/// The access size in bytes for the AccessType of a field.
+fn access_size(access_type: u8) -> u8 {
+    match access_type & 0xf {
+        2 => 2,
+        3 => 4,
+        4 => 8,
+        // AnyAcc, ByteAcc and BufferAcc
+        _ => 1,
+    }
+}
+
+fn mask(bits: u64) -> u128 {
+    (1u128 << bits) - 1
+}
+
+#[derive(Clone)]
+struct Reader<'c> {
+    code: &'c [u8],
+    pos: usize,
+    /// The end of the object being read.
+    end: usize,
+}
+
+impl<'c> Reader<'c> {
+    fn new(code: &'c [u8]) -> Self {
+        Reader {
+            code,
+            pos: 0,
+            end: code.len(),
+        }
+    }
+
+    /// A reader for the rest of the current object, up to `end`.
+    fn sub(&self, end: usize) -> Self {
+        Reader {
+            code: self.code,
+            pos: self.pos,
+            end,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    fn peek(&self) -> Result<u8> {
+        if self.at_end() {
+            Err(Error::UnexpectedEnd)
+        } else {
+            Ok(self.code[self.pos])
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'c [u8]> {
+        if self.pos + len > self.end {
+            return Err(Error::UnexpectedEnd);
+        }
+        let bytes = &self.code[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn opcode(&mut self) -> Result<u16> {
+        match self.byte()? {
+            EXT_OP_PREFIX => Ok(0x5b00 | self.byte()? as u16),
+            op => Ok(op as u16),
+        }
+    }
+
+    /// The value of a PkgLength.
+    fn pkg_length(&mut self) -> Result<usize> {
+        let lead = self.byte()?;
+        let count = (lead >> 6) as usize;
+        if count == 0 {
+            return Ok((lead & 0x3f) as usize);
+        }
+        let mut len = (lead & 0x0f) as usize;
+        for i in 0..count {
+            len |= (self.byte()? as usize) << (4 + 8 * i);
+        }
+        Ok(len)
+    }
+
+    /// Reads a PkgLength and returns where the object ends.
+    fn pkg_end(&mut self) -> Result<usize> {
+        let start = self.pos;
+        let end = start + self.pkg_length()?;
+        if end < self.pos || end > self.end {
+            return Err(Error::UnexpectedEnd);
+        }
+        Ok(end)
+    }
+
+    fn name_string(&mut self) -> Result<Name> {
+        let mut name = Name {
+            root: false,
+            parents: 0,
+            segments: Vec::new(),
+        };
+        if self.peek()? == ROOT_CHAR {
+            name.root = true;
+            self.pos += 1;
+        } else {
+            while self.peek()? == PARENT_PREFIX {
+                name.parents += 1;
+                self.pos += 1;
+            }
+        }
+        let count = match self.peek()? {
+            0 => {
+                self.pos += 1;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.pos += 1;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.pos += 1;
+                self.byte()? as usize
+            }
+            _ => 1,
+        };
+        for _ in 0..count {
+            name.segments.push(name_seg(self.bytes(4)?)?);
+        }
+        Ok(name)
+    }
+}
+
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+/// Where a result is stored.
+enum Target {
+    Null,
+    Debug,
+    Local(usize),
+    Arg(usize),
+    Name(Path),
+    /// An element of a package or a byte of a buffer.
+    Index(Box<Target>, usize),
+}
+
+struct Frame {
+    scope: Path,
+    args: Vec<Value>,
+    locals: [Option<Value>; 8],
+    /// Objects a method created, which go away when it returns. `None` while
+    /// loading a table.
+    created: Option<Vec<Path>>,
+}
+
+impl Frame {
+    fn new(scope: Path, args: Vec<Value>, method: bool) -> Self {
+        Frame {
+            scope,
+            args,
+            locals: Default::default(),
+            created: if method { Some(Vec::new()) } else { None },
+        }
+    }
+}
+
+fn element_of(source: &Value, index: usize) -> Result<Value> {
+    match source {
+        Value::Package(elements) => elements.get(index).cloned(),
+        Value::Buffer(bytes) => bytes.get(index).map(|&b| Value::Integer(b as u64)),
+        Value::String(s) => s.as_bytes().get(index).map(|&b| Value::Integer(b as u64)),
+        _ => return Err(Error::TypeMismatch),
+    }
+    .ok_or(Error::IndexOutOfRange)
+}
+
+fn set_element(container: &mut Value, index: usize, value: Value) -> Result<()> {
+    match container {
+        Value::Package(elements) => {
+            *elements.get_mut(index).ok_or(Error::IndexOutOfRange)? = value;
+        }
+        Value::Buffer(bytes) => {
+            *bytes.get_mut(index).ok_or(Error::IndexOutOfRange)? = value.as_integer()? as u8;
+        }
+        _ => return Err(Error::TypeMismatch),
+    }
+    Ok(())
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering> {
+    match (a, b) {
+        (Value::Integer(a), b) => Ok(a.cmp(&b.as_integer()?)),
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        (Value::Buffer(a), Value::Buffer(b)) => Ok(a.cmp(b)),
+        _ => Err(Error::TypeMismatch),
+    }
+}
+
+impl Interpreter {
+    /// Executes the body of a definition block.
+    pub(super) fn load(&mut self, code: &[u8]) -> Result<()> {
+        let mut frame = Frame::new(Path::root(), Vec::new(), false);
+        self.term_list(&mut Reader::new(code), &mut frame)?;
+        Ok(())
+    }
+
+    pub(super) fn call(&mut self, path: &Path, method: &Method, args: Vec<Value>) -> Result<Value> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        let code = method.code.clone();
+        let mut frame = Frame::new(path.clone(), args, true);
+        self.depth += 1;
+        let result = self.term_list(&mut Reader::new(&code), &mut frame);
+        self.depth -= 1;
+        for path in frame.created.iter().flatten().rev() {
+            self.namespace.remove(path);
+        }
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Integer(0)),
+        }
+    }
+
+    fn term_list(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Flow> {
+        while !r.at_end() {
+            match self.term(r, frame)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn define(&mut self, frame: &mut Frame, path: Path, object: Object) -> Result<()> {
+        self.namespace.insert(path.clone(), object)?;
+        if let Some(created) = frame.created.as_mut() {
+            created.push(path);
+        }
+        Ok(())
+    }
+
+    /// Executes the term list of a scope, a device or a similar object.
+    ///
+    /// While loading a table, an error skips the rest of the object instead
+    /// of the rest of the table.
+    fn object_body(
+        &mut self,
+        r: &mut Reader,
+        end: usize,
+        frame: &mut Frame,
+        path: Path,
+    ) -> Result<Flow> {
+        let mut body = r.sub(end);
+        r.pos = end;
+        let scope = mem::replace(&mut frame.scope, path);
+        let result = self.term_list(&mut body, frame);
+        let path = mem::replace(&mut frame.scope, scope);
+        match result {
+            Err(e) if frame.created.is_none() => {
+                warn!("AML: {:?} in {}; skipped the rest of it", e, path);
+                Ok(Flow::Normal)
+            }
+            result => result,
+        }
+    }
+
+    fn term(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Flow> {
+        if is_name_start(r.peek()?) {
+            self.term_arg(r, frame)?;
+            return Ok(Flow::Normal);
+        }
+        let op = r.opcode()?;
+        match op {
+            SCOPE_OP => {
+                let end = r.pkg_end()?;
+                let name = r.name_string()?;
+                let path = match self.namespace.lookup(&name, &frame.scope) {
+                    Some(path) => path,
+                    None => {
+                        let path = name.resolve(&frame.scope)?;
+                        self.define(frame, path.clone(), Object::Scope)?;
+                        path
+                    }
+                };
+                self.object_body(r, end, frame, path)
+            }
+            NAME_OP => {
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                let value = self.term_arg(r, frame)?;
+                self.define(frame, path, Object::Value(value))?;
+                Ok(Flow::Normal)
+            }
+            ALIAS_OP => {
+                let source = r.name_string()?;
+                let target = self
+                    .namespace
+                    .lookup(&source, &frame.scope)
+                    .ok_or(Error::UndefinedName)?;
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                self.define(frame, path, Object::Alias(target))?;
+                Ok(Flow::Normal)
+            }
+            METHOD_OP => {
+                let end = r.pkg_end()?;
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                let flags = r.byte()?;
+                let code: Arc<[u8]> = Arc::from(r.bytes(end - r.pos)?);
+                let method = Method {
+                    args: flags & 0x7,
+                    serialized: flags & 0x8 != 0,
+                    code,
+                };
+                self.define(frame, path, Object::Method(method))?;
+                Ok(Flow::Normal)
+            }
+            EXTERNAL_OP => {
+                r.name_string()?;
+                // the object type and the number of arguments
+                r.bytes(2)?;
+                Ok(Flow::Normal)
+            }
+            DEVICE_OP | PROCESSOR_OP | POWER_RES_OP | THERMAL_ZONE_OP => {
+                let end = r.pkg_end()?;
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                let object = match op {
+                    DEVICE_OP => Object::Device,
+                    PROCESSOR_OP => {
+                        // the processor ID and the P_BLK address and length
+                        r.bytes(6)?;
+                        Object::Processor
+                    }
+                    POWER_RES_OP => {
+                        // the system level and the resource order
+                        r.bytes(3)?;
+                        Object::PowerResource
+                    }
+                    _ => Object::ThermalZone,
+                };
+                self.define(frame, path.clone(), object)?;
+                self.object_body(r, end, frame, path)
+            }
+            MUTEX_OP | EVENT_OP => {
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                let object = if op == MUTEX_OP {
+                    // the sync level
+                    r.byte()?;
+                    Object::Mutex
+                } else {
+                    Object::Event
+                };
+                self.define(frame, path, object)?;
+                Ok(Flow::Normal)
+            }
+            OP_REGION_OP => {
+                let path = r.name_string()?.resolve(&frame.scope)?;
+                let space = match r.byte()? {
+                    0 => AddressSpace::SystemMemory,
+                    1 => AddressSpace::SystemIo,
+                    2 => AddressSpace::PciConfig,
+                    n => AddressSpace::Other(n),
+                };
+                let offset = self.integer(r, frame)?;
+                let length = self.integer(r, frame)?;
+                let region = Region {
+                    space,
+                    offset,
+                    length,
+                };
+                self.define(frame, path, Object::Region(region))?;
+                Ok(Flow::Normal)
+            }
+            FIELD_OP => {
+                let end = r.pkg_end()?;
+                let mut list = r.sub(end);
+                r.pos = end;
+                self.def_field(&mut list, frame)?;
+                Ok(Flow::Normal)
+            }
+            IF_OP => self.def_if(r, frame),
+            ELSE_OP => {
+                // an Else without an If
+                r.pos = r.pkg_end()?;
+                Ok(Flow::Normal)
+            }
+            WHILE_OP => self.def_while(r, frame),
+            RETURN_OP => Ok(Flow::Return(self.term_arg(r, frame)?)),
+            BREAK_OP => Ok(Flow::Break),
+            CONTINUE_OP => Ok(Flow::Continue),
+            NOOP_OP | BREAKPOINT_OP => Ok(Flow::Normal),
+            NOTIFY_OP => {
+                // nobody listens to notifications
+                self.super_name(r, frame)?;
+                self.term_arg(r, frame)?;
+                Ok(Flow::Normal)
+            }
+            SLEEP_OP | STALL_OP => {
+                self.term_arg(r, frame)?;
+                Ok(Flow::Normal)
+            }
+            SIGNAL_OP | RESET_OP | RELEASE_OP => {
+                self.super_name(r, frame)?;
+                Ok(Flow::Normal)
+            }
+            FATAL_OP => {
+                // the type and the code
+                r.bytes(5)?;
+                self.term_arg(r, frame)?;
+                Err(Error::Fatal)
+            }
+            op => {
+                self.operation(op, r, frame)?;
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    fn def_field(&mut self, list: &mut Reader, frame: &mut Frame) -> Result<()> {
+        let region = list.name_string()?;
+        let region = self
+            .namespace
+            .lookup(&region, &frame.scope)
+            .ok_or(Error::UndefinedName)?;
+        let mut access = list.byte()?;
+        let mut bit_offset = 0;
+        while !list.at_end() {
+            match list.peek()? {
+                RESERVED_FIELD => {
+                    list.pos += 1;
+                    bit_offset += list.pkg_length()? as u64;
+                }
+                ACCESS_FIELD => {
+                    list.pos += 1;
+                    access = list.byte()?;
+                    // the access attribute
+                    list.byte()?;
+                }
+                EXTENDED_ACCESS_FIELD => {
+                    list.pos += 1;
+                    access = list.byte()?;
+                    // the access attribute and length
+                    list.bytes(2)?;
+                }
+                _ => {
+                    let seg = name_seg(list.bytes(4)?)?;
+                    let bit_width = list.pkg_length()? as u64;
+                    let field = Field {
+                        region: region.clone(),
+                        bit_offset,
+                        bit_width,
+                        access_size: access_size(access),
+                    };
+                    self.define(frame, frame.scope.join(seg), Object::Field(field))?;
+                    bit_offset += bit_width;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn def_if(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Flow> {
+        let end = r.pkg_end()?;
+        let mut body = r.sub(end);
+        let predicate = self.integer(&mut body, frame)? != 0;
+        r.pos = end;
+        let else_end = if !r.at_end() && r.peek()? == ELSE_OP as u8 {
+            r.pos += 1;
+            Some(r.pkg_end()?)
+        } else {
+            None
+        };
+        let flow = if predicate {
+            self.term_list(&mut body, frame)?
+        } else if let Some(else_end) = else_end {
+            self.term_list(&mut r.sub(else_end), frame)?
+        } else {
+            Flow::Normal
+        };
+        if let Some(else_end) = else_end {
+            r.pos = else_end;
+        }
+        Ok(flow)
+    }
+
+    fn def_while(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Flow> {
+        let end = r.pkg_end()?;
+        for _ in 0..MAX_LOOP_ITERATIONS {
+            let mut body = r.sub(end);
+            if self.integer(&mut body, frame)? == 0 {
+                r.pos = end;
+                return Ok(Flow::Normal);
+            }
+            match self.term_list(&mut body, frame)? {
+                Flow::Break => {
+                    r.pos = end;
+                    return Ok(Flow::Normal);
+                }
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => {}
+            }
+        }
+        Err(Error::LoopLimit)
+    }
+
+    fn integer(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<u64> {
+        self.term_arg(r, frame)?.as_integer()
+    }
+
+    fn logical(&self, b: bool) -> Value {
+        Value::Integer(if b { self.ones } else { 0 })
+    }
+
+    fn term_arg(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Value> {
+        if !is_name_start(r.peek()?) {
+            let op = r.opcode()?;
+            return self.operation(op, r, frame);
+        }
+        let name = r.name_string()?;
+        let path = self
+            .namespace
+            .lookup(&name, &frame.scope)
+            .ok_or(Error::UndefinedName)?;
+        match self.namespace.get(&path).cloned() {
+            Some(Object::Method(method)) => {
+                let mut args = Vec::new();
+                for _ in 0..method.args {
+                    args.push(self.term_arg(r, frame)?);
+                }
+                self.call(&path, &method, args)
+            }
+            Some(Object::Value(value)) => Ok(value),
+            Some(Object::Field(field)) => Ok(Value::Integer(self.read_field(&field)?)),
+            Some(_) => Ok(Value::Reference(path)),
+            None => Err(Error::UndefinedName),
+        }
+    }
+
+    /// An element of a package. Names in packages refer to objects rather
+    /// than evaluate them.
+    fn package_element(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Value> {
+        if !is_name_start(r.peek()?) {
+            return self.term_arg(r, frame);
+        }
+        // the object may be defined later in the table, so a name which is
+        // not found yet is kept as it is written
+        let name = r.name_string()?;
+        Ok(match self.namespace.lookup(&name, &frame.scope) {
+            Some(path) => Value::Reference(path),
+            None => Value::String(format!("{}", name)),
+        })
+    }
+
+    fn operation(&mut self, op: u16, r: &mut Reader, frame: &mut Frame) -> Result<Value> {
+        match op {
+            ZERO_OP => Ok(Value::Integer(0)),
+            ONE_OP => Ok(Value::Integer(1)),
+            ONES_OP => Ok(Value::Integer(self.ones)),
+            BYTE_PREFIX => Ok(Value::Integer(r.byte()? as u64)),
+            WORD_PREFIX => Ok(Value::Integer(u16_at(r.bytes(2)?, 0) as u64)),
+            DWORD_PREFIX => Ok(Value::Integer(u32_at(r.bytes(4)?, 0) as u64)),
+            QWORD_PREFIX => Ok(Value::Integer(u64_at(r.bytes(8)?, 0))),
+            STRING_PREFIX => {
+                let start = r.pos;
+                while r.byte()? != 0 {}
+                let s = core::str::from_utf8(&r.code[start..r.pos - 1])
+                    .map_err(|_| Error::TypeMismatch)?;
+                Ok(Value::String(String::from(s)))
+            }
+            REVISION_OP => Ok(Value::Integer(INTERPRETER_REVISION)),
+            BUFFER_OP => {
+                let end = r.pkg_end()?;
+                let mut body = r.sub(end);
+                let size = self.integer(&mut body, frame)? as usize;
+                let mut bytes = body.bytes(end - body.pos)?.to_vec();
+                r.pos = end;
+                // the initializer may be longer than the size
+                if bytes.len() < size {
+                    bytes.resize(size, 0);
+                }
+                Ok(Value::Buffer(bytes))
+            }
+            PACKAGE_OP | VAR_PACKAGE_OP => {
+                let end = r.pkg_end()?;
+                let mut body = r.sub(end);
+                let count = if op == PACKAGE_OP {
+                    body.byte()? as usize
+                } else {
+                    self.integer(&mut body, frame)? as usize
+                };
+                let mut elements = Vec::new();
+                while !body.at_end() {
+                    elements.push(self.package_element(&mut body, frame)?);
+                }
+                r.pos = end;
+                // elements without an initializer are uninitialized, which
+                // zero stands in for
+                if elements.len() < count {
+                    elements.resize(count, Value::Integer(0));
+                }
+                Ok(Value::Package(elements))
+            }
+            LOCAL0_OP..=LOCAL7_OP => self.read(&Target::Local((op - LOCAL0_OP) as usize), frame),
+            ARG0_OP..=ARG6_OP => self.read(&Target::Arg((op - ARG0_OP) as usize), frame),
+            STORE_OP => {
+                let value = self.term_arg(r, frame)?;
+                self.store_result(r, frame, value)
+            }
+            ADD_OP | SUBTRACT_OP | MULTIPLY_OP | SHIFT_LEFT_OP | SHIFT_RIGHT_OP | AND_OP
+            | NAND_OP | OR_OP | NOR_OP | XOR_OP | MOD_OP => {
+                let a = self.integer(r, frame)?;
+                let b = self.integer(r, frame)?;
+                let value = match op {
+                    ADD_OP => a.wrapping_add(b),
+                    SUBTRACT_OP => a.wrapping_sub(b),
+                    MULTIPLY_OP => a.wrapping_mul(b),
+                    SHIFT_LEFT_OP => a.checked_shl(b as u32).unwrap_or(0),
+                    SHIFT_RIGHT_OP => a.checked_shr(b as u32).unwrap_or(0),
+                    AND_OP => a & b,
+                    NAND_OP => !(a & b),
+                    OR_OP => a | b,
+                    NOR_OP => !(a | b),
+                    XOR_OP => a ^ b,
+                    _ => a.checked_rem(b).ok_or(Error::DivideByZero)?,
+                };
+                self.store_result(r, frame, Value::Integer(value & self.ones))
+            }
+            DIVIDE_OP => {
+                let a = self.integer(r, frame)?;
+                let b = self.integer(r, frame)?;
+                if b == 0 {
+                    return Err(Error::DivideByZero);
+                }
+                let remainder = self.super_name(r, frame)?;
+                self.store(&remainder, Value::Integer(a % b), frame)?;
+                self.store_result(r, frame, Value::Integer(a / b))
+            }
+            NOT_OP | FIND_SET_LEFT_BIT_OP | FIND_SET_RIGHT_BIT_OP | TO_INTEGER_OP => {
+                let a = self.integer(r, frame)?;
+                let value = match op {
+                    NOT_OP => !a & self.ones,
+                    FIND_SET_LEFT_BIT_OP => 64 - a.leading_zeros() as u64,
+                    FIND_SET_RIGHT_BIT_OP if a == 0 => 0,
+                    FIND_SET_RIGHT_BIT_OP => a.trailing_zeros() as u64 + 1,
+                    _ => a,
+                };
+                self.store_result(r, frame, Value::Integer(value))
+            }
+            INCREMENT_OP | DECREMENT_OP => {
+                let target = self.super_name(r, frame)?;
+                let a = self.read(&target, frame)?.as_integer()?;
+                let value = if op == INCREMENT_OP {
+                    a.wrapping_add(1)
+                } else {
+                    a.wrapping_sub(1)
+                };
+                let value = Value::Integer(value & self.ones);
+                self.store(&target, value.clone(), frame)?;
+                Ok(value)
+            }
+            LAND_OP | LOR_OP => {
+                let a = self.integer(r, frame)? != 0;
+                let b = self.integer(r, frame)? != 0;
+                Ok(self.logical(if op == LAND_OP { a && b } else { a || b }))
+            }
+            LNOT_OP => {
+                let a = self.integer(r, frame)?;
+                Ok(self.logical(a == 0))
+            }
+            LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+                let a = self.term_arg(r, frame)?;
+                let b = self.term_arg(r, frame)?;
+                let expected = match op {
+                    LEQUAL_OP => Ordering::Equal,
+                    LGREATER_OP => Ordering::Greater,
+                    _ => Ordering::Less,
+                };
+                Ok(self.logical(compare(&a, &b)? == expected))
+            }
+            CONCAT_OP => {
+                let a = self.term_arg(r, frame)?;
+                let b = self.term_arg(r, frame)?;
+                let value = match (a, b) {
+                    (Value::String(mut a), Value::String(b)) => {
+                        a.push_str(&b);
+                        Value::String(a)
+                    }
+                    (Value::Buffer(mut a), Value::Buffer(b)) => {
+                        a.extend_from_slice(&b);
+                        Value::Buffer(a)
+                    }
+                    (Value::Integer(a), Value::Integer(b)) => {
+                        let len = if self.ones == u32::MAX as u64 { 4 } else { 8 };
+                        let mut bytes = a.to_le_bytes()[..len].to_vec();
+                        bytes.extend_from_slice(&b.to_le_bytes()[..len]);
+                        Value::Buffer(bytes)
+                    }
+                    _ => return Err(Error::TypeMismatch),
+                };
+                self.store_result(r, frame, value)
+            }
+            SIZE_OF_OP => {
+                let target = self.super_name(r, frame)?;
+                let size = self.read(&target, frame)?.size()?;
+                Ok(Value::Integer(size as u64))
+            }
+            INDEX_OP => {
+                let source = self.term_arg(r, frame)?;
+                let index = self.integer(r, frame)? as usize;
+                let element = element_of(&source, index)?;
+                self.store_result(r, frame, element)
+            }
+            DEREF_OF_OP => match self.term_arg(r, frame)? {
+                Value::Reference(path) => self.read(&Target::Name(path), frame),
+                value => Ok(value),
+            },
+            OBJECT_TYPE_OP => {
+                let target = self.super_name(r, frame)?;
+                let code = match &target {
+                    Target::Name(path) => self
+                        .namespace
+                        .get(path)
+                        .ok_or(Error::UndefinedName)?
+                        .type_code(),
+                    target => self.read(target, frame)?.type_code(),
+                };
+                Ok(Value::Integer(code))
+            }
+            COND_REF_OF_OP => {
+                let exists = if is_name_start(r.peek()?) {
+                    let name = r.name_string()?;
+                    self.namespace.lookup(&name, &frame.scope).is_some()
+                } else {
+                    self.super_name(r, frame)?;
+                    true
+                };
+                // references are not supported, so nothing is stored
+                self.super_name(r, frame)?;
+                Ok(self.logical(exists))
+            }
+            ACQUIRE_OP => {
+                // there is nobody to wait for
+                self.super_name(r, frame)?;
+                r.bytes(2)?;
+                Ok(Value::Integer(0))
+            }
+            WAIT_OP => {
+                self.super_name(r, frame)?;
+                self.term_arg(r, frame)?;
+                Ok(Value::Integer(0))
+            }
+            op => Err(Error::InvalidOpcode(op)),
+        }
+    }
+
+    fn super_name(&mut self, r: &mut Reader, frame: &mut Frame) -> Result<Target> {
+        if is_name_start(r.peek()?) {
+            let name = r.name_string()?;
+            let path = self
+                .namespace
+                .lookup(&name, &frame.scope)
+                .ok_or(Error::UndefinedName)?;
+            return Ok(Target::Name(path));
+        }
+        match r.opcode()? {
+            // NullName, encoded like ZeroOp
+            ZERO_OP => Ok(Target::Null),
+            DEBUG_OP => Ok(Target::Debug),
+            op @ LOCAL0_OP..=LOCAL7_OP => Ok(Target::Local((op - LOCAL0_OP) as usize)),
+            op @ ARG0_OP..=ARG6_OP => Ok(Target::Arg((op - ARG0_OP) as usize)),
+            INDEX_OP => {
+                let source = self.super_name(r, frame)?;
+                let index = self.integer(r, frame)? as usize;
+                self.super_name(r, frame)?;
+                Ok(Target::Index(Box::new(source), index))
+            }
+            DEREF_OF_OP => match self.term_arg(r, frame)? {
+                Value::Reference(path) => Ok(Target::Name(path)),
+                _ => Err(Error::TypeMismatch),
+            },
+            op => Err(Error::InvalidOpcode(op)),
+        }
+    }
+
+    /// Stores `value` in the target that follows and returns it.
+    fn store_result(&mut self, r: &mut Reader, frame: &mut Frame, value: Value) -> Result<Value> {
+        let target = self.super_name(r, frame)?;
+        self.store(&target, value.clone(), frame)?;
+        Ok(value)
+    }
+
+    fn read(&mut self, target: &Target, frame: &mut Frame) -> Result<Value> {
+        match target {
+            Target::Null | Target::Debug => Err(Error::TypeMismatch),
+            Target::Local(i) => frame.locals[*i].clone().ok_or(Error::Uninitialized),
+            Target::Arg(i) => frame.args.get(*i).cloned().ok_or(Error::Uninitialized),
+            Target::Name(path) => match self.namespace.get(path).cloned() {
+                Some(Object::Value(value)) => Ok(value),
+                Some(Object::Field(field)) => Ok(Value::Integer(self.read_field(&field)?)),
+                Some(Object::Method(method)) => self.call(path, &method, Vec::new()),
+                Some(_) => Ok(Value::Reference(path.clone())),
+                None => Err(Error::UndefinedName),
+            },
+            Target::Index(source, index) => element_of(&self.read(source, frame)?, *index),
+        }
+    }
+
+    fn store(&mut self, target: &Target, value: Value, frame: &mut Frame) -> Result<()> {
+        match target {
+            Target::Null => {}
+            Target::Debug => debug!("AML: {:?}", value),
+            Target::Local(i) => frame.locals[*i] = Some(value),
+            Target::Arg(i) => {
+                if frame.args.len() <= *i {
+                    frame.args.resize(*i + 1, Value::Integer(0));
+                }
+                frame.args[*i] = value;
+            }
+            Target::Name(path) => match self.namespace.get_mut(path) {
+                Some(Object::Value(old)) => {
+                    // named integers stay integers
+                    *old = match old {
+                        Value::Integer(_) => Value::Integer(value.as_integer()?),
+                        _ => value,
+                    };
+                }
+                Some(Object::Field(field)) => {
+                    let field = field.clone();
+                    self.write_field(&field, value.as_integer()?)?;
+                }
+                Some(_) => return Err(Error::TypeMismatch),
+                None => return Err(Error::UndefinedName),
+            },
+            Target::Index(source, index) => {
+                let mut container = self.read(source, frame)?;
+                set_element(&mut container, *index, value)?;
+                self.store(source, container, frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn region_address(&mut self, region: &Path, offset: u64) -> Result<RegionAddress> {
+        let Region {
+            space,
+            offset: base,
+            ..
+        } = match self.namespace.get(region) {
+            Some(Object::Region(r)) => *r,
+            _ => return Err(Error::UndefinedName),
+        };
+        let address = base + offset;
+        match space {
+            AddressSpace::SystemMemory => Ok(RegionAddress::Memory(address)),
+            AddressSpace::SystemIo => Ok(RegionAddress::Io(address as u16)),
+            AddressSpace::PciConfig => {
+                // the region belongs to the device it is declared in
+                let device = region.parent().ok_or(Error::InvalidName)?;
+                let adr = self.evaluate_or(&device.join(*b"_ADR"), 0)?;
+                // the bus number of the host bridge, found by the search rules
+                let bbn = Name {
+                    root: false,
+                    parents: 0,
+                    segments: vec![*b"_BBN"],
+                };
+                let bus = match self.namespace.lookup(&bbn, &device) {
+                    Some(path) => self.evaluate(&path, Vec::new())?.as_integer()?,
+                    None => 0,
+                };
+                let pci = pci::Address {
+                    bus: bus as u8,
+                    device: (adr >> 16) as u8,
+                    function: adr as u8,
+                };
+                Ok(RegionAddress::PciConfig(pci, address as u16))
+            }
+            AddressSpace::Other(_) => Err(Error::Unsupported),
+        }
+    }
+
+    pub(super) fn read_field(&mut self, field: &Field) -> Result<u64> {
+        if field.bit_width == 0 || field.bit_width > 64 {
+            return Err(Error::Unsupported);
+        }
+        let size = field.access_size;
+        let unit_bits = size as u64 * 8;
+        let first = field.bit_offset / unit_bits;
+        let last = (field.bit_offset + field.bit_width - 1) / unit_bits;
+        let mut raw = 0u128;
+        for (i, unit) in (first..=last).enumerate() {
+            let address = self.region_address(&field.region, unit * size as u64)?;
+            let value = self.handler.read(address, size)? as u128;
+            raw |= value << (i as u64 * unit_bits);
+        }
+        let shift = field.bit_offset % unit_bits;
+        Ok(((raw >> shift) & mask(field.bit_width)) as u64)
+    }
+
+    /// Bits of partially written access units are preserved.
+    fn write_field(&mut self, field: &Field, value: u64) -> Result<()> {
+        if field.bit_width == 0 || field.bit_width > 64 {
+            return Err(Error::Unsupported);
+        }
+        let size = field.access_size;
+        let unit_bits = size as u64 * 8;
+        let first = field.bit_offset / unit_bits;
+        let last = (field.bit_offset + field.bit_width - 1) / unit_bits;
+        let shift = field.bit_offset % unit_bits;
+        let bits = (value as u128 & mask(field.bit_width)) << shift;
+        let bits_mask = mask(field.bit_width) << shift;
+        let unit_mask = mask(unit_bits);
+        for (i, unit) in (first..=last).enumerate() {
+            let pos = i as u64 * unit_bits;
+            let written = (bits_mask >> pos) & unit_mask;
+            let mut value = (bits >> pos) & unit_mask;
+            let address = self.region_address(&field.region, unit * size as u64)?;
+            if written != unit_mask {
+                let old = self.handler.read(address, size)? as u128;
+                value |= old & unit_mask & !written;
+            }
+            self.handler.write(address, size, value as u64)?;
+        }
+        Ok(())
+    }
+}
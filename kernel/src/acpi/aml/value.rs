@@ -0,0 +1,78 @@
+//! Data objects AML code computes with.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{Error, Path, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<Value>),
+    /// A named object such as a device, for example the link device in a
+    /// `_PRT` entry.
+    Reference(Path),
+}
+
+impl Value {
+    /// Converts strings and buffers as an implicit conversion to an integer
+    /// does.
+    pub fn as_integer(&self) -> Result<u64> {
+        match self {
+            Value::Integer(n) => Ok(*n),
+            Value::Buffer(bytes) => {
+                let mut le = [0u8; 8];
+                let len = core::cmp::min(bytes.len(), 8);
+                le[..len].copy_from_slice(&bytes[..len]);
+                Ok(u64::from_le_bytes(le))
+            }
+            Value::String(s) => {
+                let s = s.trim_start_matches("0x").trim_start_matches("0X");
+                let digits = s
+                    .find(|c: char| !c.is_ascii_hexdigit())
+                    .map_or(s, |end| &s[..end]);
+                if digits.is_empty() {
+                    return Ok(0);
+                }
+                u64::from_str_radix(digits, 16).map_err(|_| Error::TypeMismatch)
+            }
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    pub fn as_package(&self) -> Result<&[Value]> {
+        match self {
+            Value::Package(elements) => Ok(elements),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// The number of characters, bytes or elements, as `SizeOf` returns.
+    pub fn size(&self) -> Result<usize> {
+        match self {
+            Value::String(s) => Ok(s.len()),
+            Value::Buffer(bytes) => Ok(bytes.len()),
+            Value::Package(elements) => Ok(elements.len()),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// The code `ObjectType` returns.
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Value::Integer(_) => 1,
+            Value::String(_) => 2,
+            Value::Buffer(_) => 3,
+            Value::Package(_) => 4,
+            Value::Reference(_) => 0,
+        }
+    }
+}
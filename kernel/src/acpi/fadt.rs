@@ -0,0 +1,159 @@
+//! Fixed ACPI Description Table.
+use super::{u16_at, u32_at, u64_at, Error, GenericAddress, Result, SdtHeader};
+
+pub const SIGNATURE: &[u8; 4] = b"FACP";
+
+/// Size of the ACPI 1.0 FADT data, without the header.
+const V1_SIZE: usize = 80;
+
+// offsets in the data following the header
+const DSDT: usize = 4;
+const SMI_CMD: usize = 12;
+const ACPI_ENABLE: usize = 16;
+const PM1A_CNT_BLK: usize = 28;
+const PM1B_CNT_BLK: usize = 32;
+const PM1_CNT_LEN: usize = 53;
+const IAPC_BOOT_ARCH: usize = 73;
+const FLAGS: usize = 76;
+const RESET_REG: usize = 80;
+const RESET_VALUE: usize = 92;
+const X_DSDT: usize = 104;
+const X_PM1A_CNT_BLK: usize = 136;
+const X_PM1B_CNT_BLK: usize = 148;
+
+/// IAPC_BOOT_ARCH: the motherboard has an 8042 keyboard controller.
+const BOOT_ARCH_8042: u16 = 1 << 1;
+/// The reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Fadt {
+    dsdt: u64,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    /// `None` on hardware-reduced ACPI systems.
+    pub pm1a_control: Option<GenericAddress>,
+    pub pm1b_control: Option<GenericAddress>,
+    iapc_boot_arch: u16,
+    reset: Option<(GenericAddress, u8)>,
+}
+
+impl Fadt {
+    pub fn parse(table: &SdtHeader) -> Result<Self> {
+        table.validate(SIGNATURE)?;
+        let data = table.data();
+        if data.len() < V1_SIZE {
+            return Err(Error::BadLength);
+        }
+        // the 64-bit fields, which were added later, are used if present
+        let extended = |offset: usize, size: usize| {
+            if data.len() >= offset + size {
+                Some(&data[offset..offset + size])
+            } else {
+                None
+            }
+        };
+        let dsdt = match extended(X_DSDT, 8).map(|d| u64_at(d, 0)) {
+            Some(addr) if addr != 0 => addr,
+            _ => u32_at(data, DSDT) as u64,
+        };
+        let pm1_cnt_len = data[PM1_CNT_LEN];
+        let pm1_control = |x_offset: usize, offset: usize| {
+            let x_control = extended(x_offset, GenericAddress::SIZE).map(GenericAddress::parse);
+            match x_control {
+                Some(gas) if !gas.is_null() => Some(gas),
+                _ => match u32_at(data, offset) {
+                    0 => None,
+                    port => Some(GenericAddress::io(port, pm1_cnt_len)),
+                },
+            }
+        };
+        let flags = u32_at(data, FLAGS);
+        let reset = match extended(RESET_REG, GenericAddress::SIZE + 1) {
+            Some(d) if flags & RESET_REG_SUP != 0 => {
+                let reg = GenericAddress::parse(d);
+                if reg.is_null() {
+                    None
+                } else {
+                    Some((reg, data[RESET_VALUE]))
+                }
+            }
+            _ => None,
+        };
+        Ok(Fadt {
+            dsdt,
+            smi_cmd: u32_at(data, SMI_CMD),
+            acpi_enable: data[ACPI_ENABLE],
+            pm1a_control: pm1_control(X_PM1A_CNT_BLK, PM1A_CNT_BLK),
+            pm1b_control: pm1_control(X_PM1B_CNT_BLK, PM1B_CNT_BLK),
+            iapc_boot_arch: u16_at(data, IAPC_BOOT_ARCH),
+            reset,
+        })
+    }
+
+    /// Physical address of the DSDT.
+    pub fn dsdt_address(&self) -> u64 {
+        self.dsdt
+    }
+
+    /// The register and the value to write to it to reset the system.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        self.reset
+    }
+
+    /// Always false on ACPI 1.0, which has no such flag.
+    pub fn has_8042(&self) -> bool {
+        self.iapc_boot_arch & BOOT_ARCH_8042 != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acpi::test::make_table;
+    use crate::acpi::AddressSpace;
+    use alloc::vec;
+
+    #[test_case]
+    fn test_parse_fadt() {
+        let mut data = vec![0u8; 208];
+        data[DSDT..DSDT + 4].copy_from_slice(&0x1234_5000u32.to_le_bytes());
+        data[SMI_CMD..SMI_CMD + 4].copy_from_slice(&0xb2u32.to_le_bytes());
+        data[ACPI_ENABLE] = 0xf1;
+        data[PM1A_CNT_BLK..PM1A_CNT_BLK + 4].copy_from_slice(&0x604u32.to_le_bytes());
+        data[PM1_CNT_LEN] = 2;
+        data[FLAGS..FLAGS + 4].copy_from_slice(&RESET_REG_SUP.to_le_bytes());
+        data[RESET_REG..RESET_REG + 4].copy_from_slice(&[1, 8, 0, 1]);
+        data[RESET_REG + 4] = 0xf9;
+        data[RESET_REG + 5] = 0x0c;
+        data[RESET_VALUE] = 0x0f;
+        let table = make_table(SIGNATURE, &data);
+        let fadt = Fadt::parse(unsafe { &*(table.as_ptr() as *const SdtHeader) }).unwrap();
+
+        assert_eq!(fadt.dsdt_address(), 0x1234_5000);
+        assert_eq!((fadt.smi_cmd, fadt.acpi_enable), (0xb2, 0xf1));
+        assert_eq!(fadt.pm1a_control, Some(GenericAddress::io(0x604, 2)));
+        assert_eq!(fadt.pm1b_control, None);
+        assert!(!fadt.has_8042());
+        let (reset, value) = fadt.reset_register().unwrap();
+        assert_eq!(
+            (reset.space, reset.address, value),
+            (AddressSpace::SystemIo, 0xcf9, 0x0f)
+        );
+
+        // the extended fields take precedence
+        data[X_DSDT..X_DSDT + 8].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        data[X_PM1A_CNT_BLK..X_PM1A_CNT_BLK + 4].copy_from_slice(&[1, 16, 0, 2]);
+        data[X_PM1A_CNT_BLK + 4] = 0x04;
+        data[X_PM1A_CNT_BLK + 5] = 0x08;
+        let table = make_table(SIGNATURE, &data);
+        let fadt = Fadt::parse(unsafe { &*(table.as_ptr() as *const SdtHeader) }).unwrap();
+        assert_eq!(fadt.dsdt_address(), 0x1_0000_0000);
+        let pm1a = fadt.pm1a_control.unwrap();
+        assert_eq!((pm1a.address, pm1a.access_size), (0x804, 2));
+
+        let table = make_table(SIGNATURE, &data[..V1_SIZE - 1]);
+        let r = Fadt::parse(unsafe { &*(table.as_ptr() as *const SdtHeader) });
+        assert_eq!(r.unwrap_err(), Error::BadLength);
+    }
+}
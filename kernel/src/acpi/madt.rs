@@ -0,0 +1,354 @@
+//! Multiple APIC Description Table.
+use super::{u16_at, u32_at, u64_at, Error, Result, SdtHeader};
+
+pub const SIGNATURE: &[u8; 4] = b"APIC";
+
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_OVERRIDE: u8 = 2;
+const NMI_SOURCE: u8 = 3;
+const LOCAL_APIC_NMI: u8 = 4;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const LOCAL_X2APIC: u8 = 9;
+const LOCAL_X2APIC_NMI: u8 = 10;
+
+/// The system also has dual 8259 PICs, which must be masked to use the APICs.
+const PCAT_COMPAT: u32 = 1;
+
+/// Matches every processor in local APIC NMI entries.
+const ALL_PROCESSORS: u32 = 0xff;
+const ALL_X2APIC_PROCESSORS: u32 = 0xffff_ffff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// As the bus specifies; active high for ISA.
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// As the bus specifies; edge for ISA.
+    Conforming,
+    Edge,
+    Level,
+}
+
+/// MPS INTI flags.
+fn inti_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::Conforming,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Conforming,
+    };
+    (polarity, trigger)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LocalApic {
+    /// Matches the processor object in the namespace.
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+    /// The processor is disabled, but can be enabled at runtime.
+    pub online_capable: bool,
+    pub x2apic: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    /// The first global system interrupt this I/O APIC handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ which is not identity mapped to a global system interrupt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NmiSource {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// `None` for all processors.
+    pub processor_uid: Option<u32>,
+    /// LINT0 or LINT1.
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    LocalApic(LocalApic),
+    IoApic(IoApic),
+    InterruptOverride(InterruptOverride),
+    NmiSource(NmiSource),
+    LocalApicNmi(LocalApicNmi),
+    LocalApicAddressOverride(u64),
+    Unknown(u8),
+}
+
+impl Entry {
+    /// `data` is a whole entry including the type and length bytes. Returns
+    /// `None` if it is too short for its type.
+    fn parse(data: &[u8]) -> Option<Self> {
+        let min_len = match data[0] {
+            LOCAL_APIC => 8,
+            IO_APIC => 12,
+            INTERRUPT_OVERRIDE => 10,
+            NMI_SOURCE => 8,
+            LOCAL_APIC_NMI => 6,
+            LOCAL_APIC_ADDRESS_OVERRIDE => 12,
+            LOCAL_X2APIC => 16,
+            LOCAL_X2APIC_NMI => 12,
+            _ => 2,
+        };
+        if data.len() < min_len {
+            return None;
+        }
+        let entry = match data[0] {
+            LOCAL_APIC | LOCAL_X2APIC => {
+                let x2apic = data[0] == LOCAL_X2APIC;
+                let (processor_uid, apic_id, flags) = if x2apic {
+                    (u32_at(data, 12), u32_at(data, 4), u32_at(data, 8))
+                } else {
+                    (data[2] as u32, data[3] as u32, u32_at(data, 4))
+                };
+                Entry::LocalApic(LocalApic {
+                    processor_uid,
+                    apic_id,
+                    enabled: flags & 1 != 0,
+                    online_capable: flags & 2 != 0,
+                    x2apic,
+                })
+            }
+            IO_APIC => Entry::IoApic(IoApic {
+                id: data[2],
+                address: u32_at(data, 4),
+                gsi_base: u32_at(data, 8),
+            }),
+            INTERRUPT_OVERRIDE => {
+                let (polarity, trigger) = inti_flags(u16_at(data, 8));
+                Entry::InterruptOverride(InterruptOverride {
+                    bus: data[2],
+                    source: data[3],
+                    gsi: u32_at(data, 4),
+                    polarity,
+                    trigger,
+                })
+            }
+            NMI_SOURCE => {
+                let (polarity, trigger) = inti_flags(u16_at(data, 2));
+                Entry::NmiSource(NmiSource {
+                    gsi: u32_at(data, 4),
+                    polarity,
+                    trigger,
+                })
+            }
+            LOCAL_APIC_NMI | LOCAL_X2APIC_NMI => {
+                let (uid, all, flags, lint) = if data[0] == LOCAL_X2APIC_NMI {
+                    (
+                        u32_at(data, 4),
+                        ALL_X2APIC_PROCESSORS,
+                        u16_at(data, 2),
+                        data[8],
+                    )
+                } else {
+                    (data[2] as u32, ALL_PROCESSORS, u16_at(data, 3), data[5])
+                };
+                let (polarity, trigger) = inti_flags(flags);
+                Entry::LocalApicNmi(LocalApicNmi {
+                    processor_uid: if uid == all { None } else { Some(uid) },
+                    lint,
+                    polarity,
+                    trigger,
+                })
+            }
+            LOCAL_APIC_ADDRESS_OVERRIDE => Entry::LocalApicAddressOverride(u64_at(data, 4)),
+            ty => Entry::Unknown(ty),
+        };
+        Some(entry)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Madt<'a> {
+    local_apic_address: u32,
+    flags: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> Madt<'a> {
+    pub fn parse(table: &'a SdtHeader) -> Result<Self> {
+        table.validate(SIGNATURE)?;
+        let data = table.data();
+        if data.len() < 8 {
+            return Err(Error::BadLength);
+        }
+        Ok(Madt {
+            local_apic_address: u32_at(data, 0),
+            flags: u32_at(data, 4),
+            entries: &data[8..],
+        })
+    }
+
+    /// Physical address of the local APICs, taking an address override entry
+    /// into account.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|e| match e {
+                Entry::LocalApicAddressOverride(addr) => Some(addr),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    pub fn has_8259_pics(&self) -> bool {
+        self.flags & PCAT_COMPAT != 0
+    }
+
+    /// Stops at the first malformed entry.
+    pub fn entries(&self) -> Entries<'a> {
+        Entries { data: self.entries }
+    }
+
+    pub fn processors(&self) -> impl Iterator<Item = LocalApic> + 'a {
+        self.entries().filter_map(|e| match e {
+            Entry::LocalApic(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApic> + 'a {
+        self.entries().filter_map(|e| match e {
+            Entry::IoApic(io) => Some(io),
+            _ => None,
+        })
+    }
+
+    pub fn interrupt_overrides(&self) -> impl Iterator<Item = InterruptOverride> + 'a {
+        self.entries().filter_map(|e| match e {
+            Entry::InterruptOverride(o) => Some(o),
+            _ => None,
+        })
+    }
+
+    /// The global system interrupt, polarity and trigger mode an ISA IRQ is
+    /// connected to.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        match self
+            .interrupt_overrides()
+            .find(|o| o.bus == 0 && o.source == irq)
+        {
+            Some(o) => (o.gsi, o.polarity, o.trigger),
+            None => (irq as u32, Polarity::Conforming, TriggerMode::Conforming),
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if self.data.len() < 2 {
+            return None;
+        }
+        let len = self.data[1] as usize;
+        if len < 2 || len > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let (entry, rest) = self.data.split_at(len);
+        self.data = rest;
+        match Entry::parse(entry) {
+            Some(e) => Some(e),
+            None => {
+                self.data = &[];
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acpi::test::make_table;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_parse_madt() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+        data.extend_from_slice(&PCAT_COMPAT.to_le_bytes());
+        // CPU 0 enabled, CPU 1 online capable
+        data.extend_from_slice(&[LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+        data.extend_from_slice(&[LOCAL_APIC, 8, 1, 2, 2, 0, 0, 0]);
+        data.extend_from_slice(&[IO_APIC, 12, 4, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+        // IRQ0 -> GSI2, IRQ9 -> GSI9 active low, level
+        data.extend_from_slice(&[INTERRUPT_OVERRIDE, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[INTERRUPT_OVERRIDE, 10, 0, 9, 9, 0, 0, 0, 0x0f, 0]);
+        data.extend_from_slice(&[LOCAL_APIC_NMI, 6, 0xff, 0x05, 0, 1]);
+        data.extend_from_slice(&[0x7f, 3, 0]);
+        let table = make_table(SIGNATURE, &data);
+        let madt = Madt::parse(unsafe { &*(table.as_ptr() as *const SdtHeader) }).unwrap();
+
+        assert_eq!(madt.local_apic_address(), 0xfee0_0000);
+        assert!(madt.has_8259_pics());
+        let cpus: Vec<_> = madt.processors().collect();
+        assert_eq!(cpus.len(), 2);
+        assert!(cpus[0].enabled && !cpus[0].online_capable);
+        assert_eq!((cpus[1].apic_id, cpus[1].enabled), (2, false));
+        assert!(cpus[1].online_capable);
+        let io_apic = madt.io_apics().next().unwrap();
+        assert_eq!(
+            (io_apic.id, io_apic.address, io_apic.gsi_base),
+            (4, 0xfec0_0000, 0)
+        );
+        assert_eq!(
+            madt.isa_irq(0),
+            (2, Polarity::Conforming, TriggerMode::Conforming)
+        );
+        assert_eq!(
+            madt.isa_irq(9),
+            (9, Polarity::ActiveLow, TriggerMode::Level)
+        );
+        assert_eq!(
+            madt.isa_irq(1),
+            (1, Polarity::Conforming, TriggerMode::Conforming)
+        );
+        let nmi = madt
+            .entries()
+            .find_map(|e| match e {
+                Entry::LocalApicNmi(nmi) => Some(nmi),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((nmi.processor_uid, nmi.lint), (None, 1));
+        assert_eq!(nmi.polarity, Polarity::ActiveHigh);
+        assert_eq!(madt.entries().last(), Some(Entry::Unknown(0x7f)));
+    }
+}
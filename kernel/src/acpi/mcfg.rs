@@ -0,0 +1,94 @@
+//! PCI Express memory mapped configuration space base address description
+//! table.
+use super::{u16_at, u64_at, Error, Result, SdtHeader};
+
+pub const SIGNATURE: &[u8; 4] = b"MCFG";
+
+/// The entries follow 8 reserved bytes.
+const ENTRIES: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+/// The enhanced configuration space of a range of buses in a PCI segment.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    /// Physical address of the configuration space of bus 0, even if
+    /// `start_bus` is not 0.
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl Allocation {
+    fn parse(entry: &[u8]) -> Self {
+        Allocation {
+            base: u64_at(entry, 0),
+            segment: u16_at(entry, 8),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        }
+    }
+
+    pub fn contains(&self, bus: u8) -> bool {
+        (self.start_bus..=self.end_bus).contains(&bus)
+    }
+}
+
+pub struct Mcfg<'a> {
+    entries: &'a [u8],
+}
+
+impl<'a> Mcfg<'a> {
+    pub fn parse(table: &'a SdtHeader) -> Result<Self> {
+        table.validate(SIGNATURE)?;
+        let data = table.data();
+        if data.len() < ENTRIES {
+            return Err(Error::BadLength);
+        }
+        Ok(Mcfg {
+            entries: &data[ENTRIES..],
+        })
+    }
+
+    /// A trailing partial entry is ignored.
+    pub fn allocations(&self) -> impl Iterator<Item = Allocation> + 'a {
+        self.entries.chunks_exact(ENTRY_SIZE).map(Allocation::parse)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acpi::test::make_table;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_parse_mcfg() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&0xb000_0000u64.to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0xff, 0, 0, 0, 0]);
+        data.extend_from_slice(&0x8_0000_0000u64.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 0x10, 0x1f, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0; 4]);
+        let table = make_table(SIGNATURE, &data);
+        let mcfg = Mcfg::parse(unsafe { &*(table.as_ptr() as *const SdtHeader) }).unwrap();
+
+        let allocations: Vec<_> = mcfg.allocations().collect();
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(
+            allocations[0],
+            Allocation {
+                base: 0xb000_0000,
+                segment: 0,
+                start_bus: 0,
+                end_bus: 0xff,
+            }
+        );
+        assert_eq!(
+            (allocations[1].segment, allocations[1].base),
+            (1, 0x8_0000_0000)
+        );
+        assert!(allocations[1].contains(0x10) && !allocations[1].contains(0x20));
+    }
+}
@@ -0,0 +1,213 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+
+use x86_64::PhysAddr;
+
+use crate::frame_allocator::{self, FRAME_SIZE};
+use crate::paging::phys_to_virt;
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap(spin::Mutex::new(Heap::empty()));
+
+struct Node {
+    size: usize,
+    next: *mut Node,
+}
+
+/// First-fit heap keeping an address ordered list of free regions.
+/// Adjacent free regions are merged when memory is returned.
+pub struct Heap {
+    head: *mut Node,
+    free_bytes: usize,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Heap {
+            head: null_mut(),
+            free_bytes: 0,
+        }
+    }
+
+    /// # Safety
+    /// `start..start + size` must be unused memory which lives forever.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = Self::align_up(start, align_of::<Node>());
+        if size > aligned - start && size - (aligned - start) >= size_of::<Node>() {
+            self.add_free_region(aligned, size - (aligned - start));
+        }
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    fn align_up(addr: usize, alignment: usize) -> usize {
+        (addr + alignment - 1) & !(alignment - 1)
+    }
+
+    // every block must be able to hold a Node once it is freed
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(align_of::<Node>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        (
+            core::cmp::max(layout.size(), size_of::<Node>()),
+            layout.align(),
+        )
+    }
+
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut Node = null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let node = addr as *mut Node;
+        node.write(Node { size, next });
+        if prev.is_null() {
+            self.head = node;
+        } else {
+            (*prev).next = node;
+        }
+        self.free_bytes += size;
+
+        if !next.is_null() && addr + size == next as usize {
+            (*node).size += (*next).size;
+            (*node).next = (*next).next;
+        }
+        if !prev.is_null() && prev as usize + (*prev).size == addr {
+            (*prev).size += (*node).size;
+            (*prev).next = (*node).next;
+        }
+    }
+
+    /// Returns null if there is no region large enough.
+    ///
+    /// # Safety
+    /// The heap must have been initialized with valid memory.
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        let mut prev: *mut Node = null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            let region_start = current as usize;
+            let region_end = region_start + (*current).size;
+            let mut alloc_start = Self::align_up(region_start, align);
+            if alloc_start != region_start && alloc_start - region_start < size_of::<Node>() {
+                // the gap in front must be able to hold a Node
+                alloc_start = Self::align_up(region_start + size_of::<Node>(), align);
+            }
+            let alloc_end = alloc_start + size;
+            if alloc_end <= region_end {
+                let rest = region_end - alloc_end;
+                if rest == 0 || rest >= size_of::<Node>() {
+                    let next = (*current).next;
+                    if prev.is_null() {
+                        self.head = next;
+                    } else {
+                        (*prev).next = next;
+                    }
+                    self.free_bytes -= region_end - region_start;
+                    if alloc_start > region_start {
+                        self.add_free_region(region_start, alloc_start - region_start);
+                    }
+                    if rest > 0 {
+                        self.add_free_region(alloc_end, rest);
+                    }
+                    return alloc_start as *mut u8;
+                }
+            }
+            prev = current;
+            current = (*current).next;
+        }
+        null_mut()
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` with the same `layout`.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+}
+
+pub struct LockedHeap(spin::Mutex<Heap>);
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().deallocate(ptr, layout)
+    }
+}
+
+/// Reserve the kernel heap from the frame allocator.
+pub fn init() -> frame_allocator::Result<()> {
+    let frames = HEAP_SIZE / FRAME_SIZE;
+    let start = frame_allocator::allocate(frames)?;
+    let start = phys_to_virt(PhysAddr::new(start.addr()));
+    unsafe {
+        ALLOCATOR
+            .0
+            .lock()
+            .init(start.as_u64() as usize, frames * FRAME_SIZE)
+    };
+    Ok(())
+}
+
+pub fn free_bytes() -> usize {
+    ALLOCATOR.0.lock().free_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+    #[repr(align(4096))]
+    struct Pool([u8; 4096]);
+
+    #[test_case]
+    fn test_allocate_and_merge() {
+        let mut pool = Pool([0; 4096]);
+        let mut heap = Heap::empty();
+        unsafe { heap.init(pool.0.as_mut_ptr() as usize, 4096) };
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let a = unsafe { heap.allocate(layout) };
+        let b = unsafe { heap.allocate(layout) };
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(b as usize - a as usize, 104);
+        let aligned = unsafe { heap.allocate(Layout::from_size_align(64, 256).unwrap()) };
+        assert_eq!(aligned as usize % 256, 0);
+        unsafe {
+            heap.deallocate(a, layout);
+            heap.deallocate(aligned, Layout::from_size_align(64, 256).unwrap());
+            heap.deallocate(b, layout);
+        }
+        assert_eq!(heap.free_bytes(), 4096);
+        let whole = unsafe { heap.allocate(Layout::from_size_align(4096, 8).unwrap()) };
+        assert_eq!(whole, pool.0.as_mut_ptr());
+    }
+
+    #[test_case]
+    fn test_alloc_collections() {
+        let b = Box::new(42);
+        assert_eq!(*b, 42);
+        let v: Vec<usize> = (0..1000).collect();
+        assert_eq!(v.iter().sum::<usize>(), 999 * 1000 / 2);
+        let mut m = BTreeMap::new();
+        m.insert(String::from("laranja"), 1);
+        assert_eq!(m.get("laranja"), Some(&1));
+    }
+}
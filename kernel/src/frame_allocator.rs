@@ -0,0 +1,358 @@
+use boot_info::MemoryDescriptor;
+
+use crate::memory_map::MemoryMap;
+
+pub const FRAME_SIZE: usize = 4096;
+const MAX_PHYSICAL_MEMORY_BYTES: usize = 128 * 1024 * 1024 * 1024;
+const MAX_FRAMES: usize = MAX_PHYSICAL_MEMORY_BYTES / FRAME_SIZE;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+static FRAME_ALLOCATOR: spin::Mutex<BitmapFrameAllocator<{ MAX_FRAMES / BITS_PER_WORD }>> =
+    spin::Mutex::new(BitmapFrameAllocator::new());
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    NoEnoughMemory,
+    InvalidArgument,
+    OutOfRange,
+    DoubleFree,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Index of a 4 KiB physical frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FrameId(usize);
+
+impl FrameId {
+    pub const fn new(id: usize) -> Self {
+        FrameId(id)
+    }
+
+    /// The frame containing `addr`.
+    pub const fn containing(addr: u64) -> Self {
+        FrameId(addr as usize / FRAME_SIZE)
+    }
+
+    pub const fn id(&self) -> usize {
+        self.0
+    }
+
+    pub const fn addr(&self) -> u64 {
+        (self.0 * FRAME_SIZE) as u64
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Stats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+}
+
+impl Stats {
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+}
+
+/// Physical frame allocator keeping one bit per frame (set = allocated).
+/// `N` is the number of 64-bit words of the bitmap.
+pub struct BitmapFrameAllocator<const N: usize> {
+    bitmap: [u64; N],
+    /// One bit per frame which came from a usable region of the memory map.
+    usable: [u64; N],
+    range_end: usize,
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl<const N: usize> BitmapFrameAllocator<N> {
+    pub const fn new() -> Self {
+        Self {
+            bitmap: [0; N],
+            usable: [0; N],
+            range_end: 0,
+            total_frames: 0,
+            free_frames: 0,
+        }
+    }
+
+    pub const fn capacity() -> usize {
+        N * BITS_PER_WORD
+    }
+
+    /// Marks every frame allocated, then frees the usable regions of `memory_map`.
+    /// Boot services memory stays allocated until [`Self::reclaim_boot_services`].
+    pub fn init(&mut self, memory_map: &MemoryMap) {
+        self.bitmap.iter_mut().for_each(|w| *w = !0);
+        self.usable.iter_mut().for_each(|w| *w = 0);
+        self.range_end = 0;
+        self.total_frames = 0;
+        self.free_frames = 0;
+        memory_map.usable().for_each(|d| self.add_region(d));
+    }
+
+    /// Frees the boot services regions of `memory_map`. Nothing the firmware
+    /// left may be in use any more. Calling it again changes nothing.
+    pub fn reclaim_boot_services(&mut self, memory_map: &MemoryMap) {
+        memory_map.boot_services().for_each(|d| self.add_region(d));
+    }
+
+    fn add_region(&mut self, d: &MemoryDescriptor) {
+        let start = FrameId::containing(d.phys_start).id();
+        let end = core::cmp::min(FrameId::containing(d.phys_end()).id(), Self::capacity());
+        // never hand out frame 0, so that a null address is never valid
+        let start = core::cmp::max(start, 1);
+        if start >= end {
+            return;
+        }
+        // frames added before may have been allocated since
+        let mut added = 0;
+        for frame in start..end {
+            if !self.is_usable(frame) {
+                set_bit(&mut self.usable, frame, true);
+                self.set_bit(frame, false);
+                added += 1;
+            }
+        }
+        self.range_end = core::cmp::max(self.range_end, end);
+        self.total_frames += added;
+        self.free_frames += added;
+    }
+
+    fn is_usable(&self, frame: usize) -> bool {
+        get_bit(&self.usable, frame)
+    }
+
+    fn is_allocated(&self, frame: usize) -> bool {
+        get_bit(&self.bitmap, frame)
+    }
+
+    fn set_bit(&mut self, frame: usize, allocated: bool) {
+        set_bit(&mut self.bitmap, frame, allocated);
+    }
+
+    fn align_up(value: usize, alignment: usize) -> usize {
+        (value + alignment - 1) & !(alignment - 1)
+    }
+
+    pub fn allocate(&mut self, num_frames: usize) -> Result<FrameId> {
+        self.allocate_aligned(num_frames, 1, 0)
+    }
+
+    /// Allocates `num_frames` contiguous frames whose first frame is a multiple
+    /// of `align_frames` and which do not cross a multiple of `boundary_frames`
+    /// (0 means no boundary).
+    pub fn allocate_aligned(
+        &mut self,
+        num_frames: usize,
+        align_frames: usize,
+        boundary_frames: usize,
+    ) -> Result<FrameId> {
+        if num_frames == 0
+            || !align_frames.is_power_of_two()
+            || (boundary_frames != 0
+                && (!boundary_frames.is_power_of_two() || boundary_frames < num_frames))
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut start = Self::align_up(1, align_frames);
+        loop {
+            if boundary_frames != 0
+                && start / boundary_frames != (start + num_frames - 1) / boundary_frames
+            {
+                start = Self::align_up(start, boundary_frames);
+                start = Self::align_up(start, align_frames);
+                continue;
+            }
+            if start + num_frames > self.range_end {
+                return Err(Error::NoEnoughMemory);
+            }
+            match (start..start + num_frames).find(|&f| self.is_allocated(f)) {
+                Some(allocated) => {
+                    let mut next = allocated + 1;
+                    // skip fully allocated words quickly
+                    while next % BITS_PER_WORD == 0
+                        && next < self.range_end
+                        && self.bitmap[next / BITS_PER_WORD] == !0
+                    {
+                        next += BITS_PER_WORD;
+                    }
+                    start = Self::align_up(next, align_frames);
+                }
+                None => {
+                    for frame in start..start + num_frames {
+                        self.set_bit(frame, true);
+                    }
+                    self.free_frames -= num_frames;
+                    return Ok(FrameId::new(start));
+                }
+            }
+        }
+    }
+
+    pub fn free(&mut self, start: FrameId, num_frames: usize) -> Result<()> {
+        let start = start.id();
+        if start == 0
+            || start + num_frames > self.range_end
+            || (start..start + num_frames).any(|f| !self.is_usable(f))
+        {
+            return Err(Error::OutOfRange);
+        }
+        if (start..start + num_frames).any(|f| !self.is_allocated(f)) {
+            return Err(Error::DoubleFree);
+        }
+        for frame in start..start + num_frames {
+            self.set_bit(frame, false);
+        }
+        self.free_frames += num_frames;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            total_frames: self.total_frames,
+            free_frames: self.free_frames,
+        }
+    }
+}
+
+fn get_bit<const N: usize>(bitmap: &[u64; N], frame: usize) -> bool {
+    bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+}
+
+fn set_bit<const N: usize>(bitmap: &mut [u64; N], frame: usize, value: bool) {
+    let bit = 1 << (frame % BITS_PER_WORD);
+    if value {
+        bitmap[frame / BITS_PER_WORD] |= bit;
+    } else {
+        bitmap[frame / BITS_PER_WORD] &= !bit;
+    }
+}
+
+impl<const N: usize> Default for BitmapFrameAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn init(memory_map: &MemoryMap) {
+    FRAME_ALLOCATOR.lock().init(memory_map);
+}
+
+pub fn reclaim_boot_services(memory_map: &MemoryMap) {
+    FRAME_ALLOCATOR.lock().reclaim_boot_services(memory_map);
+}
+
+pub fn allocate(num_frames: usize) -> Result<FrameId> {
+    FRAME_ALLOCATOR.lock().allocate(num_frames)
+}
+
+pub fn allocate_aligned(
+    num_frames: usize,
+    align_frames: usize,
+    boundary_frames: usize,
+) -> Result<FrameId> {
+    FRAME_ALLOCATOR
+        .lock()
+        .allocate_aligned(num_frames, align_frames, boundary_frames)
+}
+
+pub fn free(start: FrameId, num_frames: usize) -> Result<()> {
+    FRAME_ALLOCATOR.lock().free(start, num_frames)
+}
+
+pub fn stats() -> Stats {
+    FRAME_ALLOCATOR.lock().stats()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use boot_info::{MemoryDescriptor, MemoryType};
+
+    static DESCRIPTORS: [MemoryDescriptor; 3] = [
+        MemoryDescriptor {
+            ty: MemoryType::CONVENTIONAL,
+            phys_start: 0,
+            page_count: 64,
+            attribute: 0,
+        },
+        MemoryDescriptor {
+            ty: MemoryType::RESERVED,
+            phys_start: 64 * 4096,
+            page_count: 64,
+            attribute: 0,
+        },
+        MemoryDescriptor {
+            ty: MemoryType::BOOT_SERVICES_DATA,
+            phys_start: 128 * 4096,
+            page_count: 128,
+            attribute: 0,
+        },
+    ];
+
+    fn allocator() -> BitmapFrameAllocator<4> {
+        let mut allocator = BitmapFrameAllocator::new();
+        allocator.init(&MemoryMap::new(&DESCRIPTORS));
+        allocator.reclaim_boot_services(&MemoryMap::new(&DESCRIPTORS));
+        allocator
+    }
+
+    #[test_case]
+    fn test_init_stats() {
+        let mut early = BitmapFrameAllocator::<4>::new();
+        early.init(&MemoryMap::new(&DESCRIPTORS));
+        assert_eq!(early.stats().total_frames, 63);
+        // boot services memory is held back until it is reclaimed
+        assert_eq!(early.allocate(64), Err(Error::NoEnoughMemory));
+
+        let mut allocator = allocator();
+        let stats = allocator.stats();
+        assert_eq!(stats.total_frames, 63 + 128);
+        assert_eq!(stats.free_frames, 63 + 128);
+
+        // reclaiming again neither counts nor frees anything twice
+        let frame = allocator.allocate(64).unwrap();
+        allocator.reclaim_boot_services(&MemoryMap::new(&DESCRIPTORS));
+        assert_eq!(allocator.stats().total_frames, 63 + 128);
+        assert_eq!(allocator.stats().used_frames(), 64);
+        assert!(allocator.is_allocated(frame.id() + 63));
+    }
+
+    #[test_case]
+    fn test_allocate_skips_reserved() {
+        let mut allocator = allocator();
+        assert_eq!(allocator.allocate(60), Ok(FrameId::new(1)));
+        assert_eq!(allocator.allocate(8), Ok(FrameId::new(128)));
+        assert_eq!(allocator.stats().used_frames(), 68);
+    }
+
+    #[test_case]
+    fn test_allocate_aligned_and_boundary() {
+        let mut allocator = allocator();
+        allocator.allocate(63).unwrap();
+        assert_eq!(allocator.allocate(10), Ok(FrameId::new(128)));
+        // 138..146 would cross the 144 boundary
+        assert_eq!(allocator.allocate_aligned(8, 1, 16), Ok(FrameId::new(144)));
+        assert_eq!(allocator.allocate_aligned(4, 32, 0), Ok(FrameId::new(160)));
+        assert_eq!(
+            allocator.allocate_aligned(3, 1, 2),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn test_free() {
+        let mut allocator = allocator();
+        let frame = allocator.allocate(63).unwrap();
+        assert_eq!(allocator.free(frame, 63), Ok(()));
+        assert_eq!(allocator.free(frame, 1), Err(Error::DoubleFree));
+        // the reserved region was never handed out
+        assert_eq!(allocator.free(FrameId::new(64), 1), Err(Error::OutOfRange));
+        assert_eq!(allocator.free(FrameId::new(60), 8), Err(Error::OutOfRange));
+        assert_eq!(allocator.allocate(63), Ok(frame));
+        assert_eq!(allocator.allocate(129), Err(Error::NoEnoughMemory));
+    }
+}
@@ -0,0 +1,45 @@
+//! The kernel's GDT and TSS.
+//!
+//! The GDT the firmware left lives in boot services memory and has no TSS. The
+//! TSS gives double faults a stack of their own, so that an overflow of the
+//! kernel stack into its guard page can still be reported.
+use core::ptr::{addr_of, addr_of_mut};
+
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+/// The entry of the interrupt stack table double faults run on.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 16 * 1024;
+
+/// `u128`s keep the stack 16-byte aligned.
+static mut DOUBLE_FAULT_STACK: [u128; DOUBLE_FAULT_STACK_SIZE / 16] =
+    [0; DOUBLE_FAULT_STACK_SIZE / 16];
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+
+/// Loads the GDT and the TSS and reloads the segment registers. Called once,
+/// before interrupts are enabled.
+pub fn init() {
+    unsafe {
+        let stack = VirtAddr::from_ptr(addr_of!(DOUBLE_FAULT_STACK));
+        let tss = &mut *addr_of_mut!(TSS);
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack + DOUBLE_FAULT_STACK_SIZE;
+
+        let gdt = &mut *addr_of_mut!(GDT);
+        let code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(&*addr_of!(TSS)));
+        gdt.load_unsafe();
+        CS::set_reg(code);
+        SS::set_reg(data);
+        DS::set_reg(data);
+        ES::set_reg(data);
+        load_tss(tss);
+    }
+}
@@ -1,43 +1,12 @@
 use crate::ascii_font::FONTS;
-use crate::println;
+use crate::paging::phys_to_virt;
+use crate::params::{Param, ParamValue};
+use crate::{println, register_param};
 use core::mem::MaybeUninit;
+use x86_64::PhysAddr;
 
-#[derive(Debug, Copy, Clone)]
-#[repr(u32)]
-pub enum PixelFormat {
-    Rgb = 0,
-    Bgr,
-    Bitmask,
-    BltOnly,
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-#[repr(C)]
-pub struct PixelBitmask {
-    pub red: u32,
-    pub green: u32,
-    pub blue: u32,
-    pub reserved: u32,
-}
+pub use boot_info::{FrameBufferInfo, ModeInfo, PixelBitmask, PixelFormat};
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C)]
-pub struct ModeInfo {
-    pub version: u32,
-    pub hor_res: u32,
-    pub ver_res: u32,
-    pub format: PixelFormat,
-    pub mask: PixelBitmask,
-    pub stride: u32,
-}
-
-impl ModeInfo {
-    pub fn resolution(&self) -> (usize, usize) {
-        (self.hor_res as usize, self.ver_res as usize)
-    }
-}
-
-#[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FrameBuffer {
     base: *mut u8,
@@ -45,6 +14,10 @@ pub struct FrameBuffer {
 }
 
 impl FrameBuffer {
+    pub fn new(base: *mut u8, size: usize) -> Self {
+        FrameBuffer { base, size }
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut u8 {
         self.base
     }
@@ -77,13 +50,46 @@ pub struct PixelColor(pub u8, pub u8, pub u8); // RGB
 static mut RAW_GRAPHICS: MaybeUninit<Graphics> = MaybeUninit::<Graphics>::uninit();
 static mut GRAPHICS_INITIALIZED: bool = false;
 
+/// Clockwise rotation of the screen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    fn is_sideways(&self) -> bool {
+        matches!(self, Rotation::Deg90 | Rotation::Deg270)
+    }
+}
+
+impl ParamValue for Rotation {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "0" => Some(Rotation::Deg0),
+            "90" => Some(Rotation::Deg90),
+            "180" => Some(Rotation::Deg180),
+            "270" => Some(Rotation::Deg270),
+            _ => None,
+        }
+    }
+}
+
+/// `auto` rotates and scales the screen of GPD Pocket.
+pub static ROTATION: Param<Option<Rotation>> = Param::new("fb.rotate", None);
+pub static SCALE: Param<Option<usize>> = Param::new("fb.scale", None);
+register_param!(ROTATION);
+register_param!(SCALE);
+
 #[derive(Copy, Clone)]
 pub struct Graphics {
     fb: FrameBuffer,
     mi: ModeInfo,
     pixel_writer: unsafe fn(&mut FrameBuffer, usize, &PixelColor),
-    rotated: bool,
-    double_scaled: bool,
+    rotation: Rotation,
+    scale: usize,
 }
 
 impl Graphics {
@@ -102,18 +108,26 @@ impl Graphics {
             }
         };
 
-        // Hardcode for GPD Pocket resolution
-        let rotated = mi.resolution() == (1200, 1920);
-        let double_scaled = mi.resolution() == (1200, 1920);
+        let gpd_pocket = mi.resolution() == (1200, 1920);
+        let rotation = ROTATION.get().unwrap_or(if gpd_pocket {
+            Rotation::Deg90
+        } else {
+            Rotation::Deg0
+        });
+        let scale = SCALE.get().unwrap_or(if gpd_pocket { 2 } else { 1 }).max(1);
         Graphics {
             fb,
             mi,
             pixel_writer,
-            rotated,
-            double_scaled,
+            rotation,
+            scale,
         }
     }
 
+    pub fn is_initialized() -> bool {
+        unsafe { GRAPHICS_INITIALIZED }
+    }
+
     pub fn instance() -> &'static mut Self {
         if unsafe { !GRAPHICS_INITIALIZED } {
             panic!("graphics not initialized");
@@ -123,39 +137,37 @@ impl Graphics {
 
     ///
     /// # Safety
-    /// This is unsafe : handle raw pointers.
-    pub unsafe fn initialize_instance(fb: *mut FrameBuffer, mi: *mut ModeInfo) {
-        core::ptr::write(RAW_GRAPHICS.as_mut_ptr(), Graphics::new(*fb, *mi));
+    /// This is unsafe : `info` must describe a valid framebuffer.
+    pub unsafe fn initialize_instance(info: &FrameBufferInfo) {
+        let base = phys_to_virt(PhysAddr::new(info.base));
+        let fb = FrameBuffer::new(base.as_mut_ptr(), info.size as usize);
+        core::ptr::write(RAW_GRAPHICS.as_mut_ptr(), Graphics::new(fb, info.mode));
         GRAPHICS_INITIALIZED = true;
     }
 
     /// Write to the pixel of the buffer
     ///
-    pub fn write_pixel(&mut self, mut x: usize, mut y: usize, color: &PixelColor) {
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: &PixelColor) {
         let (width, height) = self.resolution();
-        if x > width {
+        if x >= width {
             println!("bad x coord: {}", x);
             return;
         }
-        if y > height as usize {
+        if y >= height {
             println!("bad y coord: {}", y);
             return;
         }
 
-        if self.rotated {
-            let oy = y;
-            y = x;
-            x = height - oy;
-        }
-        if self.double_scaled {
-            x *= 2;
-            y *= 2;
-            self.write_actual_pixel(x, y, color);
-            self.write_actual_pixel(x + 1, y, color);
-            self.write_actual_pixel(x, y + 1, color);
-            self.write_actual_pixel(x + 1, y + 1, color);
-        } else {
-            self.write_actual_pixel(x, y, color);
+        let (x, y) = match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (height - 1 - y, x),
+            Rotation::Deg180 => (width - 1 - x, height - 1 - y),
+            Rotation::Deg270 => (y, width - 1 - x),
+        };
+        for dy in 0..self.scale {
+            for dx in 0..self.scale {
+                self.write_actual_pixel(x * self.scale + dx, y * self.scale + dy, color);
+            }
         }
     }
 
@@ -207,12 +219,12 @@ impl Graphics {
 
     pub fn resolution(&self) -> (usize, usize) {
         let r = self.mi.resolution();
-        let r = if self.rotated { (r.1, r.0) } else { r };
-        if self.double_scaled {
-            (r.0 / 2, r.1 / 2)
+        let r = if self.rotation.is_sideways() {
+            (r.1, r.0)
         } else {
             r
-        }
+        };
+        (r.0 / self.scale, r.1 / self.scale)
     }
 
     pub fn clear(&mut self, color: &PixelColor) {
@@ -224,6 +236,11 @@ impl Graphics {
         }
     }
 
+    /// Switch to another virtual address of the same framebuffer.
+    pub fn remap_frame_buffer(&mut self, base: *mut u8) {
+        self.fb.base = base;
+    }
+
     pub fn fb(&self) -> FrameBuffer {
         self.fb
     }
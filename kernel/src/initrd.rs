@@ -0,0 +1,241 @@
+//! Read-only access to the initial ramdisk, a tar (ustar) archive loaded by
+//! the bootloader.
+use boot_info::{BootInfo, FileKind};
+
+const BLOCK_SIZE: usize = 512;
+
+static INITRD: spin::Mutex<Option<Archive<'static>>> = spin::Mutex::new(None);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    BadChecksum,
+    BadHeader,
+    UnsupportedPath,
+    Truncated,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Other(u8),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry<'a> {
+    /// Path without the leading `./`. Directories end with `/`.
+    pub name: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+#[derive(Copy, Clone)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+}
+
+fn field(header: &[u8], offset: usize, len: usize) -> &[u8] {
+    let field = &header[offset..offset + len];
+    let end = field.iter().position(|&c| c == 0).unwrap_or(len);
+    &field[..end]
+}
+
+fn parse_octal(field: &[u8]) -> Result<usize> {
+    let s = core::str::from_utf8(field).map_err(|_| Error::BadHeader)?;
+    let s = s.trim_matches(|c| c == ' ' || c == '\0');
+    if s.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(s, 8).map_err(|_| Error::BadHeader)
+}
+
+fn check_checksum(header: &[u8]) -> Result<()> {
+    let expected = parse_octal(field(header, 148, 8))?;
+    // the checksum field itself is counted as spaces
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as usize)
+        .sum();
+    if sum == expected {
+        Ok(())
+    } else {
+        Err(Error::BadChecksum)
+    }
+}
+
+impl<'a> Archive<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Archive { data }
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            offset: 0,
+        }
+    }
+
+    /// Looks up a regular file by its path, with or without a leading `/`.
+    pub fn find(&self, path: &str) -> Option<&'a [u8]> {
+        let path = path.trim_start_matches('/');
+        self.entries()
+            .filter_map(|e| e.ok())
+            .find(|e| e.kind == EntryKind::File && e.name == path)
+            .map(|e| e.data)
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Entries<'a> {
+    fn parse(&mut self) -> Result<Option<Entry<'a>>> {
+        let data = self.data;
+        if self.offset + BLOCK_SIZE > data.len() {
+            return Err(Error::Truncated);
+        }
+        let header = &data[self.offset..self.offset + BLOCK_SIZE];
+        if header.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        check_checksum(header)?;
+        if field(header, 257, 5) != b"ustar" {
+            return Err(Error::BadHeader);
+        }
+        let size = parse_octal(field(header, 124, 12))?;
+        let start = self.offset + BLOCK_SIZE;
+        if start + size > data.len() {
+            return Err(Error::Truncated);
+        }
+
+        // paths longer than 100 bytes, which are split into a prefix and a
+        // name, are not supported
+        if !field(header, 345, 155).is_empty() {
+            return Err(Error::UnsupportedPath);
+        }
+        let name = core::str::from_utf8(field(header, 0, 100)).map_err(|_| Error::BadHeader)?;
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            k => EntryKind::Other(k),
+        };
+        self.offset = start + ((size + BLOCK_SIZE - 1) & !(BLOCK_SIZE - 1));
+        Ok(Some(Entry {
+            name: name.trim_start_matches("./"),
+            kind,
+            data: &data[start..start + size],
+        }))
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        match self.parse() {
+            Ok(entry) => {
+                if entry.is_none() {
+                    self.offset = self.data.len();
+                }
+                entry.map(Ok)
+            }
+            Err(e) => {
+                // stop after the first broken header
+                self.offset = self.data.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Finds the initrd among the files loaded by the bootloader.
+///
+/// # Safety
+/// The physical memory offset must be set, and the loaded files must stay
+/// mapped and untouched.
+pub unsafe fn init(boot_info: &BootInfo) -> Option<Archive<'static>> {
+    let file = boot_info
+        .files
+        .as_slice(boot_info.physical_memory_offset)
+        .iter()
+        .find(|f| f.kind == FileKind::Initrd)?;
+    let data = core::slice::from_raw_parts(
+        (boot_info.physical_memory_offset + file.base) as *const u8,
+        file.size as usize,
+    );
+    let archive = Archive::new(data);
+    *INITRD.lock() = Some(archive);
+    Some(archive)
+}
+
+pub fn archive() -> Option<Archive<'static>> {
+    *INITRD.lock()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_header(block: &mut [u8], name: &str, kind: u8, size: usize) {
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..107].copy_from_slice(b"0000644");
+        let size = alloc::format!("{:011o}", size);
+        block[124..135].copy_from_slice(size.as_bytes());
+        block[156] = kind;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[148..156].fill(b' ');
+        let sum: usize = block[..BLOCK_SIZE].iter().map(|&b| b as usize).sum();
+        let sum = alloc::format!("{:06o}\0 ", sum);
+        block[148..156].copy_from_slice(sum.as_bytes());
+    }
+
+    fn make_archive() -> alloc::vec::Vec<u8> {
+        let mut data = alloc::vec![0; BLOCK_SIZE * 6];
+        write_header(&mut data[..], "./etc/", b'5', 0);
+        write_header(&mut data[BLOCK_SIZE..], "./etc/motd", b'0', 13);
+        data[BLOCK_SIZE * 2..BLOCK_SIZE * 2 + 13].copy_from_slice(b"Hello, world!");
+        write_header(&mut data[BLOCK_SIZE * 3..], "./empty", b'0', 0);
+        data
+    }
+
+    #[test_case]
+    fn test_entries() {
+        let data = make_archive();
+        let archive = Archive::new(&data);
+        let mut entries = archive.entries().map(|e| e.unwrap());
+        let dir = entries.next().unwrap();
+        assert_eq!((dir.name, dir.kind), ("etc/", EntryKind::Directory));
+        let motd = entries.next().unwrap();
+        assert_eq!((motd.name, motd.kind), ("etc/motd", EntryKind::File));
+        assert_eq!(motd.data, b"Hello, world!");
+        assert_eq!(entries.next().unwrap().name, "empty");
+        assert!(entries.next().is_none());
+        assert_eq!(archive.find("/etc/motd"), Some(&b"Hello, world!"[..]));
+        assert_eq!(archive.find("etc"), None);
+    }
+
+    #[test_case]
+    fn test_broken_archive() {
+        let mut data = make_archive();
+        data[BLOCK_SIZE + 1] ^= 1;
+        let archive = Archive::new(&data);
+        let mut entries = archive.entries();
+        assert!(entries.next().unwrap().is_ok());
+        assert_eq!(entries.next().unwrap().unwrap_err(), Error::BadChecksum);
+        assert!(entries.next().is_none());
+        assert!(Archive::new(&data[..BLOCK_SIZE + 100])
+            .entries()
+            .nth(1)
+            .unwrap()
+            .is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Interrupt descriptor table and the allocation of its vectors.
+//!
+//! Vectors below [`FIRST_DEVICE_VECTOR`] are exceptions, handled from
+//! [`init_idt`] on. Device interrupts need [`init`]. A driver takes
+//! vectors with [`allocate_vectors`], installs a handler for each with
+//! [`set_handler`] and ends each interrupt with [`apic::end_of_interrupt`].
+use x86_64::instructions::port::PortWriteOnly;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{
+    Entry, HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+};
+
+use crate::acpi::{self, madt::Madt};
+use crate::gdt;
+use crate::paging;
+use crate::warn;
+
+pub mod apic;
+
+pub const FIRST_DEVICE_VECTOR: u8 = 0x30;
+/// The vector the local APIC delivers spurious interrupts to.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Data ports of the master and slave 8259 PICs.
+const PIC_MASTER_DATA: u16 = 0x21;
+const PIC_SLAVE_DATA: u16 = 0xa1;
+
+static IDT: spin::Mutex<Option<InterruptDescriptorTable>> = spin::Mutex::new(None);
+static VECTORS: spin::Mutex<VectorAllocator> = spin::Mutex::new(VectorAllocator::new());
+
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    NotInitialized,
+    /// No free block of vectors is large enough.
+    NoVectors,
+    Acpi(acpi::Error),
+    Paging(paging::Error),
+}
+
+impl From<acpi::Error> for Error {
+    fn from(e: acpi::Error) -> Self {
+        Error::Acpi(e)
+    }
+}
+
+impl From<paging::Error> for Error {
+    fn from(e: paging::Error) -> Self {
+        Error::Paging(e)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Which of the 256 vectors are in use.
+struct VectorAllocator {
+    used: [u64; 4],
+}
+
+impl VectorAllocator {
+    const fn new() -> Self {
+        let mut used = [0; 4];
+        // exceptions and the vectors reserved after them
+        used[0] = (1 << FIRST_DEVICE_VECTOR) - 1;
+        used[3] = 1 << (SPURIOUS_VECTOR - 192);
+        VectorAllocator { used }
+    }
+
+    fn is_used(&self, vector: usize) -> bool {
+        self.used[vector / 64] & 1 << (vector % 64) != 0
+    }
+
+    fn set(&mut self, first: usize, count: usize, used: bool) {
+        for vector in first..first + count {
+            if used {
+                self.used[vector / 64] |= 1 << (vector % 64);
+            } else {
+                self.used[vector / 64] &= !(1 << (vector % 64));
+            }
+        }
+    }
+
+    /// Takes `count` consecutive vectors starting at a multiple of `align`,
+    /// which is a power of two.
+    fn allocate(&mut self, count: usize, align: usize) -> Result<u8> {
+        let mut first = (FIRST_DEVICE_VECTOR as usize + align - 1) & !(align - 1);
+        while first + count <= 256 {
+            match (first..first + count).find(|&v| self.is_used(v)) {
+                Some(used) => first = (used + align) & !(align - 1),
+                None => {
+                    self.set(first, count, true);
+                    return Ok(first as u8);
+                }
+            }
+        }
+        Err(Error::NoVectors)
+    }
+
+    fn free(&mut self, first: u8, count: usize) {
+        self.set(first as usize, count, false);
+    }
+}
+
+/// Takes `count` consecutive vectors, the first of which is a multiple of
+/// `align`. `align` has to be a power of two.
+pub fn allocate_vectors(count: usize, align: usize) -> Result<u8> {
+    assert!(count > 0 && align.is_power_of_two());
+    VECTORS.lock().allocate(count, align)
+}
+
+/// Releases vectors and removes their handlers.
+pub fn free_vectors(first: u8, count: usize) {
+    if let Some(idt) = IDT.lock().as_mut() {
+        for vector in first as usize..first as usize + count {
+            idt[vector] = Entry::missing();
+        }
+    }
+    VECTORS.lock().free(first, count);
+}
+
+/// Installs the handler of an allocated vector. It has to call
+/// [`apic::end_of_interrupt`].
+pub fn set_handler(vector: u8, handler: HandlerFunc) -> Result<()> {
+    assert!((FIRST_DEVICE_VECTOR..SPURIOUS_VECTOR).contains(&vector));
+    let mut idt = IDT.lock();
+    let idt = idt.as_mut().ok_or(Error::NotInitialized)?;
+    idt[vector as usize].set_handler_fn(handler);
+    Ok(())
+}
+
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    warn!("breakpoint: {:#x?}", frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, code: PageFaultErrorCode) {
+    panic!("page fault at {:?}: {:?}\n{:#x?}", Cr2::read(), code, frame);
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(frame: InterruptStackFrame, code: u64) {
+    panic!("general protection fault: {:x}\n{:#x?}", code, frame);
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _code: u64) -> ! {
+    panic!("double fault\n{:#x?}", frame);
+}
+
+/// Not acknowledged with an EOI.
+extern "x86-interrupt" fn spurious_handler(_frame: InterruptStackFrame) {}
+
+/// Loads the IDT with the exception handlers. Double faults run on the stack
+/// [`gdt::init`] has set up, as they may be caused by a stack overflow.
+pub fn init_idt() {
+    let mut idt = IDT.lock();
+    let idt = idt.insert(InterruptDescriptorTable::new());
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt[SPURIOUS_VECTOR as usize].set_handler_fn(spurious_handler);
+    // the table stays in IDT until the kernel ends
+    unsafe { idt.load_unsafe() };
+}
+
+/// Masks the 8259 PICs and enables the local APIC from the MADT, then enables
+/// interrupts. The IDT has to be loaded.
+pub fn init(tables: &acpi::Tables) -> Result<()> {
+    if IDT.lock().is_none() {
+        return Err(Error::NotInitialized);
+    }
+    let madt = Madt::parse(tables.find(acpi::madt::SIGNATURE)?)?;
+    if madt.has_8259_pics() {
+        unsafe {
+            PortWriteOnly::<u8>::new(PIC_MASTER_DATA).write(0xff);
+            PortWriteOnly::<u8>::new(PIC_SLAVE_DATA).write(0xff);
+        }
+    }
+    apic::init(&madt)?;
+    x86_64::instructions::interrupts::enable();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn test_allocate_vectors() {
+        let mut vectors = VectorAllocator::new();
+        assert_eq!(vectors.allocate(1, 1).unwrap(), 0x30);
+        // MSI needs a block aligned to its size
+        assert_eq!(vectors.allocate(4, 4).unwrap(), 0x34);
+        assert_eq!(vectors.allocate(2, 1).unwrap(), 0x31);
+        vectors.free(0x34, 4);
+        assert_eq!(vectors.allocate(8, 8).unwrap(), 0x38);
+        assert_eq!(vectors.allocate(3, 1).unwrap(), 0x33);
+
+        // up to the spurious vector
+        assert_eq!(vectors.allocate(0x40, 0x40).unwrap(), 0x40);
+        assert_eq!(vectors.allocate(0x40, 0x40).unwrap(), 0x80);
+        assert_eq!(vectors.allocate(0x3f, 1).unwrap(), 0xc0);
+        assert!(vectors.is_used(0xff));
+        assert!(matches!(vectors.allocate(1, 0x40), Err(Error::NoVectors)));
+    }
+}
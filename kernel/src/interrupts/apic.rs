@@ -0,0 +1,74 @@
+//! The local APIC of the processor, in xAPIC mode.
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+
+use super::{Error, Result, SPURIOUS_VECTOR};
+use crate::acpi::madt::Madt;
+use crate::paging::{self, CacheMode};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+const REGISTERS_SIZE: usize = 0x1000;
+const ID: usize = 0x20;
+const EOI: usize = 0xb0;
+/// The spurious interrupt vector register.
+const SVR: usize = 0xf0;
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_TIMER: usize = 0x320;
+const LVT_MASKED: u32 = 1 << 16;
+
+/// The address MSI messages are written to. Bits 19-12 select the APIC.
+pub const MSI_ADDRESS: u64 = 0xfee0_0000;
+
+/// Virtual address of the registers, or 0 before `init`.
+static REGISTERS: AtomicU64 = AtomicU64::new(0);
+
+fn register(offset: usize) -> Result<*mut u32> {
+    match REGISTERS.load(Ordering::Relaxed) {
+        0 => Err(Error::NotInitialized),
+        base => Ok((base as usize + offset) as *mut u32),
+    }
+}
+
+fn read(offset: usize) -> Result<u32> {
+    Ok(unsafe { register(offset)?.read_volatile() })
+}
+
+fn write(offset: usize, value: u32) -> Result<()> {
+    unsafe { register(offset)?.write_volatile(value) };
+    Ok(())
+}
+
+/// Maps the registers and enables the APIC, delivering spurious interrupts to
+/// [`SPURIOUS_VECTOR`]. The timer the firmware may have left running is masked.
+pub(super) fn init(madt: &Madt) -> Result<()> {
+    let base = paging::map_mmio(
+        PhysAddr::new(madt.local_apic_address()),
+        REGISTERS_SIZE,
+        CacheMode::Uncached,
+    )?;
+    REGISTERS.store(base.as_u64(), Ordering::Relaxed);
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    unsafe {
+        let value = apic_base.read();
+        if value & APIC_GLOBAL_ENABLE == 0 {
+            apic_base.write(value | APIC_GLOBAL_ENABLE);
+        }
+    }
+    write(LVT_TIMER, LVT_MASKED)?;
+    write(SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32)
+}
+
+/// The ID of the APIC of the running processor.
+pub fn id() -> Result<u8> {
+    Ok((read(ID)? >> 24) as u8)
+}
+
+/// Tells the APIC the current interrupt has been handled.
+pub fn end_of_interrupt() {
+    // the handler of an interrupt can only run after init
+    let _ = write(EOI, 0);
+}
@@ -1,7 +1,11 @@
 use core::fmt::Write;
 
+use crate::params::{Param, ParamValue};
+use crate::register_param;
+
 static LOG_LEVEL_DISPLAY: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
-pub static LOG_LEVEL: spin::Mutex<LogLevel> = spin::Mutex::new(LogLevel::Debug);
+pub static LOG_LEVEL: Param<LogLevel> = Param::new("log", LogLevel::Debug);
+register_param!(LOG_LEVEL);
 
 #[repr(usize)]
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -20,6 +24,20 @@ impl LogLevel {
     }
 }
 
+impl ParamValue for LogLevel {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
@@ -72,11 +90,16 @@ macro_rules! trace {
     ($($arg:tt)*) => ($crate::log!(level: $crate::LogLevel::Trace, $($arg)*));
 }
 
+/// Writes to the screen, or to the serial port when booted headless.
 pub fn _print(args: core::fmt::Arguments) {
-    let console = crate::console::Console::instance();
-    console.write_fmt(args).unwrap();
+    if crate::graphics::Graphics::is_initialized() {
+        let console = crate::console::Console::instance();
+        console.write_fmt(args).unwrap();
+    } else {
+        crate::serial::_print(args);
+    }
 }
 
 pub fn _log_level() -> LogLevel {
-    *LOG_LEVEL.lock()
+    LOG_LEVEL.get()
 }
@@ -1,27 +1,49 @@
 #![no_std]
 #![no_main]
 #![feature(lang_items)]
+#![feature(alloc_error_handler)]
+#![feature(abi_x86_interrupt)]
 #![feature(custom_test_frameworks)]
+#![feature(used_with_arg)]
 #![test_runner(tests::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
 mod ascii_font;
 pub mod bitwise_macro;
 pub mod console;
+pub mod frame_allocator;
+pub mod gdt;
 pub mod graphics;
+pub mod initrd;
+pub mod interrupts;
 pub mod log;
+pub mod memory_map;
+pub mod paging;
+pub mod params;
 pub mod pci;
+pub mod power;
+pub mod serial;
+pub mod stack;
 pub mod usb;
 pub mod volatile;
 
 use log::*;
 
+use alloc::vec::Vec;
+use boot_info::BootInfo;
 use console::Console;
 use core::arch::asm;
 use core::panic::PanicInfo;
-use graphics::{FrameBuffer, Graphics, ModeInfo, PixelColor};
+use graphics::{Graphics, PixelColor};
+use memory_map::MemoryMap;
+use paging::CacheMode;
+use pci::scan_all_bus;
 use pci::PciDevices;
-use pci::{read_bar, read_class_code, read_vendor_id, scan_all_bus, ClassCode, Device};
+use x86_64::PhysAddr;
 
 const BG_COLOR: PixelColor = PixelColor(0, 80, 80);
 const FG_COLOR: PixelColor = PixelColor(255, 128, 0);
@@ -55,10 +77,13 @@ const MOUSE_CURSOR_SHAPE: [&str; MOUSE_CURSOR_HEIGHT] = [
     "         @@@   ",
 ];
 
-fn initialize(fb: *mut FrameBuffer, mi: *mut ModeInfo) {
-    unsafe { Graphics::initialize_instance(fb, mi) }
-    Console::initialize(&FG_COLOR, &BG_COLOR);
-    Graphics::instance().clear(&BG_COLOR);
+fn initialize(boot_info: &BootInfo) {
+    serial::init();
+    if let Some(fb) = boot_info.frame_buffer() {
+        unsafe { Graphics::initialize_instance(fb) }
+        Console::initialize(&FG_COLOR, &BG_COLOR);
+        Graphics::instance().clear(&BG_COLOR);
+    }
 }
 
 fn draw_mouse_cursor() {
@@ -92,129 +117,291 @@ fn welcome_message() {
 
 "
     );
-    info!("Resolution {:?}", Graphics::instance().resolution());
+    if Graphics::is_initialized() {
+        info!("Resolution {:?}", Graphics::instance().resolution());
+    } else {
+        info!("no framebuffer; logging to the serial port");
+    }
 }
 
-fn list_pci_devices() -> PciDevices {
-    let pci_devices = scan_all_bus().unwrap();
-    debug!("scanned pci devices.");
-    for dev in pci_devices.iter() {
-        let vendor_id = read_vendor_id(dev.bus, dev.device, dev.function);
-        let class_code = read_class_code(dev.bus, dev.device, dev.function);
+fn print_params() {
+    info!("command line: {}", params::command_line());
+    params::for_each_error(|arg, e| warn!("ignored {}: {:?}", arg, e));
+    for p in params::iter() {
+        debug!("{}", p);
+    }
+}
+
+fn print_memory_map(memory_map: &MemoryMap) {
+    for d in memory_map.iter() {
         trace!(
-            "{}.{}.{}:, vend {:04x}, class {}, head {:02x}",
-            dev.bus,
-            dev.device,
-            dev.function,
-            vendor_id,
-            class_code,
-            dev.header_type
+            "{:?}, {:08x} - {:08x}, pages {}, attr {:x}",
+            d.ty,
+            d.phys_start,
+            d.phys_end(),
+            d.page_count,
+            d.attribute
         );
     }
-    pci_devices
+    debug!(
+        "memory map: {} entries, usable {} MiB",
+        memory_map.len(),
+        memory_map.usable_bytes() / 1024 / 1024
+    );
 }
 
-fn find_xhc(pci_devices: &PciDevices) -> Option<Device> {
-    let mut xhc_dev = None;
-    let xhcclass = ClassCode {
-        base: 0x0c,
-        sub: 0x03,
-        interface: 0x30,
-    };
-    for dev in pci_devices.iter() {
-        if dev.class_code == xhcclass {
-            xhc_dev = Some(dev);
-            if dev.get_vendor_id() == 0x8086 {
-                break;
+fn list_acpi_tables(tables: &acpi::Tables) {
+    debug!("ACPI: {}", tables.root());
+    for table in tables.iter() {
+        if table.is_valid() {
+            debug!("ACPI: {}", table);
+        } else {
+            warn!("ACPI: {} has a bad checksum", table.signature());
+        }
+    }
+    match tables
+        .find(acpi::madt::SIGNATURE)
+        .and_then(acpi::madt::Madt::parse)
+    {
+        Ok(madt) => {
+            debug!("local APIC at {:x}", madt.local_apic_address());
+            for cpu in madt.processors() {
+                debug!("{:?}", cpu);
+            }
+            for io_apic in madt.io_apics() {
+                debug!("{:?}", io_apic);
             }
         }
+        Err(e) => warn!("no MADT: {:?}", e),
     }
-    xhc_dev
 }
 
-fn switch_echi_to_xhci(_xhc_dev: &Device, pci_devices: &PciDevices) {
-    let ehciclass = ClassCode {
-        base: 0x0c,
-        sub: 0x03,
-        interface: 0x20,
-    };
-    let ehci = pci_devices
-        .iter()
-        .find(|&dev| dev.class_code == ehciclass && dev.get_vendor_id() == 0x8086);
-    if ehci.is_none() {
-        info!("no ehci");
-    } else {
-        panic!("ehci found, but do nothing for the present");
+fn list_acpi_devices() {
+    acpi::aml::with(|aml| {
+        let devices: Vec<_> = aml.namespace().devices().cloned().collect();
+        debug!("AML: {} devices", devices.len());
+        for device in devices {
+            let id = aml.hardware_id(&device).ok().flatten().unwrap_or_default();
+            trace!("AML: {} {} status {:x?}", device, id, aml.status(&device));
+        }
+        let pci0 = acpi::aml::Path::parse("\\_SB.PCI0").unwrap();
+        match aml.pci_routing(&pci0) {
+            Ok(routing) => debug!("AML: {} PCI interrupt routes", routing.len()),
+            Err(e) => warn!("AML: no PCI interrupt routing: {:?}", e),
+        }
+    });
+}
+
+fn list_initrd(archive: &initrd::Archive) {
+    for entry in archive.entries() {
+        match entry {
+            Ok(e) => debug!("initrd: {} ({} bytes)", e.name, e.data.len()),
+            Err(e) => warn!("initrd: broken archive: {:?}", e),
+        }
+    }
+}
+
+fn list_pci_devices() -> PciDevices {
+    let pci_devices = scan_all_bus();
+    debug!("scanned pci devices: {} functions", pci_devices.len());
+    for (depth, dev) in pci_devices.tree() {
+        trace!(
+            "{:indent$}{}, head {:02x}, pin {} line {}",
+            "",
+            dev,
+            dev.header_type,
+            dev.interrupt_pin,
+            dev.interrupt_line,
+            indent = depth * 2
+        );
+        if let Some(bridge) = dev.bridge {
+            trace!(
+                "{:indent$}  buses {:02x}-{:02x}",
+                "",
+                bridge.secondary_bus,
+                bridge.subordinate_bus,
+                indent = depth * 2
+            );
+        }
+        for cap in dev.capabilities() {
+            trace!("{:indent$}  {:x?}", "", cap, indent = depth * 2);
+        }
+        for cap in dev.extended_capabilities() {
+            trace!("{:indent$}  {:x?}", "", cap, indent = depth * 2);
+        }
+    }
+    if pci::LIST.get() {
+        for dev in pci_devices.iter() {
+            println!("{}", dev);
+        }
+    }
+    pci_devices
+}
+
+fn halt() -> ! {
+    unsafe {
+        loop {
+            asm!("hlt");
+        }
     }
 }
 
 #[no_mangle]
-extern "C" fn kernel_main(fb: *mut FrameBuffer, mi: *mut ModeInfo) {
-    initialize(fb, mi);
+extern "C" fn kernel_main(boot_info: *const BootInfo) {
+    let boot_info = unsafe { &*boot_info };
+    if !boot_info.is_compatible() {
+        // the framebuffer can't be trusted, but the serial port can be used
+        serial::init();
+        println!("incompatible boot info; rebuild the loader and the kernel together");
+        halt();
+    }
+    paging::set_physical_memory_offset(boot_info.physical_memory_offset);
+    stack::init(&boot_info.kernel_stack);
+    params::init(unsafe {
+        boot_info
+            .command_line
+            .as_str(boot_info.physical_memory_offset)
+    });
+    initialize(boot_info);
+    gdt::init();
+    interrupts::init_idt();
     welcome_message();
+    print_params();
+    let kernel_stack = &boot_info.kernel_stack;
+    debug!(
+        "kernel stack: {:x} - {:x} ({} KiB)",
+        kernel_stack.bottom,
+        kernel_stack.top,
+        kernel_stack.size() / 1024
+    );
+    let memory_map = unsafe { MemoryMap::from_boot_info(boot_info) };
+    print_memory_map(&memory_map);
+    frame_allocator::init(&memory_map);
+    // the kernel runs on its own stack, page tables, GDT and IDT by now
+    frame_allocator::reclaim_boot_services(&memory_map);
+    let stats = frame_allocator::stats();
+    debug!(
+        "frame allocator: {} frames, {} free",
+        stats.total_frames, stats.free_frames
+    );
+    paging::init(&memory_map).expect("failed to set up page tables");
+    if let Some(fb) = boot_info.frame_buffer() {
+        let fb_base = paging::map_mmio(
+            PhysAddr::new(fb.base),
+            fb.size as usize,
+            CacheMode::WriteCombining,
+        )
+        .expect("failed to map framebuffer");
+        Graphics::instance().remap_frame_buffer(fb_base.as_mut_ptr());
+    }
+    allocator::init().expect("failed to allocate kernel heap");
+    debug!("kernel heap: {} KiB free", allocator::free_bytes() / 1024);
+    match acpi::init(boot_info.rsdp) {
+        Ok(tables) => {
+            list_acpi_tables(&tables);
+            if let Err(e) = interrupts::init(&tables) {
+                warn!("no interrupts: {:?}", e);
+            }
+            match pci::init(&tables) {
+                Ok(()) => debug!("PCI: using ECAM"),
+                Err(e) => debug!("PCI: using I/O ports: {:?}", e),
+            }
+            match acpi::aml::init(&tables) {
+                Ok(()) => list_acpi_devices(),
+                Err(e) => warn!("no AML tables: {:?}", e),
+            }
+            if let Err(e) = power::init(&tables) {
+                warn!("no FADT: {:?}", e);
+            }
+        }
+        Err(e) => warn!("no ACPI tables: {:?}", e),
+    }
+    match unsafe { initrd::init(boot_info) } {
+        Some(archive) => list_initrd(&archive),
+        None => info!("no initrd"),
+    }
 
     #[cfg(test)]
     test_main();
 
     let pci_devices = list_pci_devices();
-    let xhc = find_xhc(&pci_devices);
-    let xhc = match xhc {
-        Some(xhc) => {
-            info!(
-                "xHC has been found: {}.{}.{}",
-                xhc.bus, xhc.device, xhc.function
-            );
-            xhc
-        }
-        None => {
-            panic!("no xHC device");
-        }
-    };
-    switch_echi_to_xhci(&xhc, &pci_devices);
-    let xhc_bar = read_bar(&xhc, 0).unwrap();
-    debug!("xhc_bar = {:08x}", xhc_bar);
-    let xhc_mmio_base = (xhc_bar & !0xf) as usize;
-    debug!("xHC mmio_base = {:08x}", xhc_mmio_base);
-    unsafe {
-        usb::Controller::new(xhc_mmio_base);
-    };
+    // the EHCI driver goes first: the xHC is not started next to an Intel EHCI
+    pci::driver::register(&usb::INTEL_EHCI_DRIVER);
+    pci::driver::register(&usb::XHCI_DRIVER);
+    for dev in pci::driver::bind_all(&pci_devices) {
+        debug!("PCI: no driver for {}", dev);
+    }
+    if !usb::has_controller() {
+        panic!("no xHC device");
+    }
     info!("done");
-    draw_mouse_cursor();
-    unsafe {
-        loop {
-            asm!("hlt");
-        }
+    if Graphics::is_initialized() {
+        draw_mouse_cursor();
     }
+    halt();
 }
 
 #[lang = "eh_personality"]
 fn eh_personality() {}
 
+#[alloc_error_handler]
+fn out_of_memory(layout: ::core::alloc::Layout) -> ! {
+    panic!(
+        "Ran out of free memory while trying to allocate {:#?}",
+        layout
+    );
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     error!("{}", info);
+    match stack::remaining() {
+        Some(left) => error!("{} bytes of kernel stack left", left),
+        None => error!(
+            "stack pointer {:x} is outside the kernel stack {:x?}",
+            stack::current_sp(),
+            stack::bounds()
+        ),
+    }
+    // let the test run end instead of hanging
+    #[cfg(test)]
+    power::shutdown();
+    #[cfg(not(test))]
     loop {}
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use params::Param;
+
+    /// Only tests whose path contains this are run.
+    pub static TEST_FILTER: Param<&'static str> = Param::new("test.filter", "");
+    crate::register_param!(TEST_FILTER);
+
     pub trait TestCaseFn {
+        fn name(&self) -> &'static str;
         fn run_test(&self);
     }
     impl<T: Fn()> TestCaseFn for T {
+        fn name(&self) -> &'static str {
+            core::any::type_name::<T>()
+        }
         fn run_test(&self) {
-            print!("{} ... ", core::any::type_name::<T>());
+            print!("{} ... ", self.name());
             self();
             println!("Ok");
         }
     }
 
     pub fn test_runner(tests: &[&dyn TestCaseFn]) {
-        println!("Running tests : {}", tests.len());
-        for test in tests {
+        let filter = TEST_FILTER.get();
+        let count = tests.iter().filter(|t| t.name().contains(filter)).count();
+        println!("Running tests : {} of {}", count, tests.len());
+        for test in tests.iter().filter(|t| t.name().contains(filter)) {
             test.run_test();
         }
         println!("done.");
+        power::shutdown();
     }
 }
@@ -0,0 +1,94 @@
+use boot_info::{BootInfo, MemoryDescriptor, MemoryType};
+
+/// The final UEFI memory map, as handed over by the loader.
+#[derive(Copy, Clone)]
+pub struct MemoryMap {
+    descriptors: &'static [MemoryDescriptor],
+}
+
+/// Memory which is free to use once the kernel has taken over.
+pub fn is_usable(ty: MemoryType) -> bool {
+    ty == MemoryType::CONVENTIONAL
+}
+
+/// Memory of the firmware, which holds the GDT and IDT it left loaded; usable
+/// after the kernel has loaded its own.
+pub fn is_boot_services(ty: MemoryType) -> bool {
+    matches!(
+        ty,
+        MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA
+    )
+}
+
+/// Memory holding ACPI tables; usable after the tables have been consumed.
+pub fn is_acpi_reclaimable(ty: MemoryType) -> bool {
+    ty == MemoryType::ACPI_RECLAIM
+}
+
+/// Memory allocated by the loader (kernel image, boot info, ...).
+pub fn is_loader(ty: MemoryType) -> bool {
+    matches!(ty, MemoryType::LOADER_CODE | MemoryType::LOADER_DATA)
+}
+
+impl MemoryMap {
+    pub const fn new(descriptors: &'static [MemoryDescriptor]) -> Self {
+        MemoryMap { descriptors }
+    }
+
+    /// # Safety
+    /// `boot_info` must be the structure passed by the loader.
+    pub unsafe fn from_boot_info(boot_info: &BootInfo) -> Self {
+        Self::new(
+            boot_info
+                .memory_map
+                .as_slice(boot_info.physical_memory_offset),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static MemoryDescriptor> {
+        self.descriptors.iter()
+    }
+
+    pub fn usable(&self) -> impl Iterator<Item = &'static MemoryDescriptor> {
+        self.iter().filter(|d| is_usable(d.ty))
+    }
+
+    pub fn boot_services(&self) -> impl Iterator<Item = &'static MemoryDescriptor> {
+        self.iter().filter(|d| is_boot_services(d.ty))
+    }
+
+    pub fn acpi_reclaimable(&self) -> impl Iterator<Item = &'static MemoryDescriptor> {
+        self.iter().filter(|d| is_acpi_reclaimable(d.ty))
+    }
+
+    /// Regions the kernel must never hand out.
+    pub fn reserved(&self) -> impl Iterator<Item = &'static MemoryDescriptor> {
+        self.iter().filter(|d| {
+            !is_usable(d.ty)
+                && !is_boot_services(d.ty)
+                && !is_acpi_reclaimable(d.ty)
+                && !is_loader(d.ty)
+        })
+    }
+
+    /// Including boot services memory.
+    pub fn usable_bytes(&self) -> u64 {
+        self.usable()
+            .chain(self.boot_services())
+            .map(|d| d.phys_end() - d.phys_start)
+            .sum()
+    }
+
+    /// End address of the highest region described by the map.
+    pub fn max_phys_addr(&self) -> u64 {
+        self.iter().map(|d| d.phys_end()).max().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+}
@@ -0,0 +1,267 @@
+use boot_info::MemoryType;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    registers::control::{Cr3, Cr3Flags},
+    registers::model_specific::{Efer, EferFlags, Msr},
+    structures::paging::{
+        mapper::{MapToError, UnmapError},
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
+        PhysFrame, Size2MiB, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use crate::frame_allocator;
+use crate::memory_map::MemoryMap;
+
+/// Virtual addresses handed out by `map_mmio`.
+const MMIO_REGION_START: u64 = 0xffff_c000_0000_0000;
+
+const IA32_PAT: u32 = 0x277;
+/// PA0: WB, PA1: WC, PA2: UC-, PA3: UC, PA4-7: same as PA0-3 of the reset value
+const PAT_VALUE: u64 = 0x0007_0406_0007_0106;
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static PAGE_TABLE: spin::Mutex<Option<OffsetPageTable<'static>>> = spin::Mutex::new(None);
+static NEXT_MMIO_ADDR: spin::Mutex<u64> = spin::Mutex::new(MMIO_REGION_START);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    NotInitialized,
+    FrameAllocationFailed,
+    ParentEntryHugePage,
+    AlreadyMapped,
+    NotMapped,
+    InvalidFrameAddress,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl<S: PageSize> From<MapToError<S>> for Error {
+    fn from(e: MapToError<S>) -> Self {
+        match e {
+            MapToError::FrameAllocationFailed => Error::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => Error::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(_) => Error::AlreadyMapped,
+        }
+    }
+}
+
+impl From<UnmapError> for Error {
+    fn from(e: UnmapError) -> Self {
+        match e {
+            UnmapError::ParentEntryHugePage => Error::ParentEntryHugePage,
+            UnmapError::PageNotMapped => Error::NotMapped,
+            UnmapError::InvalidFrameAddress(_) => Error::InvalidFrameAddress,
+        }
+    }
+}
+
+/// Memory type of a mapping, selected through the PAT entries set up by `init`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    WriteBack,
+    WriteCombining,
+    Uncached,
+}
+
+impl CacheMode {
+    pub fn flags(&self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::WriteCombining => PageTableFlags::WRITE_THROUGH,
+            CacheMode::Uncached => PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_CACHE,
+        }
+    }
+}
+
+struct KernelFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        frame_allocator::allocate(1)
+            .ok()
+            .map(|f| PhysFrame::containing_address(PhysAddr::new(f.addr())))
+    }
+}
+
+/// Must be called with the offset of the loader's direct map before anything
+/// in the boot info is accessed.
+pub fn set_physical_memory_offset(offset: u64) {
+    PHYSICAL_MEMORY_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+pub fn physical_memory_offset() -> u64 {
+    PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed)
+}
+
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(addr.as_u64() + physical_memory_offset())
+}
+
+fn align_up(addr: u64, alignment: u64) -> u64 {
+    (addr + alignment - 1) & !(alignment - 1)
+}
+
+/// How memory of type `ty` is mapped at the physical memory offset. MMIO is
+/// left out: it is only reached through `map_mmio`, so that no page is mapped
+/// with two memory types. Reserved memory may hide devices as well, so it is
+/// mapped uncached.
+fn direct_map_cache_mode(ty: MemoryType) -> Option<CacheMode> {
+    match ty {
+        MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => None,
+        MemoryType::RESERVED | MemoryType::UNUSABLE | MemoryType::PAL_CODE => {
+            Some(CacheMode::Uncached)
+        }
+        _ => Some(CacheMode::WriteBack),
+    }
+}
+
+/// Maps `start..end` at the physical memory offset, with 2 MiB pages where
+/// the range covers them.
+fn map_physical_range(
+    table: &mut OffsetPageTable,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+) -> Result<()> {
+    let offset = table.phys_offset();
+    let mut addr = start;
+    while addr < end {
+        let (result, size) = if addr % Size2MiB::SIZE == 0 && addr + Size2MiB::SIZE <= end {
+            let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(addr));
+            let page = Page::<Size2MiB>::containing_address(offset + addr);
+            let result = unsafe { table.map_to(page, frame, flags, &mut KernelFrameAllocator) };
+            (result.map(|f| f.ignore()).map_err(Error::from), Size2MiB::SIZE)
+        } else {
+            let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(addr));
+            let page = Page::<Size4KiB>::containing_address(offset + addr);
+            let result = unsafe { table.map_to(page, frame, flags, &mut KernelFrameAllocator) };
+            (result.map(|f| f.ignore()).map_err(Error::from), Size4KiB::SIZE)
+        };
+        match result {
+            // overlapping descriptors
+            Ok(()) | Err(Error::AlreadyMapped) | Err(Error::ParentEntryHugePage) => {}
+            Err(e) => return Err(e),
+        }
+        addr += size;
+    }
+    Ok(())
+}
+
+/// Builds the kernel page tables and switches to them.
+///
+/// The physical memory described by the memory map is mapped at the physical
+/// memory offset, except MMIO. The kernel image and stack mappings are taken
+/// over from the loader; nothing is identity mapped any more.
+pub fn init(memory_map: &MemoryMap) -> Result<()> {
+    let offset = VirtAddr::new(physical_memory_offset());
+    let pml4_frame = KernelFrameAllocator
+        .allocate_frame()
+        .ok_or(Error::FrameAllocationFailed)?;
+    let pml4 = unsafe { &mut *phys_to_virt(pml4_frame.start_address()).as_mut_ptr::<PageTable>() };
+    pml4.zero();
+    let mut table = unsafe { OffsetPageTable::new(pml4, offset) };
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for d in memory_map.iter() {
+        if let Some(cache) = direct_map_cache_mode(d.ty) {
+            map_physical_range(&mut table, d.phys_start, d.phys_end(), flags | cache.flags())?;
+        }
+    }
+
+    let pml4 = table.level_4_table();
+    let (current, _) = Cr3::read();
+    let current = unsafe { &*phys_to_virt(current.start_address()).as_ptr::<PageTable>() };
+    // the stack lives in the same PML4 entry as the kernel image
+    let kernel_index = VirtAddr::new(init as *const () as u64).p4_index();
+    pml4[kernel_index] = current[kernel_index].clone();
+
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Msr::new(IA32_PAT).write(PAT_VALUE);
+        Cr3::write(pml4_frame, Cr3Flags::empty());
+    }
+    *PAGE_TABLE.lock() = Some(table);
+    Ok(())
+}
+
+pub fn map(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<()> {
+    let mut table = PAGE_TABLE.lock();
+    let table = table.as_mut().ok_or(Error::NotInitialized)?;
+    unsafe { table.map_to(page, frame, flags, &mut KernelFrameAllocator)? }.flush();
+    Ok(())
+}
+
+pub fn unmap(page: Page) -> Result<PhysFrame> {
+    let mut table = PAGE_TABLE.lock();
+    let table = table.as_mut().ok_or(Error::NotInitialized)?;
+    let (frame, flush) = table.unmap(page)?;
+    flush.flush();
+    Ok(frame)
+}
+
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    PAGE_TABLE.lock().as_ref()?.translate_addr(addr)
+}
+
+/// Maps the physical range `phys..phys + size` into the MMIO region and
+/// returns the virtual address corresponding to `phys`.
+pub fn map_mmio(phys: PhysAddr, size: usize, cache: CacheMode) -> Result<VirtAddr> {
+    let mut table = PAGE_TABLE.lock();
+    let table = table.as_mut().ok_or(Error::NotInitialized)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache.flags();
+    let huge = phys.is_aligned(Size2MiB::SIZE) && size as u64 % Size2MiB::SIZE == 0;
+    let page_size = if huge { Size2MiB::SIZE } else { Size4KiB::SIZE };
+
+    let phys_start = phys.align_down(page_size);
+    let phys_end = align_up(phys.as_u64() + size as u64, page_size);
+    let mut next = NEXT_MMIO_ADDR.lock();
+    let virt_start = align_up(*next, page_size);
+    *next = virt_start + (phys_end - phys_start.as_u64());
+
+    for offset in (0..phys_end - phys_start.as_u64()).step_by(page_size as usize) {
+        let virt = VirtAddr::new(virt_start + offset);
+        let frame = phys_start + offset;
+        if huge {
+            let page = Page::<Size2MiB>::containing_address(virt);
+            let frame = PhysFrame::<Size2MiB>::containing_address(frame);
+            unsafe { table.map_to(page, frame, flags, &mut KernelFrameAllocator)? }.flush();
+        } else {
+            let page = Page::<Size4KiB>::containing_address(virt);
+            let frame = PhysFrame::<Size4KiB>::containing_address(frame);
+            unsafe { table.map_to(page, frame, flags, &mut KernelFrameAllocator)? }.flush();
+        }
+    }
+    Ok(VirtAddr::new(
+        virt_start + (phys.as_u64() - phys_start.as_u64()),
+    ))
+}
+
+/// Removes the pages of a range returned by `map_mmio`. Its virtual addresses
+/// are not handed out again.
+pub fn unmap_mmio(addr: VirtAddr, size: usize) -> Result<()> {
+    let mut table = PAGE_TABLE.lock();
+    let table = table.as_mut().ok_or(Error::NotInitialized)?;
+    let end = addr + size as u64;
+    let mut virt = addr.align_down(Size4KiB::SIZE);
+    while virt < end {
+        let page = Page::<Size4KiB>::containing_address(virt);
+        match table.unmap(page) {
+            Ok((_, flush)) => {
+                flush.flush();
+                virt += Size4KiB::SIZE;
+            }
+            Err(UnmapError::ParentEntryHugePage) => {
+                let page = Page::<Size2MiB>::containing_address(virt);
+                table.unmap(page)?.1.flush();
+                virt = page.start_address() + Size2MiB::SIZE;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! Kernel parameters given on the command line.
+//!
+//! The command line is a whitespace separated list of `name=value`. Each
+//! subsystem declares its parameters as `static` [`Param`]s with a default
+//! value next to the code using them, implements [`ParamValue`] for its own
+//! types and registers the parameters with [`register_param!`], so that
+//! [`init`] can find them by name.
+//!
+//! [`register_param!`] places a reference to the parameter in the
+//! `laranja_params` section, and the linker provides the bounds of that
+//! section. The parameters are set before the heap is available, so they
+//! can't be collected at run time. The references are `#[used(linker)]`, so
+//! `--gc-sections` keeps them even though nothing refers to them by name.
+//!
+//! [`register_param!`]: crate::register_param
+use core::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    MissingValue,
+    UnknownParam,
+    InvalidValue,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Registers a `static` [`Param`] so that it can be set from the command
+/// line.
+#[macro_export]
+macro_rules! register_param {
+    ($param:path) => {
+        const _: () = {
+            #[used(linker)]
+            #[link_section = "laranja_params"]
+            static REGISTERED: &'static dyn $crate::params::ParamDef = &$param;
+        };
+    };
+}
+
+extern "C" {
+    // the bounds of the `laranja_params` section, defined by the linker
+    static __start_laranja_params: u8;
+    static __stop_laranja_params: u8;
+}
+
+/// All parameters registered with [`register_param!`](crate::register_param).
+fn registered() -> &'static [&'static dyn ParamDef] {
+    unsafe {
+        let start = core::ptr::addr_of!(__start_laranja_params) as *const &'static dyn ParamDef;
+        let end = core::ptr::addr_of!(__stop_laranja_params) as *const &'static dyn ParamDef;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+static COMMAND_LINE: spin::Mutex<&'static str> = spin::Mutex::new("");
+
+/// A type which can be parsed from a parameter value.
+pub trait ParamValue: Copy + fmt::Debug + Send {
+    fn parse(value: &'static str) -> Option<Self>;
+}
+
+/// A parameter, independent of its type.
+pub trait ParamDef: Sync {
+    fn name(&self) -> &'static str;
+    fn check(&self, value: &'static str) -> Result<()>;
+    fn set(&self, value: &'static str) -> Result<()>;
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+pub struct Param<T> {
+    name: &'static str,
+    value: spin::Mutex<T>,
+}
+
+impl<T: ParamValue> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Param {
+            name,
+            value: spin::Mutex::new(default),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        *self.value.lock()
+    }
+}
+
+impl<T: ParamValue> ParamDef for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn check(&self, value: &'static str) -> Result<()> {
+        T::parse(value).map(|_| ()).ok_or(Error::InvalidValue)
+    }
+
+    fn set(&self, value: &'static str) -> Result<()> {
+        *self.value.lock() = T::parse(value).ok_or(Error::InvalidValue)?;
+        Ok(())
+    }
+
+    fn fmt_value(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.get())
+    }
+}
+
+impl fmt::Display for dyn ParamDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name())?;
+        self.fmt_value(f)
+    }
+}
+
+impl ParamValue for bool {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "1" | "on" | "yes" | "true" => Some(true),
+            "0" | "off" | "no" | "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl ParamValue for usize {
+    fn parse(value: &'static str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(value: &'static str) -> Option<Self> {
+        Some(value)
+    }
+}
+
+/// `auto` leaves the choice to the subsystem.
+impl<T: ParamValue> ParamValue for Option<T> {
+    fn parse(value: &'static str) -> Option<Self> {
+        if value == "auto" {
+            Some(None)
+        } else {
+            T::parse(value).map(Some)
+        }
+    }
+}
+
+fn split(arg: &'static str) -> Result<(&'static str, &'static str)> {
+    arg.split_once('=').ok_or(Error::MissingValue)
+}
+
+fn find(params: &[&'static dyn ParamDef], name: &str) -> Result<&'static dyn ParamDef> {
+    params
+        .iter()
+        .find(|p| p.name() == name)
+        .copied()
+        .ok_or(Error::UnknownParam)
+}
+
+fn apply(params: &[&'static dyn ParamDef], command_line: &'static str) {
+    for arg in command_line.split_whitespace() {
+        if let Ok((name, value)) = split(arg) {
+            if let Ok(param) = find(params, name) {
+                let _ = param.set(value);
+            }
+        }
+    }
+}
+
+fn check(
+    params: &[&'static dyn ParamDef],
+    command_line: &'static str,
+    mut f: impl FnMut(&'static str, Error),
+) {
+    for arg in command_line.split_whitespace() {
+        let result = split(arg).and_then(|(name, value)| find(params, name)?.check(value));
+        if let Err(e) = result {
+            f(arg, e);
+        }
+    }
+}
+
+/// Sets the parameters given on `command_line`. Nothing is reported here,
+/// because this runs before the console is available; call [`for_each_error`]
+/// later for that. Later arguments override earlier ones.
+pub fn init(command_line: &'static str) {
+    *COMMAND_LINE.lock() = command_line;
+    apply(registered(), command_line);
+}
+
+pub fn command_line() -> &'static str {
+    *COMMAND_LINE.lock()
+}
+
+/// Calls `f` with each argument of the command line which was ignored.
+pub fn for_each_error(f: impl FnMut(&'static str, Error)) {
+    check(registered(), command_line(), f);
+}
+
+pub fn iter() -> impl Iterator<Item = &'static dyn ParamDef> {
+    registered().iter().copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pci;
+    use alloc::vec::Vec;
+
+    static FLAG: Param<bool> = Param::new("test.flag", false);
+    static COUNT: Param<Option<usize>> = Param::new("test.count", None);
+    static ADDRESS: Param<Option<pci::Address>> = Param::new("test.address", None);
+
+    #[test_case]
+    fn test_registered() {
+        let names: Vec<_> = iter().map(|p| p.name()).collect();
+        for name in ["log", "fb.rotate", "fb.scale", "pci.list", "usb.xhc", "test.filter"] {
+            assert!(names.contains(&name), "{} is not registered", name);
+        }
+        assert_eq!(names.len(), 6);
+    }
+
+    #[test_case]
+    fn test_apply() {
+        let params: &[&'static dyn ParamDef] = &[&FLAG, &COUNT, &ADDRESS];
+        apply(
+            params,
+            "test.flag=on test.count=3 test.count=4 test.address=0:1f.7 unknown=1",
+        );
+        assert!(FLAG.get());
+        assert_eq!(COUNT.get(), Some(4));
+        assert_eq!(
+            ADDRESS.get(),
+            Some(pci::Address {
+                bus: 0,
+                device: 0x1f,
+                function: 7
+            })
+        );
+        apply(params, "test.count=auto test.flag=maybe");
+        assert_eq!(COUNT.get(), None);
+        assert!(FLAG.get());
+    }
+
+    #[test_case]
+    fn test_check() {
+        let params: &[&'static dyn ParamDef] = &[&FLAG, &ADDRESS];
+        let mut errors = [("", Error::MissingValue); 4];
+        let mut n = 0;
+        check(
+            params,
+            "test.flag=no test.flag test.address=0:20.0 foo=bar",
+            |arg, e| {
+                errors[n] = (arg, e);
+                n += 1;
+            },
+        );
+        assert_eq!(n, 3);
+        assert_eq!(errors[0], ("test.flag", Error::MissingValue));
+        assert_eq!(errors[1], ("test.address=0:20.0", Error::InvalidValue));
+        assert_eq!(errors[2], ("foo=bar", Error::UnknownParam));
+    }
+}
@@ -1,18 +1,83 @@
+use alloc::vec::Vec;
 use core::fmt::Display;
+use core::mem::size_of;
 
-use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use x86_64::instructions::port::{Port, PortRead, PortWrite, PortWriteOnly};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::acpi::{self, mcfg::Mcfg};
+use crate::interrupts;
+use crate::paging::{self, CacheMode};
+use crate::params::{Param, ParamValue};
+use crate::{bit_getter, bit_setter, register_param, warn};
+
+pub mod bar;
+pub mod capability;
+pub mod driver;
+pub mod ids;
+pub mod msi;
+
+pub use bar::{Bar, BarAddress};
+
+use capability::{Capabilities, Capability, ExtendedCapabilities, Msi, MsiX, PciExpress};
+
+/// Whether to print the functions found like `lspci -nn`, one line each.
+pub static LIST: Param<bool> = Param::new("pci.list", false);
+register_param!(LIST);
 
-const MAX_DEVICES: usize = 32;
 const MAX_FUNCTIONS: usize = 8;
 
 const INVALID_VENDOR_ID: u16 = 0xffff;
 
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Header types without the multi-function bit.
+const HEADER_TYPE_GENERAL: u8 = 0x00;
+const HEADER_TYPE_PCI_BRIDGE: u8 = 0x01;
+const HEADER_TYPE_CARDBUS_BRIDGE: u8 = 0x02;
+
+const COMMAND: u16 = 0x04;
+const STATUS: u16 = 0x06;
+
+/// The enhanced configuration space of a bus: 32 devices with 8 functions of
+/// 4 KiB each.
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
 static PCI_CONFIG: spin::Mutex<PciConfig> = spin::Mutex::new(PciConfig::new());
 
 #[derive(Copy, Clone, Debug)]
 pub enum Error {
-    Full,
     OutOfRange,
+    /// An I/O BAR was used as memory.
+    NotMemory,
+    /// The BAR is not implemented.
+    NoBar,
+    /// A driver does not take the function.
+    Declined,
+    /// The function lacks the capability, or a feature of it.
+    NoCapability,
+    Acpi(acpi::Error),
+    Paging(paging::Error),
+    Interrupt(interrupts::Error),
+}
+
+impl From<acpi::Error> for Error {
+    fn from(e: acpi::Error) -> Self {
+        Error::Acpi(e)
+    }
+}
+
+impl From<paging::Error> for Error {
+    fn from(e: paging::Error) -> Self {
+        Error::Paging(e)
+    }
+}
+
+impl From<interrupts::Error> for Error {
+    fn from(e: interrupts::Error) -> Self {
+        Error::Interrupt(e)
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -24,16 +89,81 @@ pub struct ClassCode {
     pub interface: u8,
 }
 
+impl ClassCode {
+    /// The name of the subclass, or of the class if the subclass has none.
+    pub fn name(&self) -> Option<&'static str> {
+        ids::subclass_name(self.base, self.sub).or_else(|| ids::class_name(self.base))
+    }
+
+    pub fn interface_name(&self) -> Option<&'static str> {
+        ids::interface_name(self.base, self.sub, self.interface)
+    }
+
+    /// Writes the programming interface as `lspci -v` does.
+    fn fmt_interface(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.interface_name() {
+            Some(name) => write!(f, " (prog-if {:02x} [{}])", self.interface, name),
+            None if self.interface != 0 => write!(f, " (prog-if {:02x})", self.interface),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Like `USB controller [0c03] (prog-if 30 [XHCI])`.
 impl Display for ClassCode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "(0x{:02x}, 0x{:02x}, 0x{:02x})",
-            self.base, self.sub, self.interface
-        )
+            "{} [{:02x}{:02x}]",
+            self.name().unwrap_or("Class"),
+            self.base,
+            self.sub
+        )?;
+        self.fmt_interface(f)
     }
 }
 
+/// Location of a function on the PCI bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// `bus:device.function` in hex, as printed by lspci.
+impl ParamValue for Address {
+    fn parse(value: &'static str) -> Option<Self> {
+        let (bus, rest) = value.split_once(':')?;
+        let (device, function) = rest.split_once('.')?;
+        let address = Address {
+            bus: u8::from_str_radix(bus, 16).ok()?,
+            device: u8::from_str_radix(device, 16).ok()?,
+            function: u8::from_str_radix(function, 16).ok()?,
+        };
+        if address.device < 32 && address.function < 8 {
+            Some(address)
+        } else {
+            None
+        }
+    }
+}
+
+/// Bus numbers of a bridge.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bridge {
+    pub primary_bus: u8,
+    pub secondary_bus: u8,
+    /// The highest bus number behind the bridge.
+    pub subordinate_bus: u8,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Device {
     pub bus: u8,
@@ -41,127 +171,422 @@ pub struct Device {
     pub function: u8,
     pub header_type: u8,
     pub class_code: ClassCode,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision: u8,
+    /// Zero unless the function has a general header.
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    /// 1 for INTA# to 4 for INTD#, or 0 if no interrupt pin is used.
+    pub interrupt_pin: u8,
+    /// The IRQ the firmware routed the pin to, or 0xff if unknown.
+    pub interrupt_line: u8,
+    /// The bridge in front of the bus, or `None` on a root bus.
+    pub parent: Option<Address>,
+    pub bridge: Option<Bridge>,
 }
 
-const EMPTY_DEVICE: Device = Device {
-    bus: 0xde,
-    device: 0xad,
-    function: 0xbe,
-    header_type: 0xef,
-    class_code: ClassCode {
-        base: 0,
-        sub: 0,
-        interface: 0,
-    },
-};
+/// The first line `lspci -nn -v` prints for a function, like `00:02.0 USB
+/// controller [0c03]: Red Hat, Inc. QEMU XHCI Host Controller [1b36:000d]
+/// (rev 01) (prog-if 30 [XHCI])`.
+impl Display for Device {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let class = self.class_code;
+        write!(
+            f,
+            "{} {} [{:02x}{:02x}]: ",
+            self.address(),
+            class.name().unwrap_or("Class"),
+            class.base,
+            class.sub
+        )?;
+        if let Some(vendor) = ids::vendor_name(self.vendor_id) {
+            write!(f, "{} ", vendor)?;
+        }
+        write!(
+            f,
+            "{} [{:04x}:{:04x}]",
+            ids::device_name(self.vendor_id, self.device_id).unwrap_or("Device"),
+            self.vendor_id,
+            self.device_id
+        )?;
+        if self.revision != 0 {
+            write!(f, " (rev {:02x})", self.revision)?;
+        }
+        class.fmt_interface(f)
+    }
+}
 
 impl Device {
+    pub fn address(&self) -> Address {
+        Address {
+            bus: self.bus,
+            device: self.device,
+            function: self.function,
+        }
+    }
+
     pub fn get_vendor_id(&self) -> u16 {
-        read_vendor_id(self.bus, self.device, self.function)
+        self.vendor_id
+    }
+
+    pub fn read_config<T: ConfigValue>(&self, reg_addr: u16) -> T {
+        PCI_CONFIG.lock().read_dev(self, reg_addr)
+    }
+
+    pub fn write_config<T: ConfigValue>(&self, reg_addr: u16, value: T) {
+        PCI_CONFIG.lock().write_dev(self, reg_addr, value)
+    }
+
+    /// Replaces the register at `reg_addr` with what `f` returns for its
+    /// current value.
+    pub fn modify_config<T: ConfigValue>(&self, reg_addr: u16, f: impl FnOnce(T) -> T) {
+        let mut config = PCI_CONFIG.lock();
+        let value = config.read_dev(self, reg_addr);
+        config.write_dev(self, reg_addr, f(value));
+    }
+
+    pub fn command(&self) -> Command {
+        Command {
+            data: self.read_config(COMMAND),
+        }
+    }
+
+    pub fn set_command(&self, command: Command) {
+        self.write_config(COMMAND, command.data)
+    }
+
+    pub fn modify_command(&self, f: impl FnOnce(&mut Command)) {
+        self.modify_config(COMMAND, |data| {
+            let mut command = Command { data };
+            f(&mut command);
+            command.data
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            data: self.read_config(STATUS),
+        }
+    }
+
+    /// Clears the bits set in `status`. Only the error bits can be cleared.
+    pub fn clear_status(&self, status: Status) {
+        self.write_config(STATUS, status.data)
+    }
+
+    /// The number of BARs the header has.
+    pub fn bar_count(&self) -> usize {
+        match self.header_type & 0x7f {
+            HEADER_TYPE_GENERAL => 6,
+            HEADER_TYPE_PCI_BRIDGE => 2,
+            _ => 0,
+        }
+    }
+
+    /// Decodes and sizes a BAR. Decoding is disabled while the BAR is sized.
+    /// `None` if the BAR is not implemented or is the upper half of a 64-bit
+    /// BAR.
+    pub fn bar(&self, index: usize) -> Result<Option<Bar>> {
+        if index >= self.bar_count() {
+            return Err(Error::OutOfRange);
+        }
+        let reg_addr = calc_bar_address(index);
+        let mut config = PCI_CONFIG.lock();
+        if is_upper_half(&mut config, self, index) {
+            return Ok(None);
+        }
+        let low: u32 = config.read_dev(self, reg_addr);
+        let is_64bit = Bar::is_64bit(low);
+        if is_64bit && index + 1 >= self.bar_count() {
+            return Err(Error::OutOfRange);
+        }
+        let command: u16 = config.read_dev(self, COMMAND);
+        let mut disabled = Command { data: command };
+        disabled.set_io_space(false);
+        disabled.set_memory_space(false);
+        config.write_dev(self, COMMAND, disabled.data);
+        let low_readback = size_bar(&mut config, self, reg_addr, low);
+        let (value, readback) = if is_64bit {
+            let high: u32 = config.read_dev(self, reg_addr + 4);
+            let high_readback = size_bar(&mut config, self, reg_addr + 4, high);
+            (
+                (high as u64) << 32 | low as u64,
+                (high_readback as u64) << 32 | low_readback as u64,
+            )
+        } else {
+            (low as u64, low_readback as u64)
+        };
+        config.write_dev(self, COMMAND, command);
+        Ok(Bar::decode(value, readback))
+    }
+
+    /// Where BAR `index` decodes. Unlike [`Device::bar`], this only reads the
+    /// BAR, so it is safe while the function is in use. `Ok(None)` for the
+    /// upper half of a 64-bit memory BAR.
+    pub fn bar_address(&self, index: usize) -> Result<Option<BarAddress>> {
+        if index >= self.bar_count() {
+            return Err(Error::OutOfRange);
+        }
+        let reg_addr = calc_bar_address(index);
+        let mut config = PCI_CONFIG.lock();
+        if is_upper_half(&mut config, self, index) {
+            return Ok(None);
+        }
+        let low: u32 = config.read_dev(self, reg_addr);
+        let high: u32 = if Bar::is_64bit(low) {
+            if index + 1 >= self.bar_count() {
+                return Err(Error::OutOfRange);
+            }
+            config.read_dev(self, reg_addr + 4)
+        } else {
+            0
+        };
+        Ok(Some(BarAddress::decode((high as u64) << 32 | low as u64)))
+    }
+
+    /// Maps a memory BAR as uncached MMIO and returns its virtual address.
+    pub fn map_bar(&self, index: usize) -> Result<VirtAddr> {
+        self.bar(index)?.ok_or(Error::NoBar)?.map()
+    }
+
+    pub fn capabilities(&self) -> Capabilities<'_, Device> {
+        Capabilities::new(self, self.header_type, self.status().capabilities_list())
+    }
+
+    /// Empty unless the function is PCI Express and ECAM is used.
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities<'_, Device> {
+        ExtendedCapabilities::new(self)
+    }
+
+    pub fn msi(&self) -> Option<Msi> {
+        self.capabilities().find_map(|c| match c {
+            Capability::Msi(msi) => Some(msi),
+            _ => None,
+        })
+    }
+
+    pub fn msi_x(&self) -> Option<MsiX> {
+        self.capabilities().find_map(|c| match c {
+            Capability::MsiX(msix) => Some(msix),
+            _ => None,
+        })
+    }
+
+    pub fn pci_express(&self) -> Option<PciExpress> {
+        self.capabilities().find_map(|c| match c {
+            Capability::PciExpress(pcie) => Some(pcie),
+            _ => None,
+        })
     }
 }
 
-//TODO; should be fixed length vector or something linke that
-pub struct PciDevices {
-    devices: [Device; MAX_DEVICES],
-    count: usize,
+/// The command register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Command {
+    data: u16,
+}
+
+impl Command {
+    bit_setter!(data: u16; 0, pub set_io_space);
+    bit_getter!(data: u16; 0, pub io_space);
+    bit_setter!(data: u16; 1, pub set_memory_space);
+    bit_getter!(data: u16; 1, pub memory_space);
+    bit_setter!(data: u16; 2, pub set_bus_master);
+    bit_getter!(data: u16; 2, pub bus_master);
+    bit_setter!(data: u16; 6, pub set_parity_error_response);
+    bit_getter!(data: u16; 6, pub parity_error_response);
+    bit_setter!(data: u16; 8, pub set_serr_enable);
+    bit_getter!(data: u16; 8, pub serr_enable);
+    bit_setter!(data: u16; 10, pub set_interrupt_disable);
+    bit_getter!(data: u16; 10, pub interrupt_disable);
+
+    pub fn bits(&self) -> u16 {
+        self.data
+    }
+}
+
+/// The status register. The error bits are cleared by writing 1 to them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    data: u16,
 }
 
-pub struct PciDevicesIter<'a> {
-    devices: &'a [Device],
-    index: usize,
+impl Status {
+    bit_getter!(data: u16; 3, pub interrupt_status);
+    bit_getter!(data: u16; 4, pub capabilities_list);
+    bit_getter!(data: u16; 8, pub master_data_parity_error);
+    bit_getter!(data: u16; 11, pub signaled_target_abort);
+    bit_getter!(data: u16; 12, pub received_target_abort);
+    bit_getter!(data: u16; 13, pub received_master_abort);
+    bit_getter!(data: u16; 14, pub signaled_system_error);
+    bit_getter!(data: u16; 15, pub detected_parity_error);
+
+    pub fn bits(&self) -> u16 {
+        self.data
+    }
+}
+
+/// Every function found by a scan, in the order they were found. Functions
+/// behind a bridge come right after it.
+pub struct PciDevices {
+    devices: Vec<Device>,
+    /// The buses already scanned, so that a misconfigured bridge can't make
+    /// a scan loop.
+    scanned: [bool; 256],
 }
 
 impl PciDevices {
     const fn new() -> Self {
         Self {
-            devices: [EMPTY_DEVICE; MAX_DEVICES],
-            count: 0,
+            devices: Vec::new(),
+            scanned: [false; 256],
         }
     }
 
-    pub fn add_device(&mut self, device: Device) -> Result<()> {
-        if self.count > MAX_DEVICES {
-            Err(Error::Full)
-        } else {
-            self.devices[self.count] = device;
-            self.count += 1;
-            Ok(())
-        }
+    pub fn add_device(&mut self, device: Device) {
+        self.devices.push(device);
     }
 
-    fn scan_function(&mut self, bus: u8, device: u8, function: u8) -> Result<()> {
-        let header_type = read_header_type(bus, device, function);
-        let class_code = read_class_code(bus, device, function);
-        self.add_device(Device {
-            bus,
-            device,
-            function,
-            header_type,
-            class_code,
-        })?;
-        if class_code.base == 0x06 && class_code.sub == 0x04 {
-            // PCI-PCI bridge
-            let bus_numbers = read_bus_numbers(bus, device, function);
-            let secondary_bus = ((bus_numbers >> 8) & 0xff) as u8;
-            return self.scan_bus(secondary_bus);
+    fn scan_function(&mut self, bus: u8, device: u8, function: u8, parent: Option<Address>) {
+        let dev = read_device(bus, device, function, parent);
+        self.add_device(dev);
+        if let Some(bridge) = dev.bridge {
+            // an unconfigured bridge has no secondary bus yet
+            if bridge.secondary_bus > bus {
+                self.scan_bus(bridge.secondary_bus, Some(dev.address()));
+            }
         }
-        Ok(())
     }
 
-    fn scan_device(&mut self, bus: u8, device: u8) -> Result<()> {
-        self.scan_function(bus, device, 0)?;
+    fn scan_device(&mut self, bus: u8, device: u8, parent: Option<Address>) {
+        self.scan_function(bus, device, 0, parent);
         if !is_single_function_device(read_header_type(bus, device, 0)) {
             for function in 1..MAX_FUNCTIONS {
                 if read_vendor_id(bus, device, function as u8) != INVALID_VENDOR_ID {
-                    self.scan_function(bus, device, function as u8)?;
+                    self.scan_function(bus, device, function as u8, parent);
                 }
             }
         }
-        Ok(())
     }
 
-    fn scan_bus(&mut self, bus: u8) -> Result<()> {
+    fn scan_bus(&mut self, bus: u8, parent: Option<Address>) {
+        if self.scanned[bus as usize] {
+            return;
+        }
+        self.scanned[bus as usize] = true;
         for device in 0..32 {
             if read_vendor_id(bus, device, 0) != INVALID_VENDOR_ID {
-                self.scan_device(bus, device)?;
+                self.scan_device(bus, device, parent);
             }
         }
-        Ok(())
     }
 
-    pub fn iter(&self) -> PciDevicesIter {
-        PciDevicesIter {
-            devices: &self.devices[..self.count],
-            index: 0,
-        }
+    pub fn len(&self) -> usize {
+        self.devices.len()
     }
-}
 
-impl<'a> Iterator for PciDevicesIter<'a> {
-    type Item = Device;
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index == self.devices.len() {
-            None
-        } else {
-            let r = self.devices[self.index];
-            self.index += 1;
-            Some(r)
+    pub fn iter(&self) -> impl Iterator<Item = Device> + '_ {
+        self.devices.iter().copied()
+    }
+
+    pub fn get(&self, address: Address) -> Option<&Device> {
+        self.devices.iter().find(|d| d.address() == address)
+    }
+
+    /// The functions on the secondary bus of a bridge.
+    pub fn children(&self, bridge: Address) -> impl Iterator<Item = &Device> + '_ {
+        self.devices
+            .iter()
+            .filter(move |d| d.parent == Some(bridge))
+    }
+
+    /// The number of bridges between a root bus and `device`.
+    pub fn depth(&self, device: &Device) -> usize {
+        let mut depth = 0;
+        let mut parent = device.parent;
+        while let Some(address) = parent {
+            depth += 1;
+            parent = self.get(address).and_then(|d| d.parent);
         }
+        depth
     }
+
+    /// Each function with its depth in the bus hierarchy, in the order of a
+    /// tree view.
+    pub fn tree(&self) -> impl Iterator<Item = (usize, &Device)> + '_ {
+        self.devices.iter().map(move |d| (self.depth(d), d))
+    }
+}
+
+/// A width of configuration space accesses. Accesses must be aligned to
+/// their width.
+pub trait ConfigValue: PortRead + PortWrite + Copy {
+    /// What the registers of a missing function read as.
+    const ALL_ONES: Self;
 }
 
-struct PciConfig {
-    address_port: PortWriteOnly<u32>,
-    data_port: PortReadOnly<u32>,
+impl ConfigValue for u8 {
+    const ALL_ONES: Self = u8::MAX;
+}
+
+impl ConfigValue for u16 {
+    const ALL_ONES: Self = u16::MAX;
+}
+
+impl ConfigValue for u32 {
+    const ALL_ONES: Self = u32::MAX;
+}
+
+fn is_aligned<T: ConfigValue>(reg_addr: u16) -> bool {
+    reg_addr & (size_of::<T>() as u16 - 1) == 0
+}
+
+/// How the configuration space is accessed.
+enum PciConfig {
+    /// The legacy mechanism through two I/O ports, which reaches the first
+    /// 256 bytes of each function.
+    Port { address_port: PortWriteOnly<u32> },
+    /// The PCI Express enhanced configuration access mechanism, which maps
+    /// 4 KiB for each function into memory.
+    Ecam(Ecam),
+}
+
+/// The memory mapped configuration space of the buses of PCI segment 0.
+struct Ecam {
+    /// Virtual address corresponding to bus 0.
+    base: u64,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+impl Ecam {
+    /// The offset of the 32-bit register containing `reg_addr` from the
+    /// configuration space of bus 0.
+    fn offset(bus: u8, device: u8, function: u8, reg_addr: u16) -> u64 {
+        u64::from(bus) << 20
+            | u64::from(device & 0x1f) << 15
+            | u64::from(function & 0x7) << 12
+            | u64::from(reg_addr & 0xffc)
+    }
+
+    fn address(&self, bus: u8, device: u8, function: u8, reg_addr: u16) -> Option<u64> {
+        if !(self.start_bus..=self.end_bus).contains(&bus) || reg_addr >= 0x1000 {
+            return None;
+        }
+        let offset = Ecam::offset(bus, device, function, reg_addr);
+        Some(self.base + offset + u64::from(reg_addr & 3))
+    }
 }
 
 impl PciConfig {
     const fn new() -> Self {
-        Self {
-            address_port: PortWriteOnly::new(0xcf8),
-            data_port: PortReadOnly::new(0xcfc),
+        PciConfig::Port {
+            address_port: PortWriteOnly::new(CONFIG_ADDRESS),
         }
     }
 
@@ -173,29 +598,152 @@ impl PciConfig {
             | u32::from(reg_addr & 0xfc)
     }
 
-    pub fn read(&mut self, bus: u8, device: u8, function: u8, reg_addr: u8) -> u32 {
-        let addr = PciConfig::make_address(bus, device, function, reg_addr);
-        unsafe {
-            self.address_port.write(addr);
-            self.data_port.read()
+    /// Selects the register containing `reg_addr` and returns the data port
+    /// to access it, if the I/O ports can reach it.
+    fn select<T: ConfigValue>(
+        address_port: &mut PortWriteOnly<u32>,
+        bus: u8,
+        device: u8,
+        function: u8,
+        reg_addr: u16,
+    ) -> Option<Port<T>> {
+        if reg_addr >= 0x100 {
+            return None;
         }
+        let addr = PciConfig::make_address(bus, device, function, reg_addr as u8);
+        unsafe { address_port.write(addr) };
+        Some(Port::new(CONFIG_DATA + (reg_addr & 3)))
     }
 
-    pub fn read_dev(&mut self, dev: &Device, reg_addr: u8) -> u32 {
+    /// Reads the register at `reg_addr`. Registers which can't be reached
+    /// and misaligned accesses read as all ones, as those of a missing
+    /// function.
+    pub fn read<T: ConfigValue>(&mut self, bus: u8, device: u8, function: u8, reg_addr: u16) -> T {
+        if !is_aligned::<T>(reg_addr) {
+            warn!(
+                "pci: misaligned {}-byte read at {:#x} of {:02x}:{:02x}.{}; ignored",
+                size_of::<T>(),
+                reg_addr,
+                bus,
+                device,
+                function
+            );
+            return T::ALL_ONES;
+        }
+        match self {
+            PciConfig::Port { address_port } => {
+                match PciConfig::select::<T>(address_port, bus, device, function, reg_addr) {
+                    Some(mut port) => unsafe { port.read() },
+                    None => T::ALL_ONES,
+                }
+            }
+            PciConfig::Ecam(ecam) => match ecam.address(bus, device, function, reg_addr) {
+                Some(addr) => unsafe { (addr as *const T).read_volatile() },
+                None => T::ALL_ONES,
+            },
+        }
+    }
+
+    /// Writes the register at `reg_addr`. Writes to registers which can't be
+    /// reached and misaligned writes are ignored.
+    pub fn write<T: ConfigValue>(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+        reg_addr: u16,
+        value: T,
+    ) {
+        if !is_aligned::<T>(reg_addr) {
+            warn!(
+                "pci: misaligned {}-byte write at {:#x} of {:02x}:{:02x}.{}; ignored",
+                size_of::<T>(),
+                reg_addr,
+                bus,
+                device,
+                function
+            );
+            return;
+        }
+        match self {
+            PciConfig::Port { address_port } => {
+                if let Some(mut port) =
+                    PciConfig::select::<T>(address_port, bus, device, function, reg_addr)
+                {
+                    unsafe { port.write(value) }
+                }
+            }
+            PciConfig::Ecam(ecam) => {
+                if let Some(addr) = ecam.address(bus, device, function, reg_addr) {
+                    unsafe { (addr as *mut T).write_volatile(value) }
+                }
+            }
+        }
+    }
+
+    pub fn read_dev<T: ConfigValue>(&mut self, dev: &Device, reg_addr: u16) -> T {
         self.read(dev.bus, dev.device, dev.function, reg_addr)
     }
+
+    pub fn write_dev<T: ConfigValue>(&mut self, dev: &Device, reg_addr: u16, value: T) {
+        self.write(dev.bus, dev.device, dev.function, reg_addr, value)
+    }
+}
+
+/// Switches to the enhanced configuration access mechanism if the MCFG
+/// describes segment 0. Otherwise the I/O ports keep being used.
+pub fn init(tables: &acpi::Tables) -> Result<()> {
+    let mcfg = Mcfg::parse(tables.find(acpi::mcfg::SIGNATURE)?)?;
+    let allocation = mcfg
+        .allocations()
+        .find(|a| a.segment == 0 && a.start_bus <= a.end_bus)
+        .ok_or(Error::Acpi(acpi::Error::NotFound))?;
+    let start = Ecam::offset(allocation.start_bus, 0, 0, 0);
+    let size = Ecam::offset(allocation.end_bus, 0, 0, 0) - start + ECAM_BUS_SIZE;
+    let base = paging::map_mmio(
+        PhysAddr::new(allocation.base + start),
+        size as usize,
+        CacheMode::Uncached,
+    )?;
+    *PCI_CONFIG.lock() = PciConfig::Ecam(Ecam {
+        base: base.as_u64() - start,
+        start_bus: allocation.start_bus,
+        end_bus: allocation.end_bus,
+    });
+    Ok(())
+}
+
+/// Whether the whole 4 KiB configuration space of each function can be read.
+pub fn has_extended_config() -> bool {
+    matches!(*PCI_CONFIG.lock(), PciConfig::Ecam(_))
+}
+
+pub fn read_config<T: ConfigValue>(address: Address, reg_addr: u16) -> T {
+    PCI_CONFIG
+        .lock()
+        .read(address.bus, address.device, address.function, reg_addr)
+}
+
+pub fn write_config<T: ConfigValue>(address: Address, reg_addr: u16, value: T) {
+    PCI_CONFIG.lock().write(
+        address.bus,
+        address.device,
+        address.function,
+        reg_addr,
+        value,
+    )
 }
 
 pub fn read_vendor_id(bus: u8, device: u8, function: u8) -> u16 {
-    (PCI_CONFIG.lock().read(bus, device, function, 0) & 0xffff) as u16
+    PCI_CONFIG.lock().read(bus, device, function, 0)
 }
 
 pub fn read_header_type(bus: u8, device: u8, function: u8) -> u8 {
-    (PCI_CONFIG.lock().read(bus, device, function, 0x0c) >> 16 & 0xff) as u8
+    PCI_CONFIG.lock().read(bus, device, function, 0x0e)
 }
 
 pub fn read_class_code(bus: u8, device: u8, function: u8) -> ClassCode {
-    let r = PCI_CONFIG.lock().read(bus, device, function, 0x08);
+    let r: u32 = PCI_CONFIG.lock().read(bus, device, function, 0x08);
     ClassCode {
         base: ((r >> 24) & 0xff) as u8,
         sub: ((r >> 16) & 0xff) as u8,
@@ -211,46 +759,304 @@ pub fn is_single_function_device(header_type: u8) -> bool {
     header_type & 0x80 == 0
 }
 
-fn calc_bar_address(bar_index: usize) -> u8 {
-    (0x10 + 4 * bar_index) as u8
+fn calc_bar_address(bar_index: usize) -> u16 {
+    (0x10 + 4 * bar_index) as u16
 }
 
-fn read_conf_reg(dev: &Device, reg_addr: u8) -> u32 {
-    PCI_CONFIG.lock().read_dev(dev, reg_addr)
+/// Whether BAR `index` holds the upper half of the 64-bit memory BAR before
+/// it. The BARs before it are walked from BAR 0, as an upper half may look
+/// like the lower half of a 64-bit BAR itself.
+fn is_upper_half(config: &mut PciConfig, dev: &Device, index: usize) -> bool {
+    let mut bar = 0;
+    while bar < index {
+        let value: u32 = config.read_dev(dev, calc_bar_address(bar));
+        if Bar::is_64bit(value) {
+            if bar + 1 == index {
+                return true;
+            }
+            bar += 2;
+        } else {
+            bar += 1;
+        }
+    }
+    false
 }
 
-pub fn read_bar(device: &Device, bar_index: usize) -> Result<u64> {
-    if bar_index >= 6 {
-        return Err(Error::OutOfRange);
-    }
-    let addr = calc_bar_address(bar_index);
-    let bar = read_conf_reg(device, addr);
+/// Writes all ones to a BAR and returns what it reads back as, restoring its
+/// `value`.
+fn size_bar(config: &mut PciConfig, dev: &Device, reg_addr: u16, value: u32) -> u32 {
+    config.write_dev(dev, reg_addr, u32::MAX);
+    let readback = config.read_dev(dev, reg_addr);
+    config.write_dev(dev, reg_addr, value);
+    readback
+}
 
-    // 32 bit
-    if (bar & 4) == 0 {
-        return Ok(bar.into());
+/// Reads the identification and topology of a function.
+fn read_device(bus: u8, device: u8, function: u8, parent: Option<Address>) -> Device {
+    let mut config = PCI_CONFIG.lock();
+    let id: u32 = config.read(bus, device, function, 0x00);
+    let class: u32 = config.read(bus, device, function, 0x08);
+    let header_type: u8 = config.read(bus, device, function, 0x0e);
+    let interrupt: u16 = config.read(bus, device, function, 0x3c);
+    let (subsystem, bridge) = match header_type & 0x7f {
+        HEADER_TYPE_GENERAL => (config.read(bus, device, function, 0x2c), None),
+        HEADER_TYPE_PCI_BRIDGE | HEADER_TYPE_CARDBUS_BRIDGE => {
+            let buses: u32 = config.read(bus, device, function, 0x18);
+            let bridge = Bridge {
+                primary_bus: buses as u8,
+                secondary_bus: (buses >> 8) as u8,
+                subordinate_bus: (buses >> 16) as u8,
+            };
+            (0u32, Some(bridge))
+        }
+        _ => (0, None),
+    };
+    Device {
+        bus,
+        device,
+        function,
+        header_type,
+        class_code: ClassCode {
+            base: (class >> 24) as u8,
+            sub: (class >> 16) as u8,
+            interface: (class >> 8) as u8,
+        },
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        revision: class as u8,
+        subsystem_vendor_id: subsystem as u16,
+        subsystem_id: (subsystem >> 16) as u16,
+        interrupt_pin: (interrupt >> 8) as u8,
+        interrupt_line: interrupt as u8,
+        parent,
+        bridge,
     }
+}
 
-    // 64bit
-    if bar_index >= 5 {
-        return Err(Error::OutOfRange);
+/// The buses the host bridges decode: `_BBN` of the PCI host bridges in the
+/// AML namespace, or else the first bus of the ECAM range. Without either,
+/// each function of a multi-function host bridge at 00:00 decodes the bus
+/// with its number.
+fn root_buses() -> Vec<u8> {
+    let buses = acpi::aml::with(|aml| aml.pci_root_buses()).unwrap_or_default();
+    if !buses.is_empty() {
+        return buses;
     }
-
-    let bar_upper = read_conf_reg(device, addr + 4);
-    Ok(bar as u64 | (bar_upper as u64) << 32)
+    if let PciConfig::Ecam(ecam) = &*PCI_CONFIG.lock() {
+        return alloc::vec![ecam.start_bus];
+    }
+    if is_single_function_device(read_header_type(0, 0, 0)) {
+        return alloc::vec![0];
+    }
+    (0..MAX_FUNCTIONS as u8)
+        .filter(|&function| read_vendor_id(0, 0, function) != INVALID_VENDOR_ID)
+        .collect()
 }
 
-pub fn scan_all_bus() -> Result<PciDevices> {
+/// Scans the buses behind the host bridges. Functions on the other buses are
+/// logged but not added, as no bridge forwards to them.
+pub fn scan_all_bus() -> PciDevices {
     let mut pci_devices = PciDevices::new();
-    let header_type = read_header_type(0, 0, 0);
-    if is_single_function_device(header_type) {
-        pci_devices.scan_bus(0)?;
+    for bus in root_buses() {
+        pci_devices.scan_bus(bus, None);
+    }
+    let (start_bus, end_bus) = match &*PCI_CONFIG.lock() {
+        PciConfig::Ecam(ecam) => (ecam.start_bus, ecam.end_bus),
+        PciConfig::Port { .. } => (0, 255),
+    };
+    for bus in start_bus..=end_bus {
+        if pci_devices.scanned[bus as usize] {
+            continue;
+        }
+        for device in 0..32 {
+            if read_vendor_id(bus, device, 0) != INVALID_VENDOR_ID {
+                warn!(
+                    "pci: {:02x}:{:02x}.0 is outside the bridge hierarchy; ignored",
+                    bus, device
+                );
+            }
+        }
     }
-    for function in 1..MAX_FUNCTIONS {
-        let function = function as u8;
-        if read_vendor_id(0, 0, function) != INVALID_VENDOR_ID {
-            pci_devices.scan_bus(function)?;
+    pci_devices
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use alloc::format;
+
+    /// A function at `bus:device.0` with no class, vendor Red Hat and no
+    /// parent, for tests to fill in with struct update syntax.
+    pub fn make_device(bus: u8, device: u8) -> Device {
+        Device {
+            bus,
+            device,
+            function: 0,
+            header_type: HEADER_TYPE_GENERAL,
+            class_code: ClassCode {
+                base: 0,
+                sub: 0,
+                interface: 0,
+            },
+            vendor_id: 0x1b36,
+            device_id: 0,
+            revision: 0,
+            subsystem_vendor_id: 0,
+            subsystem_id: 0,
+            interrupt_pin: 0,
+            interrupt_line: 0xff,
+            parent: None,
+            bridge: None,
         }
     }
-    Ok(pci_devices)
+
+    #[test_case]
+    fn test_ecam_offset() {
+        assert_eq!(Ecam::offset(0, 0, 0, 0), 0);
+        assert_eq!(Ecam::offset(1, 2, 3, 0x10), 0x0011_3010);
+        // the register containing an unaligned offset
+        assert_eq!(Ecam::offset(0xff, 0x1f, 7, 0xfff), 0x0fff_fffc);
+    }
+
+    #[test_case]
+    fn test_command() {
+        let mut command = Command { data: 0x0400 };
+        assert!(command.interrupt_disable() && !command.bus_master());
+        command.set_memory_space(true);
+        command.set_bus_master(true);
+        command.set_interrupt_disable(false);
+        assert_eq!(command.bits(), 0x0006);
+    }
+
+    #[test_case]
+    fn test_misaligned_access() {
+        let mut space = [0x1234_5678u32; 0x400];
+        let mut config = PciConfig::Ecam(Ecam {
+            base: space.as_mut_ptr() as u64,
+            start_bus: 0,
+            end_bus: 0,
+        });
+        assert_eq!(config.read::<u16>(0, 0, 0, 0x62), 0x1234);
+        assert_eq!(config.read::<u16>(0, 0, 0, 0x61), 0xffff);
+        assert_eq!(config.read::<u32>(0, 0, 0, 0x62), 0xffff_ffff);
+        config.write::<u32>(0, 0, 0, 0x62, 0);
+        config.write::<u16>(0, 0, 0, 0x63, 0);
+        assert_eq!(space[0x18], 0x1234_5678);
+        assert!(is_aligned::<u8>(0x07) && is_aligned::<u16>(0x06));
+        assert!(!is_aligned::<u32>(0x06));
+    }
+
+    /// A bridge at `bus:device.0` forwarding to `secondary_bus`.
+    fn bridge(bus: u8, device: u8, parent: Option<Address>, secondary_bus: u8) -> Device {
+        Device {
+            header_type: HEADER_TYPE_PCI_BRIDGE,
+            parent,
+            bridge: Some(Bridge {
+                primary_bus: bus,
+                secondary_bus,
+                subordinate_bus: secondary_bus,
+            }),
+            ..make_device(bus, device)
+        }
+    }
+
+    #[test_case]
+    fn test_bar() {
+        // the configuration space of 00:00.0, with a 64-bit BAR 0 and a
+        // 32-bit BAR 2
+        let mut space = [0u32; 0x400];
+        space[4] = 0xfe00_000c;
+        space[5] = 0x1;
+        space[6] = 0xfd00_0000;
+        let ecam = PciConfig::Ecam(Ecam {
+            base: space.as_mut_ptr() as u64,
+            start_bus: 0,
+            end_bus: 0,
+        });
+        let saved = core::mem::replace(&mut *PCI_CONFIG.lock(), ecam);
+        let dev = make_device(0, 0);
+        let bars: Vec<_> = (0..3).map(|i| dev.bar(i).unwrap()).collect();
+        let addresses: Vec<_> = (0..3).map(|i| dev.bar_address(i).unwrap()).collect();
+        *PCI_CONFIG.lock() = saved;
+        assert_eq!(
+            addresses,
+            [
+                Some(BarAddress::Memory(0x1_fe00_0000)),
+                None,
+                Some(BarAddress::Memory(0xfd00_0000))
+            ]
+        );
+
+        assert!(matches!(
+            bars[0],
+            Some(Bar::Memory64 {
+                addr: 0x1_fe00_0000,
+                prefetchable: true,
+                ..
+            })
+        ));
+        assert!(bars[1].is_none());
+        assert!(matches!(
+            bars[2],
+            Some(Bar::Memory32 {
+                addr: 0xfd00_0000,
+                ..
+            })
+        ));
+        // sizing restored the BARs
+        assert_eq!(space[4..7], [0xfe00_000c, 0x1, 0xfd00_0000]);
+    }
+
+    #[test_case]
+    fn test_tree() {
+        let mut devices = PciDevices::new();
+        let root_port = bridge(0, 1, None, 1);
+        let switch = bridge(1, 0, Some(root_port.address()), 2);
+        devices.add_device(make_device(0, 0));
+        devices.add_device(root_port);
+        devices.add_device(switch);
+        devices.add_device(Device {
+            parent: Some(switch.address()),
+            ..make_device(2, 0)
+        });
+        devices.add_device(make_device(0, 2));
+
+        let depths: Vec<_> = devices.tree().map(|(depth, d)| (depth, d.bus)).collect();
+        assert_eq!(depths, [(0, 0), (0, 0), (1, 1), (2, 2), (0, 0)]);
+        assert_eq!(devices.children(root_port.address()).count(), 1);
+        assert_eq!(devices.len(), 5);
+    }
+
+    #[test_case]
+    fn test_display() {
+        let xhc = Device {
+            class_code: ClassCode {
+                base: 0x0c,
+                sub: 0x03,
+                interface: 0x30,
+            },
+            device_id: 0x000d,
+            revision: 1,
+            ..make_device(0, 2)
+        };
+        assert_eq!(
+            format!("{}", xhc),
+            "00:02.0 USB controller [0c03]: Red Hat, Inc. QEMU XHCI Host Controller \
+             [1b36:000d] (rev 01) (prog-if 30 [XHCI])"
+        );
+
+        let unknown = Device {
+            vendor_id: 0xabcd,
+            class_code: ClassCode {
+                base: 0x13,
+                sub: 0x01,
+                interface: 0x02,
+            },
+            ..make_device(0, 3)
+        };
+        assert_eq!(
+            format!("{}", unknown),
+            "00:03.0 Non-Essential Instrumentation [1301]: Device [abcd:0000] (prog-if 02)"
+        );
+    }
 }
@@ -0,0 +1,195 @@
+//! Base address registers.
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{Error, Result};
+use crate::paging::{self, CacheMode};
+
+const IO_SPACE: u64 = 0x1;
+const MEMORY_TYPE_64: u64 = 0x2 << 1;
+const MEMORY_TYPE_MASK: u64 = 0x3 << 1;
+const PREFETCHABLE: u64 = 0x8;
+
+/// A BAR with the size of the range it decodes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bar {
+    Io {
+        port: u16,
+        size: u32,
+    },
+    Memory32 {
+        addr: u32,
+        size: u32,
+        prefetchable: bool,
+    },
+    Memory64 {
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+}
+
+/// Where a BAR decodes, read without sizing the BAR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BarAddress {
+    Io(u16),
+    Memory(u64),
+}
+
+impl BarAddress {
+    /// Decodes the address from a BAR `value`. The upper 32 bits are those
+    /// of the next BAR for a 64-bit memory BAR.
+    pub fn decode(value: u64) -> BarAddress {
+        if value & IO_SPACE != 0 {
+            BarAddress::Io((value as u32 & !0x3) as u16)
+        } else if value & MEMORY_TYPE_MASK == MEMORY_TYPE_64 {
+            BarAddress::Memory(value & !0xf)
+        } else {
+            BarAddress::Memory(value & 0xffff_fff0)
+        }
+    }
+}
+
+/// The size of a range from the bits which read back as 1 after writing all
+/// ones.
+fn size_from_mask(mask: u64) -> u64 {
+    (!mask).wrapping_add(1)
+}
+
+impl Bar {
+    /// Decodes a BAR from its `value` and what it read back as after writing
+    /// all ones. The upper 32 bits of both are those of the next BAR for a
+    /// 64-bit memory BAR. `None` if the BAR is not implemented.
+    pub fn decode(value: u64, readback: u64) -> Option<Bar> {
+        if value & IO_SPACE != 0 {
+            let mut mask = readback as u32 & !0x3;
+            if mask == 0 {
+                return None;
+            }
+            // only 16 bits of the address may be implemented
+            if mask & 0xffff_0000 == 0 {
+                mask |= 0xffff_0000;
+            }
+            return Some(Bar::Io {
+                port: (value as u32 & !0x3) as u16,
+                size: size_from_mask(mask as u64 | !0xffff_ffff) as u32,
+            });
+        }
+        let prefetchable = value & PREFETCHABLE != 0;
+        if value & MEMORY_TYPE_MASK == MEMORY_TYPE_64 {
+            let mask = readback & !0xf;
+            if mask == 0 {
+                return None;
+            }
+            return Some(Bar::Memory64 {
+                addr: value & !0xf,
+                size: size_from_mask(mask),
+                prefetchable,
+            });
+        }
+        let mask = readback as u32 & !0xf;
+        if mask == 0 {
+            return None;
+        }
+        Some(Bar::Memory32 {
+            addr: value as u32 & !0xf,
+            size: size_from_mask(mask as u64 | !0xffff_ffff) as u32,
+            prefetchable,
+        })
+    }
+
+    /// Whether `value` is the lower half of a 64-bit memory BAR.
+    pub(super) fn is_64bit(value: u32) -> bool {
+        let value = value as u64;
+        value & IO_SPACE == 0 && value & MEMORY_TYPE_MASK == MEMORY_TYPE_64
+    }
+
+    pub fn is_memory(&self) -> bool {
+        !matches!(self, Bar::Io { .. })
+    }
+
+    /// The I/O port or the physical address.
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Io { port, .. } => port as u64,
+            Bar::Memory32 { addr, .. } => addr as u64,
+            Bar::Memory64 { addr, .. } => addr,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Io { size, .. } => size as u64,
+            Bar::Memory32 { size, .. } => size as u64,
+            Bar::Memory64 { size, .. } => size,
+        }
+    }
+
+    /// Maps a memory BAR as uncached MMIO and returns its virtual address.
+    pub fn map(&self) -> Result<VirtAddr> {
+        if !self.is_memory() {
+            return Err(Error::NotMemory);
+        }
+        let addr = paging::map_mmio(
+            PhysAddr::new(self.address()),
+            self.size() as usize,
+            CacheMode::Uncached,
+        )?;
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn test_decode_bar() {
+        // a 64 KiB 64-bit memory BAR, like that of the xHC in QEMU
+        let bar = Bar::decode(0x0000_0001_fe00_0004, 0xffff_ffff_ffff_0004);
+        assert_eq!(
+            bar,
+            Some(Bar::Memory64 {
+                addr: 0x1_fe00_0000,
+                size: 0x1_0000,
+                prefetchable: false
+            })
+        );
+        assert!(Bar::is_64bit(0xfe00_0004) && !Bar::is_64bit(0xfe00_0000));
+
+        let bar = Bar::decode(0xfd00_0008, 0xff00_0008).unwrap();
+        assert_eq!(
+            bar,
+            Bar::Memory32 {
+                addr: 0xfd00_0000,
+                size: 0x0100_0000,
+                prefetchable: true
+            }
+        );
+        assert_eq!((bar.address(), bar.size()), (0xfd00_0000, 0x0100_0000));
+
+        assert_eq!(
+            BarAddress::decode(0x0000_0001_fe00_0004),
+            BarAddress::Memory(0x1_fe00_0000)
+        );
+        // the upper 32 bits belong to the next BAR
+        assert_eq!(
+            BarAddress::decode(0x1234_5678_fd00_0008),
+            BarAddress::Memory(0xfd00_0000)
+        );
+        assert_eq!(BarAddress::decode(0xc041), BarAddress::Io(0xc040));
+
+        // I/O BARs may implement only 16 bits
+        let bar = Bar::decode(0xc041, 0xffe1).unwrap();
+        assert_eq!(
+            bar,
+            Bar::Io {
+                port: 0xc040,
+                size: 0x20
+            }
+        );
+        assert!(!bar.is_memory());
+        assert!(matches!(bar.map(), Err(Error::NotMemory)));
+
+        assert_eq!(Bar::decode(0, 0), None);
+    }
+}
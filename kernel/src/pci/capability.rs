@@ -0,0 +1,541 @@
+//! Capability lists in the configuration space.
+//!
+//! Standard capabilities are linked from the capabilities pointer in the first
+//! 256 bytes. PCI Express functions also have a list of extended capabilities
+//! starting at 0x100, which can only be reached through ECAM.
+use super::{Device, HEADER_TYPE_CARDBUS_BRIDGE};
+
+pub const POWER_MANAGEMENT: u8 = 0x01;
+pub const MSI: u8 = 0x05;
+pub const VENDOR_SPECIFIC: u8 = 0x09;
+pub const PCI_EXPRESS: u8 = 0x10;
+pub const MSI_X: u8 = 0x11;
+
+pub const EXT_ADVANCED_ERROR_REPORTING: u16 = 0x0001;
+pub const EXT_DEVICE_SERIAL_NUMBER: u16 = 0x0003;
+pub const EXT_VENDOR_SPECIFIC: u16 = 0x000b;
+pub const EXT_SR_IOV: u16 = 0x0010;
+
+const CAPABILITIES_POINTER: u16 = 0x34;
+const CARDBUS_CAPABILITIES_POINTER: u16 = 0x14;
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+
+/// Bounds the walk in case a list is corrupted into a cycle: 48 standard
+/// capabilities fit in 192 bytes, 960 extended ones in 3840 bytes.
+const MAX_CAPABILITIES: usize = 48;
+const MAX_EXTENDED_CAPABILITIES: usize = 960;
+
+/// The registers capabilities are read from.
+pub trait ConfigSpace {
+    fn read_dword(&self, reg_addr: u16) -> u32;
+}
+
+impl ConfigSpace for Device {
+    fn read_dword(&self, reg_addr: u16) -> u32 {
+        self.read_config(reg_addr)
+    }
+}
+
+/// Power management capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerManagement {
+    pub offset: u16,
+    /// The PMC register.
+    pub capabilities: u16,
+    /// The PMCSR register.
+    pub control: u16,
+}
+
+impl PowerManagement {
+    pub fn version(&self) -> u8 {
+        (self.capabilities & 0x7) as u8
+    }
+
+    pub fn supports_d1(&self) -> bool {
+        self.capabilities & 1 << 9 != 0
+    }
+
+    pub fn supports_d2(&self) -> bool {
+        self.capabilities & 1 << 10 != 0
+    }
+
+    /// The states PME# can be asserted from; bit 0 for D0 to bit 4 for D3cold.
+    pub fn pme_support(&self) -> u8 {
+        (self.capabilities >> 11) as u8
+    }
+
+    /// 0 for D0 to 3 for D3hot.
+    pub fn power_state(&self) -> u8 {
+        (self.control & 0x3) as u8
+    }
+
+    pub fn control_register(&self) -> u16 {
+        self.offset + 4
+    }
+}
+
+/// Message signaled interrupts capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Msi {
+    pub offset: u16,
+    /// The message control register.
+    pub control: u16,
+}
+
+impl Msi {
+    pub fn enabled(&self) -> bool {
+        self.control & 1 != 0
+    }
+
+    /// The number of vectors the function can request.
+    pub fn vectors(&self) -> u8 {
+        1 << ((self.control >> 1) & 0x7)
+    }
+
+    /// The number of vectors enabled.
+    pub fn enabled_vectors(&self) -> u8 {
+        1 << ((self.control >> 4) & 0x7)
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.control & 1 << 7 != 0
+    }
+
+    pub fn per_vector_masking(&self) -> bool {
+        self.control & 1 << 8 != 0
+    }
+
+    pub fn control_register(&self) -> u16 {
+        self.offset + 2
+    }
+
+    pub fn address_register(&self) -> u16 {
+        self.offset + 4
+    }
+
+    /// The register for the upper 32 bits of the address, if there is one.
+    pub fn upper_address_register(&self) -> Option<u16> {
+        self.is_64bit().then_some(self.offset + 8)
+    }
+
+    pub fn data_register(&self) -> u16 {
+        self.offset + if self.is_64bit() { 0x0c } else { 0x08 }
+    }
+
+    pub fn mask_register(&self) -> Option<u16> {
+        self.per_vector_masking()
+            .then_some(self.offset + if self.is_64bit() { 0x10 } else { 0x0c })
+    }
+
+    pub fn pending_register(&self) -> Option<u16> {
+        self.mask_register().map(|mask| mask + 4)
+    }
+}
+
+/// A structure placed in the memory of a BAR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BarRegion {
+    /// The index of the BAR.
+    pub bar: u8,
+    pub offset: u32,
+}
+
+impl BarRegion {
+    fn parse(dword: u32) -> Self {
+        BarRegion {
+            bar: (dword & 0x7) as u8,
+            offset: dword & !0x7,
+        }
+    }
+}
+
+/// MSI-X capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MsiX {
+    pub offset: u16,
+    /// The message control register.
+    pub control: u16,
+    pub table: BarRegion,
+    /// The pending bit array.
+    pub pba: BarRegion,
+}
+
+impl MsiX {
+    /// The number of entries in the table.
+    pub fn table_size(&self) -> u16 {
+        (self.control & 0x7ff) + 1
+    }
+
+    pub fn function_mask(&self) -> bool {
+        self.control & 1 << 14 != 0
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.control & 1 << 15 != 0
+    }
+
+    pub fn control_register(&self) -> u16 {
+        self.offset + 2
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PortType {
+    Endpoint,
+    LegacyEndpoint,
+    IntegratedEndpoint,
+    RootComplexEventCollector,
+    RootPort,
+    UpstreamPort,
+    DownstreamPort,
+    PcieToPciBridge,
+    PciToPcieBridge,
+    Other(u8),
+}
+
+/// PCI Express capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PciExpress {
+    pub offset: u16,
+    /// The PCI Express capabilities register.
+    pub capabilities: u16,
+    pub device_capabilities: u32,
+    pub link_capabilities: u32,
+}
+
+impl PciExpress {
+    pub fn version(&self) -> u8 {
+        (self.capabilities & 0xf) as u8
+    }
+
+    pub fn port_type(&self) -> PortType {
+        match (self.capabilities >> 4) & 0xf {
+            0x0 => PortType::Endpoint,
+            0x1 => PortType::LegacyEndpoint,
+            0x4 => PortType::RootPort,
+            0x5 => PortType::UpstreamPort,
+            0x6 => PortType::DownstreamPort,
+            0x7 => PortType::PcieToPciBridge,
+            0x8 => PortType::PciToPcieBridge,
+            0x9 => PortType::IntegratedEndpoint,
+            0xa => PortType::RootComplexEventCollector,
+            t => PortType::Other(t as u8),
+        }
+    }
+
+    /// The MSI or MSI-X vector used for the interrupts of this capability.
+    pub fn interrupt_message_number(&self) -> u8 {
+        ((self.capabilities >> 9) & 0x1f) as u8
+    }
+
+    /// In bytes.
+    pub fn max_payload_size(&self) -> u16 {
+        128 << (self.device_capabilities & 0x7)
+    }
+
+    pub fn supports_function_level_reset(&self) -> bool {
+        self.device_capabilities & 1 << 28 != 0
+    }
+
+    /// 1 for 2.5 GT/s, 2 for 5 GT/s, and so on.
+    pub fn max_link_speed(&self) -> u8 {
+        (self.link_capabilities & 0xf) as u8
+    }
+
+    /// The number of lanes.
+    pub fn max_link_width(&self) -> u8 {
+        ((self.link_capabilities >> 4) & 0x3f) as u8
+    }
+}
+
+/// A vendor specific capability. Its contents follow the 3-byte header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VendorSpecific {
+    pub offset: u16,
+    /// The length including the header.
+    pub length: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Capability {
+    PowerManagement(PowerManagement),
+    Msi(Msi),
+    MsiX(MsiX),
+    PciExpress(PciExpress),
+    VendorSpecific(VendorSpecific),
+    Other { id: u8, offset: u16 },
+}
+
+impl Capability {
+    fn parse<S: ConfigSpace + ?Sized>(space: &S, offset: u16, header: u32) -> Self {
+        let upper = (header >> 16) as u16;
+        match header as u8 {
+            POWER_MANAGEMENT => Capability::PowerManagement(PowerManagement {
+                offset,
+                capabilities: upper,
+                control: space.read_dword(offset + 4) as u16,
+            }),
+            MSI => Capability::Msi(Msi {
+                offset,
+                control: upper,
+            }),
+            MSI_X => Capability::MsiX(MsiX {
+                offset,
+                control: upper,
+                table: BarRegion::parse(space.read_dword(offset + 4)),
+                pba: BarRegion::parse(space.read_dword(offset + 8)),
+            }),
+            PCI_EXPRESS => Capability::PciExpress(PciExpress {
+                offset,
+                capabilities: upper,
+                device_capabilities: space.read_dword(offset + 4),
+                link_capabilities: space.read_dword(offset + 0x0c),
+            }),
+            VENDOR_SPECIFIC => Capability::VendorSpecific(VendorSpecific {
+                offset,
+                length: upper as u8,
+            }),
+            id => Capability::Other { id, offset },
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Capability::PowerManagement(_) => POWER_MANAGEMENT,
+            Capability::Msi(_) => MSI,
+            Capability::MsiX(_) => MSI_X,
+            Capability::PciExpress(_) => PCI_EXPRESS,
+            Capability::VendorSpecific(_) => VENDOR_SPECIFIC,
+            Capability::Other { id, .. } => *id,
+        }
+    }
+
+    pub fn offset(&self) -> u16 {
+        match self {
+            Capability::PowerManagement(c) => c.offset,
+            Capability::Msi(c) => c.offset,
+            Capability::MsiX(c) => c.offset,
+            Capability::PciExpress(c) => c.offset,
+            Capability::VendorSpecific(c) => c.offset,
+            Capability::Other { offset, .. } => *offset,
+        }
+    }
+}
+
+pub struct Capabilities<'a, S: ?Sized> {
+    space: &'a S,
+    next: u16,
+    remaining: usize,
+}
+
+impl<'a, S: ConfigSpace + ?Sized> Capabilities<'a, S> {
+    /// Walks the list from the capabilities pointer. `has_list` is the
+    /// capabilities list bit of the status register.
+    pub fn new(space: &'a S, header_type: u8, has_list: bool) -> Self {
+        let pointer = if header_type & 0x7f == HEADER_TYPE_CARDBUS_BRIDGE {
+            CARDBUS_CAPABILITIES_POINTER
+        } else {
+            CAPABILITIES_POINTER
+        };
+        let next = if has_list {
+            space.read_dword(pointer) as u16 & 0xfc
+        } else {
+            0
+        };
+        Capabilities {
+            space,
+            next,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+}
+
+impl<'a, S: ConfigSpace + ?Sized> Iterator for Capabilities<'a, S> {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        // the first 64 bytes are the header, not capabilities
+        if self.next < 0x40 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.space.read_dword(offset);
+        self.next = (header >> 8) as u16 & 0xfc;
+        Some(Capability::parse(self.space, offset, header))
+    }
+}
+
+/// A PCI Express extended capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+pub struct ExtendedCapabilities<'a, S: ?Sized> {
+    space: &'a S,
+    next: u16,
+    remaining: usize,
+}
+
+impl<'a, S: ConfigSpace + ?Sized> ExtendedCapabilities<'a, S> {
+    pub fn new(space: &'a S) -> Self {
+        ExtendedCapabilities {
+            space,
+            next: EXTENDED_CAPABILITIES,
+            remaining: MAX_EXTENDED_CAPABILITIES,
+        }
+    }
+}
+
+impl<'a, S: ConfigSpace + ?Sized> Iterator for ExtendedCapabilities<'a, S> {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<ExtendedCapability> {
+        if self.next < EXTENDED_CAPABILITIES || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.space.read_dword(offset);
+        // no extended capabilities, or the space can't be reached
+        if header == 0 || header == u32::MAX {
+            self.next = 0;
+            return None;
+        }
+        self.next = (header >> 20) as u16 & 0xffc;
+        Some(ExtendedCapability {
+            id: header as u16,
+            version: ((header >> 16) & 0xf) as u8,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    struct Space([u32; 1024]);
+
+    impl Space {
+        fn set(&mut self, reg_addr: u16, value: u32) {
+            self.0[reg_addr as usize / 4] = value;
+        }
+    }
+
+    impl ConfigSpace for Space {
+        fn read_dword(&self, reg_addr: u16) -> u32 {
+            self.0[reg_addr as usize / 4]
+        }
+    }
+
+    #[test_case]
+    fn test_capabilities() {
+        let mut space = Space([0; 1024]);
+        space.set(CAPABILITIES_POINTER, 0x40);
+        // PM -> MSI (64-bit, 4 vectors, masking) -> PCIe -> MSI-X -> vendor
+        space.set(0x40, 0x0203_5001);
+        space.set(0x44, 0x0000_0003);
+        space.set(0x50, 0x0184_7005);
+        space.set(0x70, 0x0002_b010);
+        space.set(0x74, 0x1000_0002);
+        space.set(0x7c, 0x0000_0042);
+        space.set(0xb0, 0x0007_c011);
+        space.set(0xb4, 0x0000_2000);
+        space.set(0xb8, 0x0000_3004);
+        space.set(0xc0, 0x000c_0009);
+
+        let caps: Vec<_> = Capabilities::new(&space, 0, true).collect();
+        let ids: Vec<_> = caps.iter().map(|c| c.id()).collect();
+        assert_eq!(
+            ids,
+            [POWER_MANAGEMENT, MSI, PCI_EXPRESS, MSI_X, VENDOR_SPECIFIC]
+        );
+        match caps[0] {
+            Capability::PowerManagement(pm) => {
+                assert_eq!((pm.version(), pm.power_state()), (3, 3));
+                assert!(pm.supports_d1() && !pm.supports_d2());
+            }
+            c => panic!("{:?}", c),
+        }
+        match caps[1] {
+            Capability::Msi(msi) => {
+                assert_eq!((msi.vectors(), msi.enabled_vectors()), (4, 1));
+                assert_eq!(msi.upper_address_register(), Some(0x58));
+                assert_eq!(msi.data_register(), 0x5c);
+                assert_eq!(msi.mask_register(), Some(0x60));
+                assert_eq!(msi.pending_register(), Some(0x64));
+            }
+            c => panic!("{:?}", c),
+        }
+        match caps[2] {
+            Capability::PciExpress(pcie) => {
+                assert_eq!((pcie.version(), pcie.port_type()), (2, PortType::Endpoint));
+                assert_eq!(pcie.max_payload_size(), 512);
+                assert!(pcie.supports_function_level_reset());
+                assert_eq!((pcie.max_link_speed(), pcie.max_link_width()), (2, 4));
+            }
+            c => panic!("{:?}", c),
+        }
+        match caps[3] {
+            Capability::MsiX(msix) => {
+                assert_eq!(msix.table_size(), 8);
+                assert_eq!(
+                    msix.table,
+                    BarRegion {
+                        bar: 0,
+                        offset: 0x2000
+                    }
+                );
+                assert_eq!(
+                    msix.pba,
+                    BarRegion {
+                        bar: 4,
+                        offset: 0x3000
+                    }
+                );
+            }
+            c => panic!("{:?}", c),
+        }
+        assert_eq!(
+            caps[4],
+            Capability::VendorSpecific(VendorSpecific {
+                offset: 0xc0,
+                length: 12
+            })
+        );
+
+        // a list which loops back on itself ends
+        space.set(0xc0, 0x000c_4009);
+        assert_eq!(Capabilities::new(&space, 0, true).count(), MAX_CAPABILITIES);
+        assert_eq!(Capabilities::new(&space, 0, false).count(), 0);
+    }
+
+    #[test_case]
+    fn test_extended_capabilities() {
+        let mut space = Space([0; 1024]);
+        assert_eq!(ExtendedCapabilities::new(&space).count(), 0);
+        space.set(0x100, 0x1401_0001);
+        space.set(0x140, 0x0001_0003);
+        let caps: Vec<_> = ExtendedCapabilities::new(&space).collect();
+        assert_eq!(
+            caps,
+            [
+                ExtendedCapability {
+                    id: EXT_ADVANCED_ERROR_REPORTING,
+                    version: 1,
+                    offset: 0x100
+                },
+                ExtendedCapability {
+                    id: EXT_DEVICE_SERIAL_NUMBER,
+                    version: 1,
+                    offset: 0x140
+                },
+            ]
+        );
+    }
+}
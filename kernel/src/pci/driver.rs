@@ -0,0 +1,280 @@
+//! Binding drivers to PCI functions.
+//!
+//! Drivers declare the functions they handle with a table of [`DeviceId`]s.
+//! Binding tries the drivers in the order they were registered, each with its
+//! IDs in order, so a table can list the functions a driver prefers first.
+use alloc::vec::Vec;
+
+use super::{Address, Device, PciDevices, Result};
+use crate::debug;
+
+static REGISTRY: spin::Mutex<Registry> = spin::Mutex::new(Registry::new());
+
+/// A pattern matched against a function.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceId {
+    /// `None` matches any vendor.
+    pub vendor_id: Option<u16>,
+    /// `None` matches any device.
+    pub device_id: Option<u16>,
+    /// The base class, subclass and programming interface in bits 23-16,
+    /// 15-8 and 7-0.
+    pub class: u32,
+    /// The bits of `class` which have to match.
+    pub class_mask: u32,
+}
+
+impl DeviceId {
+    pub const fn new(vendor_id: u16, device_id: u16) -> Self {
+        DeviceId {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: 0,
+            class_mask: 0,
+        }
+    }
+
+    pub const fn class(base: u8, sub: u8, interface: u8) -> Self {
+        DeviceId {
+            vendor_id: None,
+            device_id: None,
+            class: (base as u32) << 16 | (sub as u32) << 8 | interface as u32,
+            class_mask: 0xff_ffff,
+        }
+    }
+
+    /// Matches any programming interface.
+    pub const fn subclass(base: u8, sub: u8) -> Self {
+        DeviceId {
+            class_mask: 0xff_ff00,
+            ..DeviceId::class(base, sub, 0)
+        }
+    }
+
+    /// Also requires the vendor to match.
+    pub const fn vendor(self, vendor_id: u16) -> Self {
+        DeviceId {
+            vendor_id: Some(vendor_id),
+            ..self
+        }
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        let class = (device.class_code.base as u32) << 16
+            | (device.class_code.sub as u32) << 8
+            | device.class_code.interface as u32;
+        !matches!(self.vendor_id, Some(id) if id != device.vendor_id)
+            && !matches!(self.device_id, Some(id) if id != device.device_id)
+            && (class ^ self.class) & self.class_mask == 0
+    }
+}
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The functions the driver handles, the preferred ones first.
+    fn ids(&self) -> &'static [DeviceId];
+
+    /// Starts driving `device`, which matched `id`. An error leaves the
+    /// function to the other drivers.
+    fn probe(&self, device: &Device, id: &DeviceId) -> Result<()>;
+
+    /// Stops driving `device`.
+    fn remove(&self, device: &Device);
+}
+
+struct Binding {
+    device: Device,
+    driver: &'static dyn Driver,
+}
+
+/// Drivers and the functions bound to them. `probe` and `remove` are called
+/// with the registry locked, so they must not use it.
+pub struct Registry {
+    drivers: Vec<&'static dyn Driver>,
+    bindings: Vec<Binding>,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Registry {
+            drivers: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, driver: &'static dyn Driver) {
+        self.drivers.push(driver);
+    }
+
+    pub fn driver_of(&self, address: Address) -> Option<&'static str> {
+        self.bindings
+            .iter()
+            .find(|b| b.device.address() == address)
+            .map(|b| b.driver.name())
+    }
+
+    /// Binds drivers to the functions in `devices` which have none, and
+    /// returns those which are left unclaimed.
+    pub fn bind(&mut self, devices: &PciDevices) -> Vec<Device> {
+        for &driver in &self.drivers {
+            for id in driver.ids() {
+                for device in devices.iter() {
+                    let bound = self
+                        .bindings
+                        .iter()
+                        .any(|b| b.device.address() == device.address());
+                    if bound || !id.matches(&device) {
+                        continue;
+                    }
+                    match driver.probe(&device, id) {
+                        Ok(()) => self.bindings.push(Binding { device, driver }),
+                        Err(e) => debug!(
+                            "PCI: {} declined {}: {:?}",
+                            driver.name(),
+                            device.address(),
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+        devices
+            .iter()
+            .filter(|d| self.driver_of(d.address()).is_none())
+            .collect()
+    }
+
+    /// Calls `remove` of the driver bound to a function. Returns whether it
+    /// had one.
+    pub fn unbind(&mut self, address: Address) -> bool {
+        match self
+            .bindings
+            .iter()
+            .position(|b| b.device.address() == address)
+        {
+            Some(i) => {
+                let binding = self.bindings.remove(i);
+                binding.driver.remove(&binding.device);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub fn register(driver: &'static dyn Driver) {
+    REGISTRY.lock().register(driver);
+}
+
+/// Binds the registered drivers and returns the functions left unclaimed.
+pub fn bind_all(devices: &PciDevices) -> Vec<Device> {
+    REGISTRY.lock().bind(devices)
+}
+
+pub fn unbind(address: Address) -> bool {
+    REGISTRY.lock().unbind(address)
+}
+
+pub fn driver_of(address: Address) -> Option<&'static str> {
+    REGISTRY.lock().driver_of(address)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pci::{test::make_device, ClassCode, Error};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn device(device: u8, vendor_id: u16, class_code: ClassCode) -> Device {
+        Device {
+            class_code,
+            vendor_id,
+            device_id: 0x1234,
+            ..make_device(0, device)
+        }
+    }
+
+    const XHCI: ClassCode = ClassCode {
+        base: 0x0c,
+        sub: 0x03,
+        interface: 0x30,
+    };
+
+    /// Takes a single xHC, preferring Intel's.
+    struct OneXhc {
+        probed: AtomicUsize,
+        removed: AtomicUsize,
+    }
+
+    static XHC_IDS: [DeviceId; 2] = [
+        DeviceId::class(0x0c, 0x03, 0x30).vendor(0x8086),
+        DeviceId::class(0x0c, 0x03, 0x30),
+    ];
+
+    impl Driver for OneXhc {
+        fn name(&self) -> &'static str {
+            "xhc"
+        }
+
+        fn ids(&self) -> &'static [DeviceId] {
+            &XHC_IDS
+        }
+
+        fn probe(&self, _device: &Device, _id: &DeviceId) -> Result<()> {
+            if self.probed.fetch_add(1, Ordering::Relaxed) > 0 {
+                return Err(Error::Declined);
+            }
+            Ok(())
+        }
+
+        fn remove(&self, _device: &Device) {
+            self.removed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    static DRIVER: OneXhc = OneXhc {
+        probed: AtomicUsize::new(0),
+        removed: AtomicUsize::new(0),
+    };
+
+    #[test_case]
+    fn test_device_id() {
+        let dev = device(0, 0x8086, XHCI);
+        assert!(DeviceId::class(0x0c, 0x03, 0x30).matches(&dev));
+        assert!(DeviceId::subclass(0x0c, 0x03).matches(&dev));
+        assert!(!DeviceId::class(0x0c, 0x03, 0x20).matches(&dev));
+        assert!(DeviceId::new(0x8086, 0x1234).matches(&dev));
+        assert!(!DeviceId::subclass(0x0c, 0x03).vendor(0x1033).matches(&dev));
+    }
+
+    #[test_case]
+    fn test_bind() {
+        let mut devices = PciDevices::new();
+        devices.add_device(device(
+            0,
+            0x8086,
+            ClassCode {
+                base: 6,
+                sub: 0,
+                interface: 0,
+            },
+        ));
+        devices.add_device(device(1, 0x1033, XHCI));
+        devices.add_device(device(2, 0x8086, XHCI));
+        let mut registry = Registry::new();
+        registry.register(&DRIVER);
+
+        let unclaimed = registry.bind(&devices);
+        // the Intel xHC is preferred even though it comes later
+        let intel = devices.iter().nth(2).unwrap().address();
+        assert_eq!(registry.driver_of(intel), Some("xhc"));
+        let unclaimed: Vec<_> = unclaimed.iter().map(|d| d.device).collect();
+        assert_eq!(unclaimed, [0, 1]);
+
+        assert!(registry.unbind(intel));
+        assert!(!registry.unbind(intel));
+        assert_eq!(DRIVER.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(registry.driver_of(intel), None);
+    }
+}
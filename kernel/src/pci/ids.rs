@@ -0,0 +1,83 @@
+//! Names of vendors, devices and classes from the subset of the PCI ID
+//! database in `pci.ids`.
+struct Vendor {
+    id: u16,
+    name: &'static str,
+    devices: &'static [(u16, &'static str)],
+}
+
+struct Class {
+    id: u8,
+    name: &'static str,
+    subclasses: &'static [Subclass],
+}
+
+struct Subclass {
+    id: u8,
+    name: &'static str,
+    interfaces: &'static [(u8, &'static str)],
+}
+
+include!(concat!(env!("OUT_DIR"), "/pci_ids.rs"));
+
+fn find_vendor(vendor_id: u16) -> Option<&'static Vendor> {
+    let i = VENDORS.binary_search_by_key(&vendor_id, |v| v.id).ok()?;
+    Some(&VENDORS[i])
+}
+
+fn find_subclass(base: u8, sub: u8) -> Option<&'static Subclass> {
+    let class = CLASSES.binary_search_by_key(&base, |c| c.id).ok()?;
+    let subclasses = CLASSES[class].subclasses;
+    let i = subclasses.binary_search_by_key(&sub, |s| s.id).ok()?;
+    Some(&subclasses[i])
+}
+
+fn find_name<T: Ord + Copy>(entries: &'static [(T, &'static str)], id: T) -> Option<&'static str> {
+    let i = entries.binary_search_by_key(&id, |e| e.0).ok()?;
+    Some(entries[i].1)
+}
+
+pub fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+    find_vendor(vendor_id).map(|v| v.name)
+}
+
+pub fn device_name(vendor_id: u16, device_id: u16) -> Option<&'static str> {
+    find_name(find_vendor(vendor_id)?.devices, device_id)
+}
+
+pub fn class_name(base: u8) -> Option<&'static str> {
+    let i = CLASSES.binary_search_by_key(&base, |c| c.id).ok()?;
+    Some(CLASSES[i].name)
+}
+
+pub fn subclass_name(base: u8, sub: u8) -> Option<&'static str> {
+    find_subclass(base, sub).map(|s| s.name)
+}
+
+/// The name of a programming interface.
+pub fn interface_name(base: u8, sub: u8, interface: u8) -> Option<&'static str> {
+    find_name(find_subclass(base, sub)?.interfaces, interface)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn test_names() {
+        assert_eq!(vendor_name(0x8086), Some("Intel Corporation"));
+        assert_eq!(
+            device_name(0x1b36, 0x000d),
+            Some("QEMU XHCI Host Controller")
+        );
+        assert_eq!(device_name(0x1b36, 0xffff), None);
+        assert_eq!(vendor_name(0xffff), None);
+
+        assert_eq!(class_name(0x0c), Some("Serial bus controller"));
+        assert_eq!(subclass_name(0x0c, 0x03), Some("USB controller"));
+        assert_eq!(interface_name(0x0c, 0x03, 0x30), Some("XHCI"));
+        assert_eq!(interface_name(0x0c, 0x03, 0x31), None);
+        assert_eq!(subclass_name(0x13, 0x00), None);
+        assert_eq!(class_name(0x13), Some("Non-Essential Instrumentation"));
+    }
+}
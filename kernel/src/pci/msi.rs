@@ -0,0 +1,359 @@
+//! Message signaled interrupts.
+//!
+//! [`Device::enable_msi`] and [`Device::enable_msi_x`] allocate vectors and
+//! program the messages of a function to deliver them to a local APIC. The
+//! vectors belong to the returned [`Interrupts`] until it is disabled.
+use core::mem::size_of;
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::capability::{BarRegion, Msi, MsiX};
+use super::{BarAddress, Device, Error, Result};
+use crate::interrupts::{self, apic::MSI_ADDRESS};
+use crate::paging::{self, CacheMode};
+use crate::volatile::Volatile;
+
+const MSI_ENABLE: u16 = 1;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0x7 << 4;
+const MSI_X_FUNCTION_MASK: u16 = 1 << 14;
+const MSI_X_ENABLE: u16 = 1 << 15;
+const ENTRY_MASKED: u32 = 1;
+
+/// The address and data a function writes to raise an interrupt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl Message {
+    /// A fixed, edge triggered interrupt at `vector` of the local APIC
+    /// `apic_id`.
+    pub fn new(apic_id: u8, vector: u8) -> Self {
+        Message {
+            address: MSI_ADDRESS | (apic_id as u64) << 12,
+            data: vector as u32,
+        }
+    }
+}
+
+/// An entry of an MSI-X table.
+#[repr(C)]
+struct TableEntry {
+    address_low: Volatile<u32>,
+    address_high: Volatile<u32>,
+    data: Volatile<u32>,
+    control: Volatile<u32>,
+}
+
+impl TableEntry {
+    fn write(&mut self, message: Message) {
+        self.address_low.write(message.address as u32);
+        self.address_high.write((message.address >> 32) as u32);
+        self.data.write(message.data);
+    }
+
+    fn set_masked(&mut self, masked: bool) {
+        self.control.modify(|control| {
+            if masked {
+                *control |= ENTRY_MASKED;
+            } else {
+                *control &= !ENTRY_MASKED;
+            }
+        });
+    }
+}
+
+/// The mapped MSI-X table and pending bit array of a function.
+struct Table {
+    entries: VirtAddr,
+    pba: VirtAddr,
+    len: usize,
+}
+
+impl Table {
+    fn entries_size(len: usize) -> usize {
+        len * size_of::<TableEntry>()
+    }
+
+    fn pba_size(len: usize) -> usize {
+        ((len - 1) / 64 + 1) * size_of::<u64>()
+    }
+
+    /// Maps the table and the pending bit array of `msi_x`.
+    fn map(device: &Device, msi_x: &MsiX) -> Result<Self> {
+        let len = msi_x.table_size() as usize;
+        let entries = device.map_bar_region(msi_x.table, Table::entries_size(len))?;
+        let pba = match device.map_bar_region(msi_x.pba, Table::pba_size(len)) {
+            Ok(pba) => pba,
+            Err(e) => {
+                let _ = paging::unmap_mmio(entries, Table::entries_size(len));
+                return Err(e);
+            }
+        };
+        Ok(Table { entries, pba, len })
+    }
+
+    fn unmap(self) -> Result<()> {
+        paging::unmap_mmio(self.entries, Table::entries_size(self.len))?;
+        paging::unmap_mmio(self.pba, Table::pba_size(self.len))?;
+        Ok(())
+    }
+
+    fn entry(&mut self, index: usize) -> &mut TableEntry {
+        unsafe { &mut *self.entries.as_mut_ptr::<TableEntry>().add(index) }
+    }
+
+    fn is_pending(&self, index: usize) -> bool {
+        let bits = unsafe { self.pba.as_ptr::<u64>().add(index / 64).read_volatile() };
+        bits & 1 << (index % 64) != 0
+    }
+}
+
+enum Kind {
+    Msi(Msi),
+    MsiX(MsiX, Table),
+}
+
+/// Vectors allocated to a function and the messages delivering them.
+pub struct Interrupts {
+    device: Device,
+    kind: Kind,
+    first_vector: u8,
+    count: usize,
+}
+
+impl Interrupts {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The vector of a message or an MSI-X table entry.
+    pub fn vector(&self, index: usize) -> Option<u8> {
+        if index < self.count {
+            Some(self.first_vector + index as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Delivers the interrupts to another local APIC.
+    pub fn set_target(&mut self, apic_id: u8) {
+        match &mut self.kind {
+            Kind::Msi(msi) => {
+                write_msi_message(&self.device, msi, Message::new(apic_id, self.first_vector))
+            }
+            Kind::MsiX(_, table) => {
+                for index in 0..self.count {
+                    let entry = table.entry(index);
+                    let masked = entry.control.read() & ENTRY_MASKED != 0;
+                    entry.set_masked(true);
+                    entry.write(Message::new(apic_id, self.first_vector + index as u8));
+                    entry.set_masked(masked);
+                }
+            }
+        }
+    }
+
+    /// Fails with `NoCapability` if the function can't mask MSI vectors.
+    pub fn mask(&mut self, index: usize) -> Result<()> {
+        self.set_masked(index, true)
+    }
+
+    pub fn unmask(&mut self, index: usize) -> Result<()> {
+        self.set_masked(index, false)
+    }
+
+    fn set_masked(&mut self, index: usize, masked: bool) -> Result<()> {
+        if index >= self.count {
+            return Err(Error::OutOfRange);
+        }
+        match &mut self.kind {
+            Kind::Msi(msi) => {
+                let reg = msi.mask_register().ok_or(Error::NoCapability)?;
+                let bits: u32 = self.device.read_config(reg);
+                let bits = if masked {
+                    bits | 1 << index
+                } else {
+                    bits & !(1 << index)
+                };
+                self.device.write_config(reg, bits);
+            }
+            Kind::MsiX(_, table) => table.entry(index).set_masked(masked),
+        }
+        Ok(())
+    }
+
+    /// Whether a masked vector has been raised.
+    pub fn is_pending(&self, index: usize) -> Result<bool> {
+        if index >= self.count {
+            return Err(Error::OutOfRange);
+        }
+        match &self.kind {
+            Kind::Msi(msi) => {
+                let reg = msi.pending_register().ok_or(Error::NoCapability)?;
+                let bits: u32 = self.device.read_config(reg);
+                Ok(bits & 1 << index != 0)
+            }
+            Kind::MsiX(_, table) => Ok(table.is_pending(index)),
+        }
+    }
+
+    /// Turns the messages off, unmaps the MSI-X table and frees the vectors.
+    /// INTx is enabled again.
+    pub fn disable(self) -> Result<()> {
+        let table = match self.kind {
+            Kind::Msi(msi) => {
+                let control: u16 = self.device.read_config(msi.control_register());
+                self.device
+                    .write_config(msi.control_register(), control & !MSI_ENABLE);
+                None
+            }
+            Kind::MsiX(msi_x, table) => {
+                let control: u16 = self.device.read_config(msi_x.control_register());
+                self.device
+                    .write_config(msi_x.control_register(), control & !MSI_X_ENABLE);
+                Some(table)
+            }
+        };
+        self.device
+            .modify_command(|command| command.set_interrupt_disable(false));
+        interrupts::free_vectors(self.first_vector, self.count);
+        if let Some(table) = table {
+            table.unmap()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_msi_message(device: &Device, msi: &Msi, message: Message) {
+    device.write_config(msi.address_register(), message.address as u32);
+    if let Some(reg) = msi.upper_address_register() {
+        device.write_config(reg, (message.address >> 32) as u32);
+    }
+    device.write_config(msi.data_register(), message.data as u16);
+}
+
+impl Device {
+    /// Enables MSI with `count` vectors, rounded up to a power of two, and
+    /// disables INTx.
+    pub fn enable_msi(&self, count: usize, apic_id: u8) -> Result<Interrupts> {
+        let msi = self.msi().ok_or(Error::NoCapability)?;
+        let count = count.next_power_of_two();
+        if count > msi.vectors() as usize {
+            return Err(Error::OutOfRange);
+        }
+        // the vectors of the messages differ in their lower bits
+        let first_vector = interrupts::allocate_vectors(count, count)?;
+        let reg = msi.control_register();
+        let control: u16 = self.read_config(reg);
+        let control = control & !(MSI_ENABLE | MSI_MULTIPLE_MESSAGE_ENABLE);
+        self.write_config(reg, control);
+        write_msi_message(self, &msi, Message::new(apic_id, first_vector));
+        if let Some(mask) = msi.mask_register() {
+            self.write_config(mask, 0u32);
+        }
+        self.write_config(
+            reg,
+            control | (count.trailing_zeros() as u16) << 4 | MSI_ENABLE,
+        );
+        self.modify_command(|command| command.set_interrupt_disable(true));
+        Ok(Interrupts {
+            device: *self,
+            kind: Kind::Msi(msi),
+            first_vector,
+            count,
+        })
+    }
+
+    /// Enables the first `count` entries of the MSI-X table and disables
+    /// INTx. The memory space of the function has to be enabled.
+    pub fn enable_msi_x(&self, count: usize, apic_id: u8) -> Result<Interrupts> {
+        let msi_x = self.msi_x().ok_or(Error::NoCapability)?;
+        let len = msi_x.table_size() as usize;
+        if count == 0 || count > len {
+            return Err(Error::OutOfRange);
+        }
+        let first_vector = interrupts::allocate_vectors(count, 1)?;
+        let mut table = match Table::map(self, &msi_x) {
+            Ok(table) => table,
+            Err(e) => {
+                interrupts::free_vectors(first_vector, count);
+                return Err(e);
+            }
+        };
+        let reg = msi_x.control_register();
+        let control: u16 = self.read_config(reg);
+        // no entry fires while the table is written
+        self.write_config(reg, control | MSI_X_ENABLE | MSI_X_FUNCTION_MASK);
+        for index in 0..len {
+            let entry = table.entry(index);
+            entry.set_masked(true);
+            if index < count {
+                entry.write(Message::new(apic_id, first_vector + index as u8));
+                entry.set_masked(false);
+            }
+        }
+        self.write_config(reg, (control | MSI_X_ENABLE) & !MSI_X_FUNCTION_MASK);
+        self.modify_command(|command| command.set_interrupt_disable(true));
+        Ok(Interrupts {
+            device: *self,
+            kind: Kind::MsiX(msi_x, table),
+            first_vector,
+            count,
+        })
+    }
+
+    /// Enables MSI-X, or MSI if the function has no MSI-X capability.
+    pub fn enable_interrupts(&self, count: usize, apic_id: u8) -> Result<Interrupts> {
+        match self.enable_msi_x(count, apic_id) {
+            Err(Error::NoCapability) => self.enable_msi(count, apic_id),
+            r => r,
+        }
+    }
+
+    // the driver may already use the function, so the BAR must not be sized
+    fn map_bar_region(&self, region: BarRegion, size: usize) -> Result<VirtAddr> {
+        let addr = match self.bar_address(region.bar as usize)? {
+            Some(BarAddress::Memory(addr)) if addr != 0 => addr,
+            Some(BarAddress::Io(_)) => return Err(Error::NotMemory),
+            _ => return Err(Error::NoBar),
+        };
+        let addr = paging::map_mmio(
+            PhysAddr::new(addr + region.offset as u64),
+            size,
+            CacheMode::Uncached,
+        )?;
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn test_message() {
+        let message = Message::new(3, 0x41);
+        assert_eq!(message.address, 0xfee0_3000);
+        assert_eq!(message.data, 0x41);
+    }
+
+    #[test_case]
+    fn test_table() {
+        let mut entries = [0u32; 8];
+        let pba = [0b10u64];
+        let mut table = Table {
+            entries: VirtAddr::from_ptr(entries.as_mut_ptr()),
+            pba: VirtAddr::from_ptr(pba.as_ptr()),
+            len: 2,
+        };
+        let entry = table.entry(1);
+        entry.set_masked(true);
+        entry.write(Message::new(1, 0x30));
+        entry.set_masked(false);
+        table.entry(0).set_masked(true);
+        assert!(table.is_pending(1) && !table.is_pending(0));
+        assert_eq!(entries, [0, 0, 0, 1, 0xfee0_1000, 0, 0x30, 0]);
+    }
+}
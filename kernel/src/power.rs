@@ -0,0 +1,157 @@
+//! Shutting down and resetting the machine through ACPI.
+//!
+//! Shutdown enters the S5 sleep state by writing the `\_S5` values to the PM1
+//! control registers. Reset tries the FADT reset register, the 8042 keyboard
+//! controller if the FADT has one and finally a triple fault.
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::acpi::aml::{self, Interpreter, Path};
+use crate::acpi::{self, fadt::Fadt, GenericAddress};
+use crate::{error, info, warn};
+
+static POWER: spin::Mutex<Option<Power>> = spin::Mutex::new(None);
+
+// PM1 control register bits
+const SCI_EN: u64 = 1;
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u64 = 1 << 13;
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+#[derive(Copy, Clone, Debug)]
+struct Power {
+    fadt: Fadt,
+    /// SLP_TYPa and SLP_TYPb for S5.
+    s5: Option<(u8, u8)>,
+}
+
+/// SLP_TYPa and SLP_TYPb from the first two elements of `\_S5`.
+fn s5_sleep_type(interpreter: &mut Interpreter) -> aml::Result<(u8, u8)> {
+    let s5 = interpreter.evaluate(&Path::parse("\\_S5")?, Vec::new())?;
+    let s5 = s5.as_package()?;
+    let a = s5.first().ok_or(aml::Error::TypeMismatch)?.as_integer()?;
+    let b = match s5.get(1) {
+        Some(b) => b.as_integer()?,
+        None => 0,
+    };
+    Ok((a as u8, b as u8))
+}
+
+/// Reads the FADT and the S5 sleep type. The AML tables must have been
+/// loaded.
+pub fn init(tables: &acpi::Tables) -> acpi::Result<()> {
+    let fadt = Fadt::parse(tables.find(acpi::fadt::SIGNATURE)?)?;
+    let s5 = match aml::with(s5_sleep_type) {
+        Some(Ok(s5)) => Some(s5),
+        Some(Err(e)) => {
+            warn!("power: can't evaluate \\_S5: {:?}", e);
+            None
+        }
+        None => None,
+    };
+    *POWER.lock() = Some(Power { fadt, s5 });
+    Ok(())
+}
+
+/// Switches from legacy mode to ACPI mode, in which the SLP_EN bit works.
+unsafe fn enable_acpi(fadt: &Fadt, pm1a: &GenericAddress) {
+    if pm1a.read().unwrap_or(0) & SCI_EN != 0 || fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    PortWriteOnly::<u8>::new(fadt.smi_cmd as u16).write(fadt.acpi_enable);
+    for _ in 0..1_000_000 {
+        if pm1a.read().unwrap_or(0) & SCI_EN != 0 {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+    warn!("power: failed to enable ACPI mode");
+}
+
+unsafe fn sleep(reg: &GenericAddress, slp_typ: u8) {
+    let value = reg.read().unwrap_or(0) & !(SLP_TYP_MASK | SLP_EN);
+    reg.write(value | (slp_typ as u64) << SLP_TYP_SHIFT | SLP_EN);
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
+}
+
+/// Turns the machine off. Halts if it is not possible.
+pub fn shutdown() -> ! {
+    info!("power: shutting down");
+    x86_64::instructions::interrupts::disable();
+    let power = *POWER.lock();
+    match power {
+        Some(Power {
+            fadt,
+            s5: Some((a, b)),
+        }) => match fadt.pm1a_control {
+            Some(pm1a) => unsafe {
+                enable_acpi(&fadt, &pm1a);
+                sleep(&pm1a, a);
+                if let Some(pm1b) = fadt.pm1b_control {
+                    sleep(&pm1b, b);
+                }
+            },
+            None => warn!("power: no PM1 control register"),
+        },
+        _ => warn!("power: ACPI shutdown is not available"),
+    }
+    error!("power: failed to shut down; halting");
+    halt();
+}
+
+/// Resets the machine.
+pub fn reboot() -> ! {
+    info!("power: rebooting");
+    x86_64::instructions::interrupts::disable();
+    let power = *POWER.lock();
+    if let Some((reg, value)) = power.and_then(|p| p.fadt.reset_register()) {
+        unsafe {
+            reg.write(value as u64);
+        }
+        // the reset may take a moment
+        for _ in 0..1_000_000 {
+            core::hint::spin_loop();
+        }
+        warn!("power: the reset register did not work");
+    }
+    if power.is_some_and(|p| p.fadt.has_8042()) {
+        unsafe {
+            let mut status = PortReadOnly::<u8>::new(KBC_STATUS);
+            for _ in 0..1_000_000 {
+                if status.read() & KBC_STATUS_INPUT_FULL == 0 {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            PortWriteOnly::<u8>::new(KBC_COMMAND).write(KBC_PULSE_RESET);
+            for _ in 0..1_000_000 {
+                core::hint::spin_loop();
+            }
+        }
+        warn!("power: the 8042 did not reset");
+    }
+    warn!("power: triple faulting");
+    unsafe {
+        // any exception now escalates to a triple fault
+        x86_64::instructions::tables::lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        x86_64::instructions::interrupts::int3();
+    }
+    halt();
+}
@@ -0,0 +1,76 @@
+use core::fmt::Write;
+
+use x86_64::instructions::port::Port;
+
+const COM1: u16 = 0x3f8;
+
+static COM1_PORT: spin::Mutex<SerialPort> = spin::Mutex::new(SerialPort::new(COM1));
+
+// register offsets
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
+/// A 16550 compatible UART.
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        SerialPort { base }
+    }
+
+    fn port(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+
+    /// 115200 baud, 8N1, FIFO enabled and no interrupts.
+    pub fn init(&mut self) {
+        unsafe {
+            self.port(INTERRUPT_ENABLE).write(0x00);
+            // DLAB on, divisor 1
+            self.port(LINE_CONTROL).write(0x80);
+            self.port(DATA).write(0x01);
+            self.port(INTERRUPT_ENABLE).write(0x00);
+            self.port(LINE_CONTROL).write(0x03);
+            self.port(FIFO_CONTROL).write(0xc7);
+            // DTR, RTS, OUT2
+            self.port(MODEM_CONTROL).write(0x0b);
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.port(LINE_STATUS).read() & LINE_STATUS_THR_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            self.port(DATA).write(byte);
+        }
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        }
+        Ok(())
+    }
+}
+
+pub fn init() {
+    COM1_PORT.lock().init();
+}
+
+pub fn _print(args: core::fmt::Arguments) {
+    COM1_PORT.lock().write_fmt(args).unwrap();
+}
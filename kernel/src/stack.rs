@@ -0,0 +1,52 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use boot_info::StackInfo;
+
+static BOTTOM: AtomicU64 = AtomicU64::new(0);
+static TOP: AtomicU64 = AtomicU64::new(0);
+
+/// Records the bounds of the stack the loader switched to.
+pub fn init(stack: &StackInfo) {
+    BOTTOM.store(stack.bottom, Ordering::Relaxed);
+    TOP.store(stack.top, Ordering::Relaxed);
+}
+
+pub fn bounds() -> StackInfo {
+    StackInfo {
+        bottom: BOTTOM.load(Ordering::Relaxed),
+        top: TOP.load(Ordering::Relaxed),
+    }
+}
+
+pub fn current_sp() -> u64 {
+    let sp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) sp, options(nomem, nostack)) };
+    sp
+}
+
+/// Bytes left below the current stack pointer, or `None` if it is outside the
+/// kernel stack, which means it has overflowed into the guard page or the
+/// kernel is running on another stack.
+pub fn remaining() -> Option<u64> {
+    let sp = current_sp();
+    let stack = bounds();
+    if stack.contains(sp) {
+        Some(sp - stack.bottom)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn test_running_on_kernel_stack() {
+        let stack = bounds();
+        assert!(stack.size() > 0);
+        let left = remaining().expect("not on the kernel stack");
+        assert!(left > stack.size() / 2);
+    }
+}
@@ -1,4 +1,10 @@
-use crate::debug;
+use crate::params::Param;
+use crate::pci::{
+    self,
+    driver::{DeviceId, Driver},
+    Device,
+};
+use crate::{debug, info, register_param};
 mod context;
 mod device_manager;
 mod registers;
@@ -8,6 +14,13 @@ use registers::*;
 
 use self::simple_alloc::SimpleAlloc;
 
+/// The xHC to use, picked from the PCI devices if `auto`.
+pub static XHC: Param<Option<pci::Address>> = Param::new("usb.xhc", None);
+register_param!(XHC);
+
+/// The controller the xHCI driver has started.
+static CONTROLLER: spin::Mutex<Option<Controller<'static>>> = spin::Mutex::new(None);
+
 const MEM_POOL_SIZE: usize = 4 * 1024 * 1024;
 static ALLOC: spin::Mutex<simple_alloc::SimpleAlloc<MEM_POOL_SIZE>> =
     spin::Mutex::new(SimpleAlloc::new());
@@ -18,6 +31,9 @@ pub struct Controller<'a> {
     doorbell_first: *mut Doorbell,
 }
 
+// the registers are only reached through `CONTROLLER`
+unsafe impl Send for Controller<'_> {}
+
 impl<'a> Controller<'a> {
     /// # Safety
     /// mmio_base must be a valid base address for xHCI device MMIO
@@ -67,4 +83,94 @@ impl<'a> Controller<'a> {
             doorbell_first,
         }
     }
+
+    /// Clears Run/Stop and waits until the controller has halted.
+    pub fn stop(&mut self) {
+        self.op_regs
+            .usbcmd
+            .modify(|usbcmd| usbcmd.set_run_stop(false));
+        while !self.op_regs.usbsts.read().hc_halted() {}
+        debug!("hc halted");
+    }
+}
+
+/// Claims Intel's EHCI controllers, which share their ports with the xHC.
+/// Routing those ports to the xHC is not supported yet.
+pub struct IntelEhciDriver;
+
+pub static INTEL_EHCI_DRIVER: IntelEhciDriver = IntelEhciDriver;
+
+static EHCI_IDS: [DeviceId; 1] = [DeviceId::class(0x0c, 0x03, 0x20).vendor(0x8086)];
+
+impl Driver for IntelEhciDriver {
+    fn name(&self) -> &'static str {
+        "intel-ehci"
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &EHCI_IDS
+    }
+
+    fn probe(&self, device: &Device, _id: &DeviceId) -> pci::Result<()> {
+        panic!(
+            "ehci found at {}, but do nothing for the present",
+            device.address()
+        );
+    }
+
+    fn remove(&self, _device: &Device) {}
+}
+
+/// Starts a single xHC, `XHC` if it is set, preferring Intel's.
+pub struct XhciDriver;
+
+pub static XHCI_DRIVER: XhciDriver = XhciDriver;
+
+static XHCI_IDS: [DeviceId; 2] = [
+    DeviceId::class(0x0c, 0x03, 0x30).vendor(0x8086),
+    DeviceId::class(0x0c, 0x03, 0x30),
+];
+
+impl Driver for XhciDriver {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+
+    fn ids(&self) -> &'static [DeviceId] {
+        &XHCI_IDS
+    }
+
+    fn probe(&self, device: &Device, _id: &DeviceId) -> pci::Result<()> {
+        let mut controller = CONTROLLER.lock();
+        if matches!(XHC.get(), Some(address) if address != device.address()) || controller.is_some()
+        {
+            return Err(pci::Error::Declined);
+        }
+        info!("xHC has been found: {}", device.address());
+        device.modify_command(|command| {
+            command.set_memory_space(true);
+            command.set_bus_master(true);
+        });
+        let bar = device.bar(0)?.ok_or(pci::Error::NoBar)?;
+        debug!("xHC BAR0: {:x?}", bar);
+        let mmio = bar.map()?;
+        *controller = Some(unsafe { Controller::new(mmio.as_u64() as usize) });
+        Ok(())
+    }
+
+    /// Halts the controller before its registers stop being decoded.
+    fn remove(&self, device: &Device) {
+        if let Some(mut controller) = CONTROLLER.lock().take() {
+            controller.stop();
+        }
+        device.modify_command(|command| {
+            command.set_bus_master(false);
+            command.set_memory_space(false);
+        });
+    }
+}
+
+/// Whether the xHCI driver has started a controller.
+pub fn has_controller() -> bool {
+    CONTROLLER.lock().is_some()
 }
@@ -5,6 +5,9 @@ if [ -z "$1" ];  then
 else
    IMG_NAME="$1"
 fi
+
+# the second argument is a kernel to boot without showing the menu
+BOOT_KERNEL="$2"
     
 MOUNT_POINT=./mnt
     MKFS_FAT=mkfs.fat
@@ -29,7 +32,7 @@ else
     sudo mount -o loop $IMG_NAME $MOUNT_POINT
 fi
 
-$SUDO ./write-object-to.sh $MOUNT_POINT
+$SUDO ./write-object-to.sh $MOUNT_POINT $BOOT_KERNEL
 
 if [ `uname` = "Darwin" ]; then
     hdiutil detach $MOUNT_POINT
@@ -5,10 +5,22 @@ else
    IMG_NAME="$1"
 fi
 
+# HEADLESS=1 boots without a display; the kernel logs to the serial port,
+# which is connected to the terminal.
+if [ -n "$HEADLESS" ]; then
+    DISPLAY_OPTS="-nographic -vga none"
+else
+    DISPLAY_OPTS="-monitor stdio"
+fi
+
+# MACHINE=q35 emulates a PCI Express chipset instead of the default i440fx.
+MACHINE=${MACHINE:-pc}
+
 cp OVMFs/OVMF_VARS.fd .
 cp OVMFs/OVMF_CODE.fd .
 qemu-system-x86_64 \
-    -monitor stdio \
+    -machine $MACHINE \
+    $DISPLAY_OPTS \
     -drive if=pflash,format=raw,readonly,file=OVMF_CODE.fd \
     -drive if=pflash,format=raw,file=OVMF_VARS.fd \
     -drive if=ide,index=0,media=disk,format=raw,file=$IMG_NAME \
@@ -1,5 +1,6 @@
 #!/bin/sh
 MOUNT_POINT=$1
+BOOT_KERNEL=$2
 
 if [ -z "$1" ]; then
     echo "no argument"
@@ -8,6 +9,19 @@ fi
 
 mkdir -p "$MOUNT_POINT/EFI/BOOT"
 cp ./bootloader/target/x86_64-unknown-uefi/release/laranja-loader.efi "$MOUNT_POINT/EFI/BOOT/BOOTX64.EFI"
-cp ./kernel/laranja-kernel "$MOUNT_POINT/laranja-kernel"
-
-
+if [ -f ./kernel/laranja-kernel ]; then
+    cp ./kernel/laranja-kernel "$MOUNT_POINT/laranja-kernel"
+fi
+if [ -f ./kernel/laranja-kernel-test ]; then
+    cp ./kernel/laranja-kernel-test "$MOUNT_POINT/laranja-kernel-test"
+fi
+if [ -f ./laranja.cfg ]; then
+    cp ./laranja.cfg "$MOUNT_POINT/laranja.cfg"
+fi
+# later lines override earlier ones
+if [ -n "$BOOT_KERNEL" ]; then
+    printf '\nkernel=%s\ntimeout=0\n' "$BOOT_KERNEL" >> "$MOUNT_POINT/laranja.cfg"
+fi
+if [ -d ./initrd ]; then
+    tar --format=ustar -cf "$MOUNT_POINT/initrd.tar" -C ./initrd .
+fi